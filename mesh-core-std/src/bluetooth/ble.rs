@@ -1,11 +1,13 @@
 use crate::{
     link::link_trait::{Link, LinkConnection},
-    types::ble_types::{BleLink, BleLinkConnection, DEFAULT_MTU, HEADER_SIZE},
+    types::ble_types::{
+        datagram_packet, BleLink, BleLinkConnection, BlePeripheralConnection, DEFAULT_MTU,
+    },
+    MeshError,
 };
 use async_trait::async_trait;
-use btleplug::api::{Central, Peripheral};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use btleplug::api::{Central, Peripheral, WriteType};
+use std::time::Duration;
 
 #[async_trait]
 impl Link for BleLink {
@@ -13,19 +15,31 @@ impl Link for BleLink {
     async fn dial(
         &self,
         address: &str,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>
-    {
-        let (_tx, rx) = tokio::sync::mpsc::channel(32);
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        let adapter = self
+            .adapter
+            .as_ref()
+            .ok_or_else(|| MeshError::Bluetooth("no BLE central adapter".to_string()))?;
+
+        let cached = self.connections.get(address, |connection| async move {
+            connection.peripheral.is_connected().await.unwrap_or(false)
+        });
+        if let Some(connection) = cached.await {
+            return Ok(Box::new(connection));
+        }
 
-        self.adapter.start_scan(Default::default()).await?;
+        adapter.start_scan(Default::default()).await?;
         tokio::time::sleep(Duration::from_secs(3)).await;
 
-        let peripherals = self.adapter.peripherals().await?;
+        let peripherals = adapter.peripherals().await?;
         let peripheral = peripherals
             .into_iter()
-            .find(|p| p.address().to_string() == address.to_string())
+            .find(|p| p.address().to_string() == address)
             // address is a mac address
-            .ok_or("no peripheral with this address")?;
+            .ok_or_else(|| MeshError::DialFailed {
+                address: address.to_string(),
+                reason: "no peripheral with this address".to_string(),
+            })?;
 
         peripheral.connect().await?; // mac ra peripheral ko LinkConnection
         peripheral.discover_services().await?;
@@ -34,21 +48,29 @@ impl Link for BleLink {
             .characteristics()
             .into_iter()
             .find(|c| c.uuid == self.characteristic_uuid)
-            .ok_or("Characteristic not found")?;
-
-        Ok(Box::new(BleLinkConnection {
-            peripheral: Arc::new(peripheral),
-            characteristic,
-            rx: Arc::new(Mutex::new(rx)),
-        }))
+            .ok_or_else(|| MeshError::DialFailed {
+                address: address.to_string(),
+                reason: "characteristic not found".to_string(),
+            })?;
+
+        // subscribes to notifications so replies and datagrams reach us
+        let connection = BleLinkConnection::new(peripheral, characteristic).await?;
+        self.connections.insert(address, connection.clone());
+        Ok(Box::new(connection))
     }
 
-    // acts for peripherals
-    async fn accept(
-        &self,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>
-    {
-        unimplemented!()
+    // acts as peripheral, advertises our service and waits for a central to subscribe
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        let backend = self.peripheral.as_ref().ok_or_else(|| {
+            MeshError::Bluetooth("no BLE peripheral backend configured".to_string())
+        })?;
+        backend
+            .advertise(self.service_uuid, self.characteristic_uuid)
+            .await?;
+
+        let central = backend.accept().await?;
+        log::info!("Central {} connected", central.address());
+        Ok(Box::new(BlePeripheralConnection::new(central).await?))
     }
 
     fn mtu(&self) -> usize {
@@ -58,53 +80,77 @@ impl Link for BleLink {
     fn latency(&self) -> std::time::Duration {
         Duration::from_millis(50)
     }
+
+    // the radio draws a fraction of what wi-fi does
+    fn energy_cost(&self) -> f64 {
+        0.2
+    }
+
+    // disconnects from the peripheral, the next dial scans for it again
+    fn forget(&self, address: &str) {
+        let Some(connection) = self.connections.remove(address) else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = connection.peripheral.disconnect().await;
+            });
+        }
+    }
 }
 
 #[async_trait]
 impl LinkConnection for BleLinkConnection {
-    async fn send(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mtu = DEFAULT_MTU;
-        let total_data_len = data.len();
-        let data_length = total_data_len.to_le_bytes();
-
-        let mut seq = 0;
-        let mut offset = 0;
-
-        while offset < data.len() {
-            let space = mtu - HEADER_SIZE;
-            let end = (offset + space).min(data.len());
-
-            let mut packet = Vec::with_capacity(mtu);
-            packet.extend_from_slice(&data_length);
-            packet.push(seq);
-            packet.extend_from_slice(&data[offset..end]);
-
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError> {
+        for packet in self.fragmenter.fragment(data)? {
             self.peripheral
-                .write(
-                    &self.characteristic,
-                    &packet,
-                    btleplug::api::WriteType::WithResponse,
-                )
+                .write(&self.characteristic, &packet, WriteType::WithResponse)
                 .await?;
-
-            offset = end;
-            seq = seq.wrapping_add(1);
         }
 
         Ok(())
     }
 
-    async fn receive(&self) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
         let mut rx = self.rx.lock().await;
-        match rx.recv().await.ok_or("Channel closed") {
+        match rx
+            .recv()
+            .await
+            .ok_or_else(|| MeshError::closed("channel closed"))
+        {
             Ok(message) => {
                 log::info!("Data read successfully!");
                 Ok(message)
             }
             Err(e) => {
                 log::error!("Error occurred while reading data!");
-                Err(e.into())
+                Err(e)
             }
         }
     }
+
+    // a single write without response, lost packets are not retried
+    async fn send_datagram(&self, data: &[u8]) -> Result<(), MeshError> {
+        let packet = datagram_packet(data, self.fragmenter.mtu())?;
+        self.peripheral
+            .write(&self.characteristic, &packet, WriteType::WithoutResponse)
+            .await?;
+        Ok(())
+    }
+
+    async fn receive_datagram(&self) -> Result<Vec<u8>, MeshError> {
+        let mut rx = self.datagram_rx.lock().await;
+        Ok(rx
+            .recv()
+            .await
+            .ok_or_else(|| MeshError::closed("channel closed"))?)
+    }
+
+    fn mtu(&self) -> usize {
+        self.fragmenter.mtu()
+    }
+
+    fn max_datagram_size(&self) -> Option<usize> {
+        Some(self.fragmenter.mtu() - 1)
+    }
 }
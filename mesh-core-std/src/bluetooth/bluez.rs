@@ -0,0 +1,282 @@
+use crate::{
+    bluetooth::peripheral::{CentralSession, PeripheralBackend},
+    types::ble_types::PacketStream,
+    MeshError,
+};
+use async_trait::async_trait;
+use bluer::{
+    adv::{Advertisement, AdvertisementHandle},
+    gatt::{
+        local::{
+            characteristic_control, Application, ApplicationHandle, Characteristic,
+            CharacteristicControl, CharacteristicControlEvent, CharacteristicNotify,
+            CharacteristicNotifyMethod, CharacteristicWrite, CharacteristicWriteMethod, Service,
+        },
+        CharacteristicReader, CharacteristicWriter,
+    },
+    Adapter, Address, Session,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use uuid::Uuid;
+
+// where the writes of each central go, a central that subscribes again gets a fresh entry
+type Centrals = Arc<Mutex<HashMap<Address, Sender<Vec<u8>>>>>;
+
+// gatt server through bluez on linux, centrals subscribe to the characteristic for
+// notifications and write to it, bluez hands us a socket per central for either direction
+#[derive(Debug)]
+pub struct BluezPeripheralBackend {
+    adapter: Adapter,
+    // what is published while advertising, dropping it takes service and advertisement down
+    published: tokio::sync::Mutex<Option<Published>>,
+    sessions_tx: Sender<BluezCentralSession>,
+    sessions_rx: tokio::sync::Mutex<Receiver<BluezCentralSession>>,
+}
+
+#[derive(Debug)]
+struct Published {
+    uuids: (Uuid, Uuid),
+    _application: ApplicationHandle,
+    _advertisement: AdvertisementHandle,
+    events: JoinHandle<()>,
+}
+
+impl Drop for Published {
+    fn drop(&mut self) {
+        self.events.abort();
+    }
+}
+
+#[derive(Debug)]
+struct BluezCentralSession {
+    address: Address,
+    // notifications to the central, bluez takes care of the att side
+    writer: CharacteristicWriter,
+    writes: Mutex<Option<Receiver<Vec<u8>>>>,
+}
+
+impl BluezPeripheralBackend {
+    // the system's default adapter, powered on if it wasn't
+    pub async fn new() -> Result<Self, MeshError> {
+        let session = Session::new().await?;
+        let adapter = session.default_adapter().await?;
+        adapter.set_powered(true).await?;
+        Ok(Self::with_adapter(adapter))
+    }
+
+    pub fn with_adapter(adapter: Adapter) -> Self {
+        let (sessions_tx, sessions_rx) = mpsc::channel(8);
+        Self {
+            adapter,
+            published: tokio::sync::Mutex::new(None),
+            sessions_tx,
+            sessions_rx: tokio::sync::Mutex::new(sessions_rx),
+        }
+    }
+
+    // one characteristic centrals write to with or without response and subscribe to
+    fn application(
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> (Application, CharacteristicControl) {
+        let (control, control_handle) = characteristic_control();
+        let application = Application {
+            services: vec![Service {
+                uuid: service_uuid,
+                primary: true,
+                characteristics: vec![Characteristic {
+                    uuid: characteristic_uuid,
+                    write: Some(CharacteristicWrite {
+                        write: true,
+                        write_without_response: true,
+                        method: CharacteristicWriteMethod::Io,
+                        ..Default::default()
+                    }),
+                    notify: Some(CharacteristicNotify {
+                        notify: true,
+                        method: CharacteristicNotifyMethod::Io,
+                        ..Default::default()
+                    }),
+                    control_handle,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        (application, control)
+    }
+
+    // pairs each central's notification socket with the writes it sends us, a central is
+    // handed to accept once it subscribed, writes that come first wait for that
+    async fn serve(mut control: CharacteristicControl, sessions: Sender<BluezCentralSession>) {
+        let centrals: Centrals = Default::default();
+        let mut unsubscribed: HashMap<Address, Receiver<Vec<u8>>> = HashMap::new();
+
+        while let Some(event) = control.next().await {
+            match event {
+                CharacteristicControlEvent::Write(request) => {
+                    let address = request.device_address();
+                    let reader = match request.accept() {
+                        Ok(reader) => reader,
+                        Err(e) => {
+                            log::warn!("Failed to accept writes from {}: {}", address, e);
+                            continue;
+                        }
+                    };
+                    if let Entry::Vacant(entry) = centrals.lock().unwrap().entry(address) {
+                        let (tx, rx) = mpsc::channel(32);
+                        entry.insert(tx);
+                        unsubscribed.insert(address, rx);
+                    }
+                    tokio::spawn(Self::read_writes(reader, centrals.clone()));
+                }
+                CharacteristicControlEvent::Notify(writer) => {
+                    let address = writer.device_address();
+                    log::debug!(
+                        "Central {} subscribed with a {} byte mtu",
+                        address,
+                        writer.mtu()
+                    );
+                    let writes = match unsubscribed.remove(&address) {
+                        Some(writes) => writes,
+                        None => {
+                            let (tx, rx) = mpsc::channel(32);
+                            centrals.lock().unwrap().insert(address, tx);
+                            rx
+                        }
+                    };
+                    let session = BluezCentralSession {
+                        address,
+                        writer,
+                        writes: Mutex::new(Some(writes)),
+                    };
+                    if sessions.send(session).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // forwards what one central writes to its current session until bluez closes the socket
+    async fn read_writes(reader: CharacteristicReader, centrals: Centrals) {
+        let address = reader.device_address();
+        loop {
+            let value = match reader.recv().await {
+                Ok(value) if !value.is_empty() => value,
+                Ok(_) => return,
+                Err(e) => {
+                    log::debug!("Writes from {} ended: {}", address, e);
+                    return;
+                }
+            };
+            let session = centrals.lock().unwrap().get(&address).cloned();
+            match session {
+                Some(session) => {
+                    let _ = session.send(value).await;
+                }
+                None => log::debug!("Dropping write from unknown central {}", address),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PeripheralBackend for BluezPeripheralBackend {
+    async fn advertise(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Result<(), MeshError> {
+        let mut published = self.published.lock().await;
+        if let Some(current) = published.as_ref() {
+            if current.uuids == (service_uuid, characteristic_uuid) {
+                return Ok(());
+            }
+        }
+        // the old service goes first, bluez refuses two with the same uuid
+        *published = None;
+
+        let (application, control) = Self::application(service_uuid, characteristic_uuid);
+        let application = self.adapter.serve_gatt_application(application).await?;
+        let advertisement = Advertisement {
+            service_uuids: [service_uuid].into(),
+            discoverable: Some(true),
+            ..Default::default()
+        };
+        let advertisement = self.adapter.advertise(advertisement).await?;
+        log::info!(
+            "Advertising BLE service {} on {}",
+            service_uuid,
+            self.adapter.name()
+        );
+
+        *published = Some(Published {
+            uuids: (service_uuid, characteristic_uuid),
+            _application: application,
+            _advertisement: advertisement,
+            events: tokio::spawn(Self::serve(control, self.sessions_tx.clone())),
+        });
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<(), MeshError> {
+        *self.published.lock().await = None;
+        Ok(())
+    }
+
+    async fn accept(&self) -> Result<Box<dyn CentralSession>, MeshError> {
+        let session = self
+            .sessions_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| MeshError::closed("bluez backend closed"))?;
+        Ok(Box::new(session))
+    }
+}
+
+#[async_trait]
+impl CentralSession for BluezCentralSession {
+    fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    // what bluez reported for the central, less the few bytes it needs to keep spare
+    fn mtu(&self) -> usize {
+        self.writer.mtu()
+    }
+
+    async fn notify(&self, value: &[u8]) -> Result<(), MeshError> {
+        if value.len() > self.writer.mtu() {
+            return Err(MeshError::MtuExceeded {
+                size: value.len(),
+                limit: self.writer.mtu(),
+            });
+        }
+        self.writer
+            .send(value)
+            .await
+            .map_err(|e| MeshError::closed(format!("central {}: {}", self.address, e)))
+    }
+
+    async fn writes(&self) -> Result<PacketStream, MeshError> {
+        let writes = self
+            .writes
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| MeshError::other("writes already taken"))?;
+        Ok(Box::pin(ReceiverStream::new(writes)))
+    }
+}
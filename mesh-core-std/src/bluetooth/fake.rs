@@ -0,0 +1,188 @@
+use crate::{
+    bluetooth::peripheral::{CentralSession, PeripheralBackend},
+    types::ble_types::{PacketStream, DEFAULT_MTU},
+    MeshError,
+};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use uuid::Uuid;
+
+// in memory gatt server for tests, centrals are simulated with connect
+#[derive(Debug, Clone)]
+pub struct FakePeripheralBackend {
+    state: Arc<FakeState>,
+}
+
+#[derive(Debug)]
+struct FakeState {
+    // service and characteristic currently advertised
+    advertising: Mutex<Option<(Uuid, Uuid)>>,
+    pending_tx: UnboundedSender<FakeCentralSession>,
+    pending_rx: tokio::sync::Mutex<UnboundedReceiver<FakeCentralSession>>,
+}
+
+// the central end of a fake connection, driven by the test
+#[derive(Debug)]
+pub struct FakeCentral {
+    pub mtu: usize,
+    writes: UnboundedSender<Vec<u8>>,
+    notifications: tokio::sync::Mutex<UnboundedReceiver<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+struct FakeCentralSession {
+    address: String,
+    mtu: usize,
+    notifications: UnboundedSender<Vec<u8>>,
+    writes: Mutex<Option<UnboundedReceiver<Vec<u8>>>>,
+}
+
+impl FakePeripheralBackend {
+    pub fn new() -> Self {
+        let (pending_tx, pending_rx) = mpsc::unbounded_channel();
+        Self {
+            state: Arc::new(FakeState {
+                advertising: Mutex::new(None),
+                pending_tx,
+                pending_rx: tokio::sync::Mutex::new(pending_rx),
+            }),
+        }
+    }
+
+    pub fn advertising(&self) -> Option<(Uuid, Uuid)> {
+        *self.state.advertising.lock().unwrap()
+    }
+
+    // a central connecting and subscribing, only works while the service is advertised
+    pub fn connect(&self, address: &str, service_uuid: Uuid) -> Result<FakeCentral, MeshError> {
+        self.connect_with_mtu(address, service_uuid, DEFAULT_MTU)
+    }
+
+    // same as connect, as if the att mtu exchange settled on mtu + 3
+    pub fn connect_with_mtu(
+        &self,
+        address: &str,
+        service_uuid: Uuid,
+        mtu: usize,
+    ) -> Result<FakeCentral, MeshError> {
+        match self.advertising() {
+            Some((service, _)) if service == service_uuid => {}
+            _ => {
+                return Err(MeshError::DialFailed {
+                    address: address.to_string(),
+                    reason: format!("service {} is not advertised", service_uuid),
+                })
+            }
+        }
+
+        let (writes_tx, writes_rx) = mpsc::unbounded_channel();
+        let (notifications_tx, notifications_rx) = mpsc::unbounded_channel();
+        self.state
+            .pending_tx
+            .send(FakeCentralSession {
+                address: address.to_string(),
+                mtu,
+                notifications: notifications_tx,
+                writes: Mutex::new(Some(writes_rx)),
+            })
+            .map_err(|_| MeshError::closed("fake backend dropped"))?;
+
+        Ok(FakeCentral {
+            mtu,
+            writes: writes_tx,
+            notifications: tokio::sync::Mutex::new(notifications_rx),
+        })
+    }
+}
+
+impl Default for FakePeripheralBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeCentral {
+    // a write to the peripheral's characteristic
+    pub fn write(&self, value: &[u8]) -> Result<(), MeshError> {
+        if value.len() > self.mtu {
+            return Err(MeshError::MtuExceeded {
+                size: value.len(),
+                limit: self.mtu,
+            });
+        }
+        self.writes
+            .send(value.to_vec())
+            .map_err(|_| MeshError::closed("peripheral disconnected"))?;
+        Ok(())
+    }
+
+    // next notification from the peripheral, none once it disconnected
+    pub async fn notification(&self) -> Option<Vec<u8>> {
+        self.notifications.lock().await.recv().await
+    }
+}
+
+#[async_trait]
+impl PeripheralBackend for FakePeripheralBackend {
+    async fn advertise(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Result<(), MeshError> {
+        *self.state.advertising.lock().unwrap() = Some((service_uuid, characteristic_uuid));
+        Ok(())
+    }
+
+    async fn stop_advertising(&self) -> Result<(), MeshError> {
+        *self.state.advertising.lock().unwrap() = None;
+        Ok(())
+    }
+
+    async fn accept(&self) -> Result<Box<dyn CentralSession>, MeshError> {
+        let session = self
+            .state
+            .pending_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| MeshError::closed("fake backend closed"))?;
+        Ok(Box::new(session))
+    }
+}
+
+#[async_trait]
+impl CentralSession for FakeCentralSession {
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    async fn notify(&self, value: &[u8]) -> Result<(), MeshError> {
+        if value.len() > self.mtu {
+            return Err(MeshError::MtuExceeded {
+                size: value.len(),
+                limit: self.mtu,
+            });
+        }
+        self.notifications
+            .send(value.to_vec())
+            .map_err(|_| MeshError::closed("central disconnected"))?;
+        Ok(())
+    }
+
+    async fn writes(&self) -> Result<PacketStream, MeshError> {
+        let writes = self
+            .writes
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| MeshError::other("writes already taken"))?;
+        Ok(Box::pin(UnboundedReceiverStream::new(writes)))
+    }
+}
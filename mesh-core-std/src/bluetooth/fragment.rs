@@ -0,0 +1,286 @@
+use crate::MeshError;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU16, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+// first byte of every packet written to the characteristic
+pub const PACKET_FRAGMENT: u8 = 0x00;
+pub const PACKET_DATAGRAM: u8 = 0x01;
+// sent by the peripheral when a central subscribes: the negotiated packet size (2, le)
+pub const PACKET_MTU: u8 = 0x02;
+
+// every fragment: type (1) | flags (1) | message id (2, le) | seq (2, le) | payload
+pub const FRAGMENT_HEADER_SIZE: usize = 6;
+
+// the first fragment also carries the total message length (4, le) before its payload
+pub const FIRST_FRAGMENT_HEADER_SIZE: usize = FRAGMENT_HEADER_SIZE + 4;
+
+pub const FLAG_FIRST: u8 = 0x01;
+// set on every fragment except the last one of a message
+pub const FLAG_MORE: u8 = 0x02;
+
+// largest message reassembled unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+// incomplete messages with no new fragment for this long are dropped
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// partial messages kept at once, the stalest one is dropped to make room
+pub const MAX_PENDING_MESSAGES: usize = 16;
+
+// splits outgoing messages into fragments that fit the mtu, one per connection
+#[derive(Debug)]
+pub struct Fragmenter {
+    // changes once the connection's mtu is negotiated
+    mtu: AtomicUsize,
+    pub max_message_size: usize,
+    next_id: AtomicU16,
+}
+
+impl Fragmenter {
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu: AtomicUsize::new(mtu),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            next_id: AtomicU16::new(0),
+        }
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mtu(&self, mtu: usize) {
+        self.mtu.store(mtu, Ordering::Relaxed);
+    }
+
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn fragment(&self, data: &[u8]) -> Result<Vec<Vec<u8>>, MeshError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        fragment(data, id, self.mtu(), self.max_message_size)
+    }
+}
+
+pub fn fragment(
+    data: &[u8],
+    message_id: u16,
+    mtu: usize,
+    max_message_size: usize,
+) -> Result<Vec<Vec<u8>>, MeshError> {
+    if mtu <= FIRST_FRAGMENT_HEADER_SIZE {
+        return Err(MeshError::other(format!(
+            "mtu of {} bytes is too small to fragment",
+            mtu
+        )));
+    }
+    if data.len() > max_message_size {
+        return Err(MeshError::MtuExceeded {
+            size: data.len(),
+            limit: max_message_size,
+        });
+    }
+    let total = u32::try_from(data.len()).map_err(|_| MeshError::MtuExceeded {
+        size: data.len(),
+        limit: u32::MAX as usize,
+    })?;
+
+    let first_space = mtu - FIRST_FRAGMENT_HEADER_SIZE;
+    let space = mtu - FRAGMENT_HEADER_SIZE;
+    let rest = data.len().saturating_sub(first_space);
+    let count = 1 + rest.div_ceil(space);
+    if count > u16::MAX as usize + 1 {
+        return Err(MeshError::MtuExceeded {
+            size: data.len(),
+            limit: (u16::MAX as usize + 1) * space,
+        });
+    }
+
+    let mut packets = Vec::with_capacity(count);
+    let mut offset = 0;
+    for seq in 0..count {
+        let first = seq == 0;
+        let end = (offset + if first { first_space } else { space }).min(data.len());
+        let mut flags = 0;
+        if first {
+            flags |= FLAG_FIRST;
+        }
+        if seq + 1 < count {
+            flags |= FLAG_MORE;
+        }
+
+        let mut packet = Vec::with_capacity(mtu);
+        packet.push(PACKET_FRAGMENT);
+        packet.push(flags);
+        packet.extend_from_slice(&message_id.to_le_bytes());
+        packet.extend_from_slice(&(seq as u16).to_le_bytes());
+        if first {
+            packet.extend_from_slice(&total.to_le_bytes());
+        }
+        packet.extend_from_slice(&data[offset..end]);
+        packets.push(packet);
+
+        offset = end;
+    }
+
+    Ok(packets)
+}
+
+#[derive(Debug)]
+struct Partial {
+    total: usize,
+    next_seq: u16,
+    buffer: Vec<u8>,
+    updated_at: Instant,
+}
+
+// rebuilds messages from fragments, messages with different ids may interleave
+#[derive(Debug)]
+pub struct Reassembler {
+    pub max_message_size: usize,
+    pub timeout: Duration,
+    partial: HashMap<u16, Partial>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
+
+impl Reassembler {
+    pub fn new(max_message_size: usize, timeout: Duration) -> Self {
+        Self {
+            max_message_size,
+            timeout,
+            partial: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>, MeshError> {
+        self.push_at(packet, Instant::now())
+    }
+
+    // returns the message once its last fragment arrives, a bad fragment drops its message
+    pub fn push_at(&mut self, packet: &[u8], now: Instant) -> Result<Option<Vec<u8>>, MeshError> {
+        self.expire(now);
+
+        if packet.len() < FRAGMENT_HEADER_SIZE {
+            return Err(MeshError::decode(format!(
+                "fragment of {} bytes is shorter than its header",
+                packet.len()
+            )));
+        }
+        if packet[0] != PACKET_FRAGMENT {
+            return Err(MeshError::decode(format!(
+                "packet type {:#04x} is not a fragment",
+                packet[0]
+            )));
+        }
+        let flags = packet[1];
+        let id = u16::from_le_bytes([packet[2], packet[3]]);
+        let seq = u16::from_le_bytes([packet[4], packet[5]]);
+
+        // taken out while it is checked so any error drops it, put back if more is coming
+        let (mut partial, payload) = if flags & FLAG_FIRST != 0 {
+            if packet.len() < FIRST_FRAGMENT_HEADER_SIZE {
+                return Err(MeshError::decode(
+                    "first fragment is missing the message length",
+                ));
+            }
+            if self.partial.remove(&id).is_some() {
+                log::warn!("Message {} restarted before it completed", id);
+            }
+            let total = u32::from_le_bytes([packet[6], packet[7], packet[8], packet[9]]) as usize;
+            if total > self.max_message_size {
+                return Err(MeshError::MtuExceeded {
+                    size: total,
+                    limit: self.max_message_size,
+                });
+            }
+
+            let partial = Partial {
+                total,
+                next_seq: 0,
+                buffer: Vec::with_capacity(total),
+                updated_at: now,
+            };
+            (partial, &packet[FIRST_FRAGMENT_HEADER_SIZE..])
+        } else {
+            let partial = self.partial.remove(&id).ok_or_else(|| {
+                MeshError::decode(format!("fragment {} of unknown message {}", seq, id))
+            })?;
+            (partial, &packet[FRAGMENT_HEADER_SIZE..])
+        };
+
+        if seq != partial.next_seq {
+            return Err(MeshError::decode(format!(
+                "message {} expected fragment {} but got {}",
+                id, partial.next_seq, seq
+            )));
+        }
+        if partial.buffer.len() + payload.len() > partial.total {
+            return Err(MeshError::decode(format!(
+                "message {} is longer than announced",
+                id
+            )));
+        }
+
+        partial.buffer.extend_from_slice(payload);
+        partial.next_seq = partial.next_seq.wrapping_add(1);
+        partial.updated_at = now;
+
+        if flags & FLAG_MORE != 0 {
+            if self.partial.len() >= MAX_PENDING_MESSAGES {
+                self.drop_stalest();
+            }
+            self.partial.insert(id, partial);
+            return Ok(None);
+        }
+
+        if partial.buffer.len() != partial.total {
+            return Err(MeshError::decode(format!(
+                "message {} ended after {} of {} bytes",
+                id,
+                partial.buffer.len(),
+                partial.total
+            )));
+        }
+        Ok(Some(partial.buffer))
+    }
+
+    // drops incomplete messages that stalled for longer than the timeout
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partial.len();
+        let timeout = self.timeout;
+        self.partial.retain(|id, partial| {
+            let stale = now.duration_since(partial.updated_at) > timeout;
+            if stale {
+                log::warn!("Dropping incomplete message {}", id);
+            }
+            !stale
+        });
+        before - self.partial.len()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    fn drop_stalest(&mut self) {
+        let stalest = self
+            .partial
+            .iter()
+            .min_by_key(|(_, partial)| partial.updated_at)
+            .map(|(id, _)| *id);
+        if let Some(id) = stalest {
+            log::warn!("Too many incomplete messages, dropping {}", id);
+            self.partial.remove(&id);
+        }
+    }
+}
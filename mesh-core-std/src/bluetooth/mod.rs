@@ -1 +1,6 @@
 pub mod ble;
+#[cfg(target_os = "linux")]
+pub mod bluez;
+pub mod fake;
+pub mod fragment;
+pub mod peripheral;
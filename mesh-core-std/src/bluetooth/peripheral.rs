@@ -0,0 +1,79 @@
+use crate::{
+    link::link_trait::LinkConnection,
+    types::ble_types::{datagram_packet, BlePeripheralConnection, PacketStream},
+    MeshError,
+};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use uuid::Uuid;
+
+// gatt server side of ble, btleplug only does the central role so each platform plugs in its own,
+// the crate ships BluezPeripheralBackend for linux
+#[async_trait]
+pub trait PeripheralBackend: Send + Sync + Debug {
+    // hosts the characteristic (write + notify) under the service and starts advertising it,
+    // calling it again while already advertising does nothing
+    async fn advertise(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Result<(), MeshError>;
+
+    async fn stop_advertising(&self) -> Result<(), MeshError>;
+
+    // waits for the next central that connects and subscribes to the characteristic
+    async fn accept(&self) -> Result<Box<dyn CentralSession>, MeshError>;
+}
+
+// one connected central as seen from the gatt server
+#[async_trait]
+pub trait CentralSession: Send + Sync + Debug {
+    fn address(&self) -> String;
+
+    // largest value one notification or write carries, the negotiated att mtu minus 3
+    fn mtu(&self) -> usize;
+
+    // sends a value to the central as a characteristic notification
+    async fn notify(&self, value: &[u8]) -> Result<(), MeshError>;
+
+    // values the central writes to the characteristic, can only be taken once
+    async fn writes(&self) -> Result<PacketStream, MeshError>;
+}
+
+#[async_trait]
+impl LinkConnection for BlePeripheralConnection {
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError> {
+        for packet in self.fragmenter.fragment(data)? {
+            self.central.notify(&packet).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        let mut rx = self.rx.lock().await;
+        rx.recv()
+            .await
+            .ok_or_else(|| MeshError::closed("channel closed"))
+    }
+
+    // notifications are never acknowledged, so this is the same as any other packet
+    async fn send_datagram(&self, data: &[u8]) -> Result<(), MeshError> {
+        let packet = datagram_packet(data, self.fragmenter.mtu())?;
+        self.central.notify(&packet).await
+    }
+
+    async fn receive_datagram(&self) -> Result<Vec<u8>, MeshError> {
+        let mut rx = self.datagram_rx.lock().await;
+        rx.recv()
+            .await
+            .ok_or_else(|| MeshError::closed("channel closed"))
+    }
+
+    fn mtu(&self) -> usize {
+        self.fragmenter.mtu()
+    }
+
+    fn max_datagram_size(&self) -> Option<usize> {
+        Some(self.fragmenter.mtu() - 1)
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    mesh::{MeshMessage, MessageKind},
+    types::peer::PeerID,
+    MeshError,
+};
+use prost::Message;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// bumped whenever the meaning of existing envelope fields changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// maximum number of hops a message may travel before it is dropped
+pub const DEFAULT_TTL: u32 = 16;
+
+// ids start from the clock so a restarted node doesn't repeat recent ids
+pub fn next_message_id() -> u64 {
+    static NEXT_ID: OnceLock<AtomicU64> = OnceLock::new();
+    NEXT_ID
+        .get_or_init(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            AtomicU64::new(now.as_nanos() as u64)
+        })
+        .fetch_add(1, Ordering::Relaxed)
+}
+
+impl MeshMessage {
+    pub fn new(kind: MessageKind, source: &PeerID, destination: &PeerID, payload: Vec<u8>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            source: source.0.clone(),
+            destination: destination.0.clone(),
+            ttl: DEFAULT_TTL,
+            id: next_message_id(),
+            kind: kind as i32,
+            payload,
+            extensions: Default::default(),
+        }
+    }
+
+    pub fn data(source: &PeerID, destination: &PeerID, payload: Vec<u8>) -> Self {
+        Self::new(MessageKind::Data, source, destination, payload)
+    }
+
+    pub fn with_ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_extension(mut self, key: &str, value: Vec<u8>) -> Self {
+        self.extensions.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn source_id(&self) -> PeerID {
+        PeerID(self.source.clone())
+    }
+
+    pub fn destination_id(&self) -> PeerID {
+        PeerID(self.destination.clone())
+    }
+}
+
+// links deliver whole frames, so the envelope is encoded as is
+pub fn encode_message(message: &MeshMessage) -> Vec<u8> {
+    message.encode_to_vec()
+}
+
+pub fn decode_message(data: &[u8]) -> Result<MeshMessage, MeshError> {
+    let message = MeshMessage::decode(data)?;
+    if message.version == 0 || message.version > PROTOCOL_VERSION {
+        return Err(MeshError::decode(format!(
+            "unsupported mesh protocol version {}",
+            message.version
+        )));
+    }
+    if MessageKind::try_from(message.kind).is_err() {
+        return Err(MeshError::decode(format!(
+            "unknown message kind {}",
+            message.kind
+        )));
+    }
+    Ok(message)
+}
@@ -0,0 +1,163 @@
+use std::{io, net::AddrParseError};
+use thiserror::Error;
+
+use crate::types::peer::PeerID;
+
+// what the crate's operations fail with, kinds are split by what a caller would do about them:
+// retry, pick another link or route, drop the message or report it
+#[derive(Debug, Error)]
+pub enum MeshError {
+    // no connection could be set up to the address, another endpoint may work
+    #[error("dialing {address} failed: {reason}")]
+    DialFailed { address: String, reason: String },
+
+    // the other side isn't part of our mesh or its certificate or signature doesn't check out
+    #[error("handshake rejected: {0}")]
+    Handshake(String),
+
+    #[error("unknown peer {}", .0 .0)]
+    UnknownPeer(PeerID),
+
+    #[error("no route to {}", .0 .0)]
+    NoRoute(PeerID),
+
+    // a message or packet bigger than the link, frame or protocol allows
+    #[error("{size} bytes exceed the limit of {limit} bytes")]
+    MtuExceeded { size: usize, limit: usize },
+
+    // bytes that don't parse as what they should be, dropping them is all that's left
+    #[error("decode failed: {0}")]
+    Decode(String),
+
+    // the connection, channel or task on the other end went away
+    #[error("link closed: {0}")]
+    LinkClosed(String),
+
+    // what didn't finish in time
+    #[error("timed out: {0}")]
+    Timeout(String),
+
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("bluetooth: {0}")]
+    Bluetooth(String),
+
+    // a config file that doesn't parse or holds values a node can't run with
+    #[error("invalid config: {0}")]
+    Config(String),
+
+    // anything that doesn't fit the kinds above
+    #[error("{0}")]
+    Other(String),
+}
+
+impl MeshError {
+    pub fn other(message: impl Into<String>) -> Self {
+        Self::Other(message.into())
+    }
+
+    pub fn closed(message: impl Into<String>) -> Self {
+        Self::LinkClosed(message.into())
+    }
+
+    pub fn decode(message: impl Into<String>) -> Self {
+        Self::Decode(message.into())
+    }
+
+    // worth trying again later or over another link, the rest won't go away on their own
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::DialFailed { .. } | Self::LinkClosed(_) | Self::Timeout(_) | Self::Io(_)
+        )
+    }
+}
+
+impl From<prost::DecodeError> for MeshError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::Decode(e.to_string())
+    }
+}
+
+impl From<AddrParseError> for MeshError {
+    fn from(e: AddrParseError) -> Self {
+        Self::InvalidAddress(e.to_string())
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for MeshError {
+    fn from(e: tokio::time::error::Elapsed) -> Self {
+        Self::Timeout(e.to_string())
+    }
+}
+
+// transport errors while connecting are the handshake failing, certificates included
+impl From<quinn::ConnectionError> for MeshError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        match e {
+            quinn::ConnectionError::TimedOut => Self::Timeout("quic connection".to_string()),
+            quinn::ConnectionError::TransportError(e) => Self::Handshake(e.to_string()),
+            e => Self::LinkClosed(e.to_string()),
+        }
+    }
+}
+
+impl From<quinn::WriteError> for MeshError {
+    fn from(e: quinn::WriteError) -> Self {
+        Self::LinkClosed(e.to_string())
+    }
+}
+
+impl From<quinn::ClosedStream> for MeshError {
+    fn from(e: quinn::ClosedStream) -> Self {
+        Self::LinkClosed(e.to_string())
+    }
+}
+
+impl From<quinn::StoppedError> for MeshError {
+    fn from(e: quinn::StoppedError) -> Self {
+        Self::LinkClosed(e.to_string())
+    }
+}
+
+impl From<quinn::rustls::Error> for MeshError {
+    fn from(e: quinn::rustls::Error) -> Self {
+        Self::Handshake(e.to_string())
+    }
+}
+
+impl From<quinn::rustls::client::VerifierBuilderError> for MeshError {
+    fn from(e: quinn::rustls::client::VerifierBuilderError) -> Self {
+        Self::Handshake(e.to_string())
+    }
+}
+
+impl From<webpki::Error> for MeshError {
+    fn from(e: webpki::Error) -> Self {
+        Self::Handshake(e.to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<bluer::Error> for MeshError {
+    fn from(e: bluer::Error) -> Self {
+        match e.kind {
+            bluer::ErrorKind::NotFound => Self::Bluetooth(format!("no BLE adapter found: {}", e)),
+            _ => Self::Bluetooth(e.to_string()),
+        }
+    }
+}
+
+impl From<btleplug::Error> for MeshError {
+    fn from(e: btleplug::Error) -> Self {
+        match e {
+            btleplug::Error::NotConnected => Self::LinkClosed(e.to_string()),
+            btleplug::Error::TimedOut(_) => Self::Timeout(e.to_string()),
+            e => Self::Bluetooth(e.to_string()),
+        }
+    }
+}
@@ -1,5 +1,4 @@
-pub type MeshError = Box<dyn std::error::Error + Send + Sync>;
-pub type StaticMeshError = Box<dyn std::error::Error + Send + Sync + 'static>;
+pub use error::MeshError;
 
 // generated code is stored in mesh.rs
 pub mod mesh {
@@ -7,7 +6,12 @@ pub mod mesh {
 }
 
 pub mod bluetooth;
+pub mod envelope;
+pub mod error;
 pub mod link;
+pub mod node;
+pub mod reload;
+pub mod tcp;
 pub mod types;
 pub mod utils;
 pub mod wifi;
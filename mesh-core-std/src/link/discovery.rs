@@ -1,86 +1,445 @@
 use std::{
-    net::{Ipv4Addr, SocketAddr},
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use prost::Message;
+use quinn::rustls::pki_types::CertificateDer;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
-use tokio::time;
+use tokio::{sync::watch, time};
 
-use crate::types::peer::{PeerID, PeerInfo, PeerStore};
+use crate::{
+    mesh::{Announcement, LinkAddress, SignedAnnouncement},
+    types::{
+        discovery::{DiscoveryConfig, DiscoveryHandle},
+        identity::{verify_signed, NodeIdentity},
+        peer::{LinkType, PeerID, PeerInfo, PeerStore},
+    },
+    MeshError,
+};
+
+// bumped whenever the announcement format changes, other versions are ignored
+pub const DISCOVERY_VERSION: u32 = 2;
+
+// ids of the built-in transports as announced, see LinkType
+pub const LINK_WIFI: &str = "wifi";
+pub const LINK_BLE: &str = "ble";
+pub const LINK_TCP: &str = "tcp";
+
+// components the discovery tasks report health as
+const BROADCAST: &str = "discovery broadcast";
+const LISTENER: &str = "discovery listener";
+
+impl LinkAddress {
+    pub fn new(link: &str, address: &str) -> Self {
+        Self {
+            link: link.to_string(),
+            address: address.to_string(),
+        }
+    }
+}
+
+// signs and encodes one beacon
+pub fn encode_announcement(
+    identity: &NodeIdentity,
+    addresses: &[LinkAddress],
+    capabilities: &[String],
+    sequence: u64,
+) -> Result<Vec<u8>, MeshError> {
+    let announcement = Announcement {
+        version: DISCOVERY_VERSION,
+        node_id: identity.id.0.clone(),
+        addresses: addresses.to_vec(),
+        capabilities: capabilities.to_vec(),
+        sequence,
+        certificate: identity.certificate.to_vec(),
+    }
+    .encode_to_vec();
+
+    let signature = identity.sign(&announcement)?;
+    Ok(SignedAnnouncement {
+        announcement,
+        signature,
+    }
+    .encode_to_vec())
+}
+
+// accepts beacons signed by nodes of our mesh, each one newer than the last from that node
+#[derive(Debug)]
+pub struct AnnouncementVerifier {
+    pub ca: CertificateDer<'static>,
+    // other cas whose nodes are accepted too, e.g. while the mesh moves to a new one
+    pub trusted: Vec<CertificateDer<'static>>,
+    last_sequence: HashMap<PeerID, u64>,
+}
+
+impl AnnouncementVerifier {
+    pub fn new(ca: CertificateDer<'static>) -> Self {
+        Self {
+            ca,
+            trusted: Vec::new(),
+            last_sequence: HashMap::new(),
+        }
+    }
+
+    pub fn set_trusted(&mut self, trusted: Vec<CertificateDer<'static>>) {
+        self.trusted = trusted;
+    }
 
-// sends message to the peers
-pub async fn broadcast(sender_id: PeerID, sender_addr: SocketAddr) {
-    // 0.0.0.0 binds all local addresses
-    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await.unwrap();
-    let message = format!("{}|{}", sender_id.0, sender_addr);
+    pub fn verify(&mut self, data: &[u8]) -> Result<Announcement, MeshError> {
+        let signed = SignedAnnouncement::decode(data)?;
+        if signed.signature.is_empty() {
+            return Err(MeshError::Handshake("unsigned announcement".to_string()));
+        }
+
+        let announcement = Announcement::decode(signed.announcement.as_slice())?;
+        if announcement.version != DISCOVERY_VERSION {
+            return Err(MeshError::decode(format!(
+                "unsupported discovery version {}",
+                announcement.version
+            )));
+        }
+
+        let id = PeerID(announcement.node_id.clone());
+        let verify = |ca| {
+            verify_signed(
+                ca,
+                &announcement.certificate,
+                &id,
+                &signed.announcement,
+                &signed.signature,
+            )
+        };
+        // the error reported is the one of our own ca
+        let verified = verify(&self.ca);
+        if verified.is_err() && !self.trusted.iter().any(|ca| verify(ca).is_ok()) {
+            verified?;
+        }
+
+        // only checked once the signature holds, so forged beacons can't bump the sequence
+        if let Some(last) = self.last_sequence.get(&id) {
+            if announcement.sequence <= *last {
+                return Err(MeshError::Handshake(format!(
+                    "replayed announcement from {}: sequence {} after {}",
+                    id.0, announcement.sequence, last
+                )));
+            }
+        }
+        self.last_sequence.insert(id, announcement.sequence);
+        Ok(announcement)
+    }
+}
 
-    tokio::spawn(async move {
+// fills in what only the receiver knows: the sender's address when it announced a wildcard,
+// and the interface a link-local address is reachable through
+pub fn resolve_announced(addr: SocketAddr, src: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        let mut resolved = src;
+        resolved.set_port(addr.port());
+        return resolved;
+    }
+    match (addr, src) {
+        (SocketAddr::V6(mut addr), SocketAddr::V6(src)) if addr.ip().is_unicast_link_local() => {
+            addr.set_scope_id(src.scope_id());
+            addr.into()
+        }
+        _ => addr,
+    }
+}
+
+// verifies a beacon received from src and records the node it describes, returns who was seen
+pub fn handle_announcement(
+    peer_store: &Mutex<PeerStore>,
+    verifier: &mut AnnouncementVerifier,
+    data: &[u8],
+    src: SocketAddr,
+) -> Result<PeerID, MeshError> {
+    let announcement = verifier.verify(data)?;
+    store_announcement(peer_store, announcement, src)
+}
+
+// a verified announcement into the store, addresses resolved against where it came from
+fn store_announcement(
+    peer_store: &Mutex<PeerStore>,
+    announcement: Announcement,
+    src: SocketAddr,
+) -> Result<PeerID, MeshError> {
+    let id = PeerID(announcement.node_id);
+
+    let mut store = peer_store.lock().unwrap();
+    let mut peer_info = PeerInfo::new(id.clone());
+    for address in &announcement.addresses {
+        let link = LinkType::new(address.link.clone());
+        if !store.registry.contains(&link) {
+            log::debug!("Ignoring {} address of {}", link, id.0);
+            continue;
+        }
+        // one bad endpoint doesn't cost us the others
+        match store
+            .registry
+            .parse_address(&link, &address.address, Some(src))
+        {
+            Ok(parsed) => peer_info = peer_info.with_endpoint(link, parsed),
+            Err(e) => log::debug!("Dropping endpoint of {}: {}", id.0, e),
+        }
+    }
+    peer_info.capabilities = announcement.capabilities;
+    store.update_store(peer_info);
+    Ok(id)
+}
+
+// sockets beacons are sent from, paired with where each one sends to
+fn sender_sockets(
+    config: &DiscoveryConfig,
+) -> Result<Vec<(tokio::net::UdpSocket, SocketAddr)>, MeshError> {
+    let mut sockets = Vec::new();
+
+    if config.ipv4 {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_multicast_ttl_v4(config.ttl)?;
+        socket.set_multicast_loop_v4(config.loopback)?;
+        if !config.interface.is_unspecified() {
+            socket.set_multicast_if_v4(&config.interface)?;
+        }
+        socket.bind(&SockAddr::from(SocketAddr::new(config.interface.into(), 0)))?;
+        socket.set_nonblocking(true)?;
+        let group = SocketAddr::new(config.group.into(), config.port);
+        sockets.push((tokio::net::UdpSocket::from_std(socket.into())?, group));
+    }
+
+    // link-local groups only exist per interface, so each one gets its own socket
+    for &index in &config.ipv6_interfaces {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.set_multicast_if_v6(index)?;
+        socket.set_multicast_hops_v6(config.ttl)?;
+        socket.set_multicast_loop_v6(config.loopback)?;
+        socket.bind(&SockAddr::from(SocketAddr::new(
+            Ipv6Addr::UNSPECIFIED.into(),
+            0,
+        )))?;
+        socket.set_nonblocking(true)?;
+        let group = SocketAddrV6::new(config.group_v6, config.port, 0, index);
+        sockets.push((
+            tokio::net::UdpSocket::from_std(socket.into())?,
+            group.into(),
+        ));
+    }
+
+    if sockets.is_empty() {
+        return Err(MeshError::other(
+            "discovery has neither ipv4 nor any ipv6 interface enabled",
+        ));
+    }
+    Ok(sockets)
+}
+
+// sends our announcement to the configured groups every interval until stopped
+pub async fn broadcast(
+    config: &DiscoveryConfig,
+    identity: Arc<NodeIdentity>,
+    addresses: Vec<LinkAddress>,
+    capabilities: Vec<String>,
+) -> Result<DiscoveryHandle, MeshError> {
+    let sockets = sender_sockets(config)?;
+    let mut config = config.clone();
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let (timing_tx, mut timing_rx) = watch::channel((config.interval, config.jitter));
+
+    // starts from the clock so a restarted node isn't taken for a replay
+    let mut sequence = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let task = tokio::spawn(async move {
+        let mut backoff = config.interval;
         loop {
-            match socket
-                .send_to(message.as_bytes(), &format!("239.255.0.1:4000"))
-                .await
+            sequence += 1;
+            let message = match encode_announcement(&identity, &addresses, &capabilities, sequence)
             {
-                Ok(_) => log::info!("Message sent to the node"),
-                Err(e) => log::warn!("Failed to send UDP packet: {}", e),
+                Ok(message) => message,
+                Err(e) => {
+                    config
+                        .health
+                        .stopped(BROADCAST, format!("failed to sign announcement: {}", e));
+                    return;
+                }
+            };
+
+            let mut sent = false;
+            for (socket, group) in &sockets {
+                match socket.send_to(&message, group).await {
+                    Ok(_) => {
+                        log::debug!("Announcement {} sent to {}", sequence, group);
+                        sent = true;
+                    }
+                    Err(e) => log::warn!("Failed to send announcement to {}: {}", group, e),
+                }
             }
 
-            let _ = time::sleep(Duration::from_secs(2));
+            // back off only when no group could be reached at all
+            let delay = if sent {
+                if backoff != config.interval {
+                    config.health.recovered(BROADCAST);
+                }
+                backoff = config.interval;
+                config.next_delay()
+            } else {
+                if backoff == config.interval {
+                    config
+                        .health
+                        .degraded(BROADCAST, "no group could be reached");
+                }
+                backoff = config.backoff(backoff);
+                backoff
+            };
+
+            tokio::select! {
+                _ = time::sleep(delay) => {}
+                _ = stop_rx.changed() => return,
+                // beacons at the new pace right away
+                Ok(()) = timing_rx.changed() => {
+                    (config.interval, config.jitter) = *timing_rx.borrow_and_update();
+                    backoff = config.interval;
+                }
+            }
         }
     });
+
+    Ok(DiscoveryHandle {
+        stop_tx,
+        tasks: vec![task],
+        timing: Some(timing_tx),
+        verifier: None,
+    })
 }
 
-// listens for messages in the network in multiple available interfaces and adds to the
-// corresponding peer store
-pub async fn listener(peer_store: Arc<Mutex<PeerStore>>, address: String) {
+// sockets joined to the configured groups
+fn listener_sockets(config: &DiscoveryConfig) -> Result<Vec<tokio::net::UdpSocket>, MeshError> {
+    let mut sockets = Vec::new();
+
     // socket2 le socket modify garna help garcha
     // socket2 makes aeuta blockcing socket which cannot be converted to tokio socket
     // so non blocking is set
-    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-    socket.set_reuse_port(true).unwrap();
-    let addr = address.parse::<SocketAddr>().unwrap();
-    socket.bind(&SockAddr::from(addr)).unwrap();
-    socket.set_nonblocking(true).unwrap();
-    let std_socket: std::net::UdpSocket = socket.into();
-    let tokio_socket: tokio::net::UdpSocket = tokio::net::UdpSocket::from_std(std_socket).unwrap();
-
-    tokio_socket
-        .join_multicast_v4(
-            Ipv4Addr::new(239, 255, 0, 1),
-            Ipv4Addr::new(0, 0, 0, 0).into(),
-        )
-        .unwrap();
+    if config.ipv4 {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // several nodes on one host share the port
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), config.port);
+        socket.bind(&SockAddr::from(addr))?;
+        socket.join_multicast_v4(&config.group, &config.interface)?;
+        socket.set_nonblocking(true)?;
+        sockets.push(tokio::net::UdpSocket::from_std(socket.into())?);
+    }
 
+    // one socket joins the group on every interface, the source scope tells them apart
+    if !config.ipv6_interfaces.is_empty() {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        let addr = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), config.port);
+        socket.bind(&SockAddr::from(addr))?;
+        for &index in &config.ipv6_interfaces {
+            socket.join_multicast_v6(&config.group_v6, index)?;
+        }
+        socket.set_nonblocking(true)?;
+        sockets.push(tokio::net::UdpSocket::from_std(socket.into())?);
+    }
+
+    if sockets.is_empty() {
+        return Err(MeshError::other(
+            "discovery has neither ipv4 nor any ipv6 interface enabled",
+        ));
+    }
+    Ok(sockets)
+}
+
+// listens for announcements on the configured groups and adds their senders to the peer store,
+// only beacons signed under the mesh CA are trusted
+pub async fn listener(
+    config: &DiscoveryConfig,
+    peer_store: Arc<Mutex<PeerStore>>,
+    ca: CertificateDer<'static>,
+) -> Result<DiscoveryHandle, MeshError> {
+    let sockets = listener_sockets(config)?;
+    let (stop_tx, stop_rx) = watch::channel(false);
+    // shared so a beacon replayed over the other address family is still caught
+    let verifier = Arc::new(Mutex::new(AnnouncementVerifier::new(ca)));
+
+    let tasks = sockets
+        .into_iter()
+        .map(|socket| {
+            tokio::spawn(receive_loop(
+                socket,
+                config.clone(),
+                peer_store.clone(),
+                verifier.clone(),
+                stop_rx.clone(),
+            ))
+        })
+        .collect();
+
+    Ok(DiscoveryHandle {
+        stop_tx,
+        tasks,
+        timing: None,
+        verifier: Some(verifier),
+    })
+}
+
+async fn receive_loop(
+    socket: tokio::net::UdpSocket,
+    config: DiscoveryConfig,
+    peer_store: Arc<Mutex<PeerStore>>,
+    verifier: Arc<Mutex<AnnouncementVerifier>>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
     let mut buf = vec![0u8; 4096];
+    let mut backoff = Duration::ZERO;
 
     loop {
-        match tokio_socket.recv_from(&mut buf).await {
-            Ok((len, _src)) => {
-                let payload = String::from_utf8_lossy(&buf[..len]);
-                match payload.split_once("|") {
-                    Some((id, addr_str)) => match addr_str.parse::<SocketAddr>() {
-                        Ok(addr) => {
-                            let peer_info = PeerInfo {
-                                id: PeerID(id.to_string()),
-                                wifi_addr: Some(addr),
-                                ble_addr: None,
-                                last_seen: Instant::now(),
-                                rtt_ms: None,
-                                mtu: None,
-                                loss_percent: None,
-                            };
-                            let mut peer_store = peer_store.lock().unwrap();
-                            peer_store.update_store(peer_info);
-                            log::info!("Discovered peer: {} at {}", id, addr);
-                        }
-                        Err(e) => log::warn!("Invalid peer address '{}': {}", addr_str, e),
-                    },
-                    None => log::warn!("Invalid payload format: {}", payload),
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            _ = stop_rx.changed() => return,
+        };
+
+        match received {
+            Ok((len, src)) => {
+                if !backoff.is_zero() {
+                    config.health.recovered(LISTENER);
+                }
+                backoff = Duration::ZERO;
+                let verified = verifier.lock().unwrap().verify(&buf[..len]);
+                let stored = verified.and_then(|announcement| {
+                    // our own beacon, back over loopback
+                    if config.local_id.as_ref().map(|id| id.0.as_str())
+                        == Some(announcement.node_id.as_str())
+                    {
+                        return Ok(None);
+                    }
+                    store_announcement(&peer_store, announcement, src).map(Some)
+                });
+                match stored {
+                    Ok(Some(id)) => log::info!("Discovered peer: {} from {}", id.0, src),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Rejected announcement from {}: {}", src, e),
                 }
             }
             Err(e) => {
                 log::error!("Failed to receive UDP packet: {}", e);
-                tokio::time::sleep(Duration::from_millis(50)).await;
+                if backoff.is_zero() {
+                    config.health.degraded(LISTENER, &e);
+                }
+                backoff = config.backoff(backoff.max(Duration::from_millis(25)));
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = stop_rx.changed() => return,
+                }
             }
         }
     }
@@ -0,0 +1,50 @@
+use crate::MeshError;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// 4 byte big endian length in front of every message
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+// largest message a stream link accepts unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+    max_frame_size: usize,
+) -> Result<(), MeshError> {
+    if data.len() > max_frame_size {
+        return Err(MeshError::MtuExceeded {
+            size: data.len(),
+            limit: max_frame_size,
+        });
+    }
+
+    let length = u32::try_from(data.len()).map_err(|_| MeshError::MtuExceeded {
+        size: data.len(),
+        limit: u32::MAX as usize,
+    })?;
+    writer.write_all(&length.to_be_bytes()).await?;
+    writer.write_all(data).await?;
+    Ok(())
+}
+
+// reads exactly one frame, a stream ending half way through is an error
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_frame_size: usize,
+) -> Result<Vec<u8>, MeshError> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    reader.read_exact(&mut header).await?;
+
+    let length = u32::from_be_bytes(header) as usize;
+    if length > max_frame_size {
+        return Err(MeshError::MtuExceeded {
+            size: length,
+            limit: max_frame_size,
+        });
+    }
+
+    let mut data = vec![0u8; length];
+    reader.read_exact(&mut data).await?;
+    Ok(data)
+}
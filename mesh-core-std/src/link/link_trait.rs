@@ -1,29 +1,69 @@
+use crate::MeshError;
 use async_trait::async_trait;
+use quinn::rustls::pki_types::CertificateDer;
 use std::time::Duration;
 
+// smallest packet every link carries, a ble attribute value at the default att mtu
+pub const MIN_MTU: usize = 20;
+
 #[async_trait]
 pub trait Link {
     // Initias a connection to remote address
-    async fn dial(
-        &self,
-        address: &str,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn dial(&self, address: &str)
+        -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError>;
 
     // Accepts an incoming connection
-    async fn accept(
-        &self,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError>;
 
-    // Maximum packet size for this link
+    // Maximum packet size for this link, the datagram limit on links that have one,
+    // connections may negotiate more, see LinkConnection::mtu
     fn mtu(&self) -> usize;
 
     // Estimated Latency
     fn latency(&self) -> Duration;
+
+    // Relative energy spent per message, 1.0 for wi-fi
+    fn energy_cost(&self) -> f64 {
+        1.0
+    }
+
+    // Drops whatever the link keeps for a peer that went away, the next dial starts fresh
+    fn forget(&self, _address: &str) {}
+
+    // Replaces the certificates dialed peers are checked against, connections already up
+    // are kept, links that don't use tls have nothing to replace
+    fn set_trusted(&self, _trusted: &[CertificateDer<'static>]) -> Result<(), MeshError> {
+        Ok(())
+    }
 }
 
 // Single active connection over a link
 #[async_trait]
 pub trait LinkConnection {
-    async fn send(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
-    async fn receive(&self) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError>;
+    async fn receive(&self) -> Result<Vec<u8>, MeshError>;
+
+    // Packet size in use on this connection, never below the link's default mtu,
+    // connections that don't know theirs get the smallest packet any link carries
+    fn mtu(&self) -> usize {
+        MIN_MTU
+    }
+
+    // Unreliable, unordered delivery for beacons and heartbeats, never queued behind streams
+    async fn send_datagram(&self, _data: &[u8]) -> Result<(), MeshError> {
+        Err(MeshError::other(
+            "unreliable datagrams are not supported by this link",
+        ))
+    }
+
+    async fn receive_datagram(&self) -> Result<Vec<u8>, MeshError> {
+        Err(MeshError::other(
+            "unreliable datagrams are not supported by this link",
+        ))
+    }
+
+    // Largest payload send_datagram currently accepts, None if datagrams are unsupported
+    fn max_datagram_size(&self) -> Option<usize> {
+        None
+    }
 }
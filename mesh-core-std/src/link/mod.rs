@@ -1,3 +1,6 @@
 pub mod discovery;
+pub mod framing;
 pub mod link_trait;
 pub mod multilink;
+pub mod policy;
+pub mod registry;
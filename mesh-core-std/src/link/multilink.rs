@@ -1,17 +1,44 @@
 use crate::{
-    types::peer::{LinkType, PeerID, PeerInfo, PeerStore},
+    envelope::{decode_message, encode_message},
+    mesh::{MeshMessage, MessageKind},
+    types::{
+        health::Health,
+        multipath::{split_stripes, MultipathReceiver, SendMode},
+        peer::{LinkEndpoint, LinkType, PeerEvent, PeerID, PeerInfo, PeerStore},
+        probe::ProbeConfig,
+        routing::NeighbourSender,
+    },
     MeshError,
 };
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Instant};
-use tokio::sync::Mutex;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    task::{JoinHandle, JoinSet},
+};
 
-use super::link_trait::Link;
+use super::{
+    link_trait::Link,
+    policy::{LinkCandidate, LinkPolicy, WeightedPolicy},
+    registry::Transport,
+};
 
 pub struct MultiLinkManager {
     pub peer_store: Arc<Mutex<PeerStore>>,
     pub links: HashMap<LinkType, Box<dyn Link + Send + Sync>>,
     pub bootstraps: Vec<(PeerID, SocketAddr)>,
-    pub priority: Vec<LinkType>,
+    // links in the order they're preferred, see set_priority
+    priority: Mutex<Vec<LinkType>>,
+    pub policy: Box<dyn LinkPolicy>,
+    // where the accept loops report links that stopped
+    pub health: Health,
+    // link each peer was last picked or reached on, what hysteresis compares against
+    selected: Mutex<HashMap<PeerID, (LinkType, String)>>,
+    receiver: Mutex<MultipathReceiver>,
 }
 
 impl MultiLinkManager {
@@ -24,92 +51,575 @@ impl MultiLinkManager {
             peer_store: Arc::new(Mutex::new(PeerStore::default())),
             links,
             bootstraps,
-            priority,
+            priority: Mutex::new(priority),
+            policy: Box::new(WeightedPolicy::default()),
+            health: Health::default(),
+            selected: Mutex::new(HashMap::new()),
+            receiver: Mutex::new(MultipathReceiver::default()),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: Box<dyn LinkPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    // shares a store with discovery and routing so all of them see the same peers,
+    // transports added through with_transport carry over into its registry
+    pub fn with_peer_store(mut self, peer_store: Arc<Mutex<PeerStore>>) -> Self {
+        let registry = self.peer_store.lock().unwrap().registry.clone();
+        {
+            let mut store = peer_store.lock().unwrap();
+            for id in self.links.keys() {
+                if let (false, Some(transport)) = (store.registry.contains(id), registry.get(id)) {
+                    store.registry.register(transport.clone());
+                }
+            }
+        }
+        self.peer_store = peer_store;
+        self
+    }
+
+    // adds a link over a transport the crate doesn't know, tried after the ones already listed
+    pub fn with_transport(
+        mut self,
+        transport: Transport,
+        link: Box<dyn Link + Send + Sync>,
+    ) -> Self {
+        let id = transport.id.clone();
+        self.peer_store.lock().unwrap().registry.register(transport);
+        self.links.insert(id.clone(), link);
+        let priority = self.priority.get_mut().unwrap();
+        if !priority.contains(&id) {
+            priority.push(id);
+        }
+        self
+    }
+
+    pub fn priority(&self) -> Vec<LinkType> {
+        self.priority.lock().unwrap().clone()
+    }
+
+    // takes effect with the next send, links we don't have are dropped and links left out
+    // keep their old order behind the ones listed
+    pub fn set_priority(&self, priority: Vec<LinkType>) {
+        let mut current = self.priority.lock().unwrap();
+        let mut updated: Vec<LinkType> = Vec::new();
+        for link in priority.into_iter().chain(current.drain(..)) {
+            if self.links.contains_key(&link) && !updated.contains(&link) {
+                updated.push(link);
+            }
+        }
+        log::info!("Link priority now {:?}", updated);
+        *current = updated;
+    }
+
+    // lets the links drop what they hold for peers the store declared dead,
+    // runs until the manager is dropped
+    pub fn watch_peers(self: &Arc<Self>) -> JoinHandle<()> {
+        let mut events = self.peer_store.lock().unwrap().subscribe();
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("Missed {} peer events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                manager.on_peer_event(&event);
+            }
+        })
+    }
+
+    fn on_peer_event(&self, event: &PeerEvent) {
+        match event {
+            PeerEvent::Joined(peer) => log::debug!("Peer {} reachable", peer.id.0),
+            PeerEvent::Left(peer) => {
+                log::info!("Forgetting connections to {}", peer.id.0);
+                self.selected.lock().unwrap().remove(&peer.id);
+                for endpoint in &peer.endpoints {
+                    if let Some(link) = self.links.get(&endpoint.link) {
+                        link.forget(&endpoint.address);
+                    }
+                }
+            }
         }
     }
 
     pub async fn bootstrap_peers(&self) {
-        let mut store = self.peer_store.lock().await;
+        let mut store = self.peer_store.lock().unwrap();
         for (id, addr) in &self.bootstraps {
-            let peer = PeerInfo {
-                id: id.clone(),
-                wifi_addr: Some(*addr),
-                ble_addr: None,
-                last_seen: Instant::now(),
-                rtt_ms: Some(0),
-                mtu: Some(1500),
-                loss_percent: Some(0.0),
-            };
+            let peer = PeerInfo::new(id.clone()).with_endpoint(LinkType::WIFI, addr.to_string());
             store.update_store(peer);
         }
     }
 
-    pub async fn pick_best_link(&self, peer_id: &PeerID) -> Option<LinkType> {
-        let store = self.peer_store.lock().await;
-        let peer = store.get_peer(peer_id.clone()).unwrap();
-        for lt in &self.priority {
-            match lt {
-                LinkType::Wifi if peer.wifi_addr.is_some() => return Some(LinkType::Wifi),
-                LinkType::Ble if peer.ble_addr.is_some() => return Some(LinkType::Ble),
-                _ => {}
+    // the cheapest endpoint of the peer according to the policy, see rank_endpoints
+    pub async fn pick_best_link(&self, peer_id: &PeerID) -> Result<LinkEndpoint, MeshError> {
+        let peer = self.known_peer(peer_id)?;
+        let best = self
+            .rank_endpoints(&peer)
+            .into_iter()
+            .next()
+            .ok_or_else(|| MeshError::NoRoute(peer_id.clone()))?;
+        self.select(peer_id, &best);
+        Ok(best)
+    }
+
+    // endpoints of the peer on links we have, cheapest first, ties keep the priority order,
+    // the endpoint in use stays first unless another undercuts it by the policy's hysteresis
+    fn rank_endpoints(&self, peer: &PeerInfo) -> Vec<LinkEndpoint> {
+        let registry = self.peer_store.lock().unwrap().registry.clone();
+        let priorities = self.priority();
+        let mut ranked: Vec<(LinkEndpoint, f64)> = peer
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| {
+                let priority = priorities.iter().position(|lt| *lt == endpoint.link)?;
+                let link = self.links.get(&endpoint.link)?;
+                let capabilities = registry.capabilities(&endpoint.link)?;
+                let metrics = &endpoint.metrics;
+                let candidate = LinkCandidate {
+                    link_type: endpoint.link.clone(),
+                    address: endpoint.address.clone(),
+                    rtt: metrics.srtt.unwrap_or_else(|| link.latency()),
+                    jitter: metrics.jitter,
+                    loss: metrics.loss,
+                    mtu: link.mtu(),
+                    energy_cost: link.energy_cost(),
+                    failures: metrics.consecutive_failures,
+                    priority,
+                    capabilities,
+                };
+                Some((endpoint.clone(), self.policy.cost(&candidate)))
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((link, address)) = self.selected.lock().unwrap().get(&peer.id) {
+            let position = ranked.iter().position(|(e, _)| e.is(link, address));
+            if let (Some(position), Some((_, best))) = (position, ranked.first()) {
+                let threshold = ranked[position].1 * (1.0 - self.policy.hysteresis());
+                if *best >= threshold {
+                    let current = ranked.remove(position);
+                    ranked.insert(0, current);
+                }
+            }
+        }
+        ranked.into_iter().map(|(endpoint, _)| endpoint).collect()
+    }
+
+    // the best endpoint on each link, what multipath spreads a message over
+    fn best_per_link(&self, peer: &PeerInfo) -> Vec<LinkEndpoint> {
+        let mut best: Vec<LinkEndpoint> = Vec::new();
+        for endpoint in self.rank_endpoints(peer) {
+            if !best.iter().any(|e| e.link == endpoint.link) {
+                best.push(endpoint);
             }
         }
-        None
-    }
-
-    pub async fn send(&self, peer_id: &PeerID, data: &[u8]) -> Result<(), MeshError> {
-        let store = self.peer_store.lock().await;
-        if let Some(peer) = store.get_peer(peer_id.clone()) {
-            for lt in &self.priority {
-                let send_result = match lt {
-                    LinkType::Wifi => {
-                        if let Some(addr) = peer.wifi_addr {
-                            if let Some(link) = self.links.get(lt) {
-                                match link.dial(&addr.to_string()).await {
-                                    Ok(conn) => conn.send(data).await,
-                                    Err(e) => {
-                                        log::warn!("wi-fi dial failed for {}: {}", peer_id.0, e);
-                                        continue;
-                                    }
-                                }
-                            } else {
-                                log::warn!("wi-fi link not available in manager");
-                                continue;
-                            }
-                        } else {
-                            log::warn!("peer {} has no wi-fi address", peer_id.0);
-                            continue;
+        best
+    }
+
+    fn select(&self, peer_id: &PeerID, endpoint: &LinkEndpoint) {
+        self.selected.lock().unwrap().insert(
+            peer_id.clone(),
+            (endpoint.link.clone(), endpoint.address.clone()),
+        );
+    }
+
+    // tries the endpoints in the order rank_endpoints puts them in
+    pub async fn send(&self, peer_id: &PeerID, message: &MeshMessage) -> Result<(), MeshError> {
+        let data = &encode_message(message);
+        let peer = self.known_peer(peer_id)?;
+        for endpoint in self.rank_endpoints(&peer) {
+            match self.send_via(&peer.id, &endpoint, data).await {
+                Ok(()) => {
+                    self.select(peer_id, &endpoint);
+                    return Ok(());
+                }
+                Err(e) => log::warn!(
+                    "Failed to send data via {} {} to {}: {}",
+                    endpoint.link,
+                    endpoint.address,
+                    peer_id.0,
+                    e
+                ),
+            }
+        }
+        log::error!("No route available to peer {}", peer_id.0);
+        Err(MeshError::NoRoute(peer_id.clone()))
+    }
+
+    pub async fn send_with_mode(
+        self: &Arc<Self>,
+        peer_id: &PeerID,
+        message: &MeshMessage,
+        mode: SendMode,
+    ) -> Result<(), MeshError> {
+        match mode {
+            SendMode::Single => self.send(peer_id, message).await,
+            SendMode::Redundant => self.send_redundant(peer_id, message).await,
+            SendMode::Striped => self.send_striped(peer_id, message).await,
+        }
+    }
+
+    // the same message over every link at once, succeeds if any copy got through
+    async fn send_redundant(
+        self: &Arc<Self>,
+        peer_id: &PeerID,
+        message: &MeshMessage,
+    ) -> Result<(), MeshError> {
+        let peer = self.known_peer(peer_id)?;
+        let data = Arc::new(encode_message(message));
+        let mut sends = JoinSet::new();
+        for endpoint in self.best_per_link(&peer) {
+            let (manager, peer_id, data) = (self.clone(), peer_id.clone(), data.clone());
+            sends.spawn(async move {
+                let result = manager.send_via(&peer_id, &endpoint, &data).await;
+                (endpoint.link, result)
+            });
+        }
+
+        let mut delivered = 0;
+        while let Some(joined) = sends.join_next().await {
+            match joined {
+                Ok((_, Ok(()))) => delivered += 1,
+                Ok((lt, Err(e))) => {
+                    log::warn!("Redundant copy via {} to {} failed: {}", lt, peer_id.0, e)
+                }
+                Err(e) => log::warn!("Redundant send to {} panicked: {}", peer_id.0, e),
+            }
+        }
+        if delivered == 0 {
+            return Err(MeshError::NoRoute(peer_id.clone()));
+        }
+        log::info!(
+            "Sent {} copies of message {} to {}",
+            delivered,
+            message.id,
+            peer_id.0
+        );
+        Ok(())
+    }
+
+    // splits the payload across links by capacity, a stripe whose link fails is resent
+    // over one that worked
+    async fn send_striped(
+        self: &Arc<Self>,
+        peer_id: &PeerID,
+        message: &MeshMessage,
+    ) -> Result<(), MeshError> {
+        let peer = self.known_peer(peer_id)?;
+        let endpoints = self.best_per_link(&peer);
+        if endpoints.len() < 2 || message.payload.len() < 2 {
+            return self.send(peer_id, message).await;
+        }
+        let weights: Vec<f64> = endpoints.iter().map(|e| self.capacity(e)).collect();
+        let stripes = split_stripes(message, &weights)?;
+
+        let mut sends = JoinSet::new();
+        for (stripe, endpoint) in stripes.into_iter().zip(endpoints) {
+            let (manager, peer_id) = (self.clone(), peer_id.clone());
+            sends.spawn(async move {
+                let data = encode_message(&stripe);
+                let result = manager.send_via(&peer_id, &endpoint, &data).await;
+                (endpoint, data, result)
+            });
+        }
+
+        let mut working = Vec::new();
+        let mut failed = Vec::new();
+        while let Some(joined) = sends.join_next().await {
+            match joined {
+                Ok((endpoint, _, Ok(()))) => working.push(endpoint),
+                Ok((endpoint, data, Err(e))) => {
+                    log::warn!(
+                        "Stripe via {} to {} failed: {}",
+                        endpoint.link,
+                        peer_id.0,
+                        e
+                    );
+                    failed.push(data);
+                }
+                Err(e) => {
+                    return Err(MeshError::other(format!(
+                        "striped send to {} panicked: {}",
+                        peer_id.0, e
+                    )))
+                }
+            }
+        }
+
+        for data in failed {
+            let mut resent = false;
+            for endpoint in &working {
+                if self.send_via(peer_id, endpoint, &data).await.is_ok() {
+                    resent = true;
+                    break;
+                }
+            }
+            if !resent {
+                log::warn!(
+                    "Stripe of message {} didn't reach {}",
+                    message.id,
+                    peer_id.0
+                );
+                return Err(MeshError::NoRoute(peer_id.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    // accepts on every link and hands out data messages once, whichever links they came over,
+    // striped ones after all their stripes arrived, stops once the receiver is dropped
+    pub fn start_receiving(self: &Arc<Self>) -> mpsc::Receiver<MeshMessage> {
+        let (tx, rx) = mpsc::channel(64);
+        for lt in self.links.keys() {
+            let (manager, lt, tx) = (self.clone(), lt.clone(), tx.clone());
+            tokio::spawn(async move {
+                loop {
+                    let accepted = tokio::select! {
+                        accepted = manager.links[&lt].accept() => accepted,
+                        _ = tx.closed() => return,
+                    };
+                    let connection = match accepted {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            let reason = format!("stopped accepting connections: {}", e);
+                            manager.health.stopped(format!("{} accept", lt), reason);
+                            return;
                         }
-                    }
-                    LinkType::Ble => {
-                        if let Some(addr) = &peer.ble_addr {
-                            if let Some(link) = self.links.get(lt) {
-                                match link.dial(addr).await {
-                                    Ok(conn) => conn.send(data).await,
-                                    Err(e) => {
-                                        log::warn!("ble dial failed for {}: {}", peer_id.0, e);
-                                        continue;
+                    };
+                    let (manager, tx) = (manager.clone(), tx.clone());
+                    tokio::spawn(async move {
+                        loop {
+                            let data = tokio::select! {
+                                received = connection.receive() => match received {
+                                    Ok(data) => data,
+                                    Err(_) => return,
+                                },
+                                _ = tx.closed() => return,
+                            };
+                            match manager.deliver(&data) {
+                                Ok(Some(message)) => {
+                                    if tx.send(message).await.is_err() {
+                                        return;
                                     }
                                 }
-                            } else {
-                                log::warn!("ble link not available in manager");
-                                continue;
+                                Ok(None) => {}
+                                Err(e) => log::warn!("Dropping incoming message: {}", e),
                             }
-                        } else {
-                            log::warn!("peer {} has no ble address", peer_id.0);
-                            continue;
                         }
-                    }
+                    });
+                }
+            });
+        }
+        rx
+    }
+
+    // the data message in data if it is new and complete, routing frames are handed out as they
+    // come for whoever runs a strategy, probes and copies yield None
+    pub fn deliver(&self, data: &[u8]) -> Result<Option<MeshMessage>, MeshError> {
+        let message = decode_message(data)?;
+        match message.kind() {
+            MessageKind::Data => {}
+            MessageKind::Routing => return Ok(Some(message)),
+            _ => return Ok(None),
+        }
+        self.receiver.lock().unwrap().push(message)
+    }
+
+    fn known_peer(&self, peer_id: &PeerID) -> Result<PeerInfo, MeshError> {
+        self.peer_store.lock().unwrap().peer(peer_id).cloned()
+    }
+
+    // bytes per second an endpoint can be expected to carry, rough but comparable
+    fn capacity(&self, endpoint: &LinkEndpoint) -> f64 {
+        let Some(link) = self.links.get(&endpoint.link) else {
+            return 0.0;
+        };
+        let metrics = &endpoint.metrics;
+        let rtt = metrics.srtt.unwrap_or_else(|| link.latency()).as_secs_f64();
+        link.mtu() as f64 / rtt.max(0.001) * (1.0 - metrics.loss).max(0.01)
+    }
+
+    // dials the endpoint and sends, the outcome is recorded in the store
+    async fn send_via(
+        &self,
+        peer_id: &PeerID,
+        endpoint: &LinkEndpoint,
+        data: &[u8],
+    ) -> Result<(), MeshError> {
+        let link = self
+            .links
+            .get(&endpoint.link)
+            .ok_or_else(|| MeshError::NoRoute(peer_id.clone()))?;
+        let send_result = match link.dial(&endpoint.address).await {
+            Ok(conn) => conn.send(data).await.map(|_| conn.mtu()),
+            Err(e) => Err(e),
+        };
+
+        let mut store = self.peer_store.lock().unwrap();
+        store.record_delivery(
+            peer_id,
+            &endpoint.link,
+            &endpoint.address,
+            send_result.is_ok(),
+        );
+        let mtu = send_result?;
+        log::info!(
+            "Successfully sent data to {} via {}",
+            peer_id.0,
+            endpoint.link
+        );
+        // remember what the connection negotiated so senders can size messages
+        store.touch(peer_id);
+        if let Some(peer) = store.peers.get_mut(peer_id) {
+            peer.mtu = Some(mtu);
+        }
+        Ok(())
+    }
+
+    // probes every endpoint of every known peer on reliable links we have, every interval,
+    // runs until the manager is dropped
+    pub fn start_probing(
+        self: &Arc<Self>,
+        local_id: PeerID,
+        config: ProbeConfig,
+    ) -> JoinHandle<()> {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let Some(manager) = manager.upgrade() else {
+                    return;
                 };
-                if let Ok(_) = send_result {
-                    log::info!("Successfully sent data to {} via {:?}", peer_id.0, lt);
-                    return Ok(());
-                } else {
-                    log::warn!("Failed to send data via {:?} to {}", lt, peer_id.0);
+                manager.probe_peers(&local_id, &config).await;
+                drop(manager);
+                tokio::time::sleep(config.interval).await;
+            }
+        })
+    }
+
+    // a single probing round, peers are probed concurrently and the results written to the store
+    pub async fn probe_peers(self: &Arc<Self>, local_id: &PeerID, config: &ProbeConfig) {
+        let (peers, registry) = {
+            let store = self.peer_store.lock().unwrap();
+            (store.get_all_peers(), store.registry.clone())
+        };
+        let mut probes = JoinSet::new();
+        for peer in peers {
+            for endpoint in peer.endpoints {
+                // an unacknowledged send says nothing about the round trip
+                let reliable = registry
+                    .capabilities(&endpoint.link)
+                    .is_some_and(|capabilities| capabilities.reliable);
+                if !reliable || !self.links.contains_key(&endpoint.link) {
+                    continue;
                 }
+                let manager = self.clone();
+                let (local_id, peer_id) = (local_id.clone(), peer.id.clone());
+                let config = *config;
+                probes.spawn(async move {
+                    let result = manager.probe(&local_id, &peer_id, &endpoint, &config).await;
+                    (peer_id, endpoint, result)
+                });
             }
         }
-        log::error!("No route available to peer {}", peer_id.0);
-        Err("No route available via any link".into())
+
+        while let Some(joined) = probes.join_next().await {
+            let Ok((peer_id, endpoint, result)) = joined else {
+                continue;
+            };
+            let mut store = self.peer_store.lock().unwrap();
+            // the current metrics, a send may have updated them while we probed
+            let Some(mut metrics) = store
+                .get_peer(peer_id.clone())
+                .and_then(|peer| peer.endpoint(&endpoint.link, &endpoint.address))
+                .map(|endpoint| endpoint.metrics.clone())
+            else {
+                continue;
+            };
+            match result {
+                Ok(rtt) => {
+                    metrics.record_rtt(rtt, config);
+                    store.touch(&peer_id);
+                }
+                Err(e) => {
+                    log::debug!(
+                        "Probe to {} via {} {} failed: {}",
+                        peer_id.0,
+                        endpoint.link,
+                        endpoint.address,
+                        e
+                    );
+                    metrics.record_loss(config);
+                }
+            }
+            store.update_metrics(&peer_id, &endpoint.link, &endpoint.address, metrics);
+        }
+    }
+
+    // times a reliable send of an empty control message, it completes once the peer
+    // acknowledged it, the dial has its own timeout so links that take seconds to set up a
+    // connection, like ble scanning for the peer, aren't rated lossy
+    async fn probe(
+        &self,
+        local_id: &PeerID,
+        peer_id: &PeerID,
+        endpoint: &LinkEndpoint,
+        config: &ProbeConfig,
+    ) -> Result<Duration, MeshError> {
+        let link = self
+            .links
+            .get(&endpoint.link)
+            .ok_or_else(|| MeshError::NoRoute(peer_id.clone()))?;
+        let connection =
+            tokio::time::timeout(config.dial_timeout, link.dial(&endpoint.address)).await??;
+        let message =
+            MeshMessage::new(MessageKind::Control, local_id, peer_id, Vec::new()).with_ttl(1);
+        let data = encode_message(&message);
+
+        let started = Instant::now();
+        let send = connection.send(&data);
+        tokio::time::timeout(config.timeout, send).await??;
+        Ok(started.elapsed())
+    }
+}
+
+// routing over every link, any peer in the store is a neighbour on whichever link reaches it
+#[async_trait::async_trait]
+impl NeighbourSender for Arc<MultiLinkManager> {
+    fn neighbours(&self) -> Vec<PeerID> {
+        self.peer_store
+            .lock()
+            .unwrap()
+            .peers
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    fn is_neighbour(&self, id: &PeerID) -> bool {
+        self.peer_store.lock().unwrap().peers.contains_key(id)
+    }
+
+    async fn send_to(&self, neighbour: &PeerID, message: &MeshMessage) -> Result<(), MeshError> {
+        self.send(neighbour, message).await
+    }
+
+    // links that stop accepting are reported to the manager's own health
+    fn incoming(&self, _health: &Health) -> mpsc::Receiver<MeshMessage> {
+        self.start_receiving()
     }
 }
@@ -0,0 +1,100 @@
+use super::registry::TransportCapabilities;
+use crate::types::peer::LinkType;
+use std::time::Duration;
+
+// what is known about one endpoint of a peer when a link has to be picked
+#[derive(Debug, Clone)]
+pub struct LinkCandidate {
+    pub link_type: LinkType,
+    pub address: String,
+    // measured srtt, the link's own latency estimate until a probe got through
+    pub rtt: Duration,
+    pub jitter: Duration,
+    // smoothed share of lost probes, 0.0 to 1.0
+    pub loss: f64,
+    pub mtu: usize,
+    // relative cost of a message, see Link::energy_cost
+    pub energy_cost: f64,
+    // sends and probes that failed in a row since the last success
+    pub failures: u32,
+    // position in the manager's priority list, breaks ties
+    pub priority: usize,
+    pub capabilities: TransportCapabilities,
+}
+
+// decides which link a peer is sent over, the manager asks it for a cost per candidate
+pub trait LinkPolicy: Send + Sync {
+    // lower is better
+    fn cost(&self, candidate: &LinkCandidate) -> f64;
+
+    // share by which a challenger has to undercut the link in use before we switch,
+    // keeps links with similar costs from flapping
+    fn hysteresis(&self) -> f64 {
+        0.0
+    }
+}
+
+// the first link in the priority list wins, ignores measurements
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriorityPolicy;
+
+impl LinkPolicy for PriorityPolicy {
+    fn cost(&self, candidate: &LinkCandidate) -> f64 {
+        candidate.priority as f64
+    }
+}
+
+// expected time to get a message through, plus penalties for energy, small packets and failures
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedPolicy {
+    // milliseconds one unit of energy cost is worth
+    pub energy_weight: f64,
+    // milliseconds added per fragment a 1 KiB message needs
+    pub fragment_weight: f64,
+    // milliseconds added per failure in a row
+    pub failure_penalty: f64,
+    pub hysteresis: f64,
+}
+
+impl Default for WeightedPolicy {
+    fn default() -> Self {
+        Self {
+            energy_weight: 10.0,
+            fragment_weight: 2.0,
+            failure_penalty: 250.0,
+            hysteresis: 0.2,
+        }
+    }
+}
+
+impl WeightedPolicy {
+    pub fn with_weights(mut self, energy: f64, fragment: f64, failure_penalty: f64) -> Self {
+        self.energy_weight = energy;
+        self.fragment_weight = fragment;
+        self.failure_penalty = failure_penalty;
+        self
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+}
+
+impl LinkPolicy for WeightedPolicy {
+    fn cost(&self, candidate: &LinkCandidate) -> f64 {
+        let rtt = candidate.rtt.as_secs_f64() * 1000.0 + candidate.jitter.as_secs_f64() * 1000.0;
+        // every lost attempt costs another round trip
+        let delivery = rtt / (1.0 - candidate.loss).max(0.01);
+        let fragments = (1024.0 / candidate.mtu.max(1) as f64).ceil();
+
+        delivery
+            + self.energy_weight * candidate.energy_cost
+            + self.fragment_weight * fragments
+            + self.failure_penalty * candidate.failures as f64
+    }
+
+    fn hysteresis(&self) -> f64 {
+        self.hysteresis
+    }
+}
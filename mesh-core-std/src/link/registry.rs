@@ -0,0 +1,186 @@
+use std::{collections::HashMap, fmt, net::SocketAddr, sync::Arc};
+
+use crate::{link::discovery::resolve_announced, types::peer::LinkType, MeshError};
+
+// what a transport offers, read by the manager and policies instead of matching on ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportCapabilities {
+    // a send returns once the peer has the message, so its duration is a round trip
+    pub reliable: bool,
+    // connections implement send_datagram
+    pub datagrams: bool,
+    // addresses are socket addresses
+    pub ip: bool,
+}
+
+impl Default for TransportCapabilities {
+    fn default() -> Self {
+        Self {
+            reliable: true,
+            datagrams: false,
+            ip: false,
+        }
+    }
+}
+
+// turns an address as announced or configured into the form the link dials,
+// seen_from is where the beacon carrying it came from, None outside discovery
+pub type AddressParser =
+    Arc<dyn Fn(&str, Option<SocketAddr>) -> Result<String, MeshError> + Send + Sync>;
+
+// describes one transport, links are registered under its id
+#[derive(Clone)]
+pub struct Transport {
+    pub id: LinkType,
+    pub capabilities: TransportCapabilities,
+    parser: AddressParser,
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("id", &self.id)
+            .field("capabilities", &self.capabilities)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Transport {
+    // takes any address that isn't blank until given a parser
+    pub fn new(id: LinkType) -> Self {
+        Self {
+            id,
+            capabilities: TransportCapabilities::default(),
+            parser: Arc::new(|address, _| {
+                let address = address.trim();
+                if address.is_empty() {
+                    return Err(MeshError::InvalidAddress("empty address".to_string()));
+                }
+                Ok(address.to_string())
+            }),
+        }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: TransportCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_parser(
+        mut self,
+        parser: impl Fn(&str, Option<SocketAddr>) -> Result<String, MeshError> + Send + Sync + 'static,
+    ) -> Self {
+        self.parser = Arc::new(parser);
+        self
+    }
+
+    pub fn parse_address(
+        &self,
+        address: &str,
+        seen_from: Option<SocketAddr>,
+    ) -> Result<String, MeshError> {
+        (self.parser)(address, seen_from).map_err(|e| {
+            MeshError::InvalidAddress(format!("{} address {:?}: {}", self.id, address, e))
+        })
+    }
+
+    // quic over udp
+    pub fn wifi() -> Self {
+        Self::new(LinkType::WIFI)
+            .with_capabilities(TransportCapabilities {
+                reliable: true,
+                datagrams: true,
+                ip: true,
+            })
+            .with_parser(parse_socket_address)
+    }
+
+    // tls over tcp, the fallback where udp is blocked
+    pub fn tcp() -> Self {
+        Self::new(LinkType::TCP)
+            .with_capabilities(TransportCapabilities {
+                reliable: true,
+                datagrams: false,
+                ip: true,
+            })
+            .with_parser(parse_socket_address)
+    }
+
+    // gatt writes with response, addresses are whatever the platform names peripherals by
+    pub fn ble() -> Self {
+        Self::new(LinkType::BLE)
+    }
+}
+
+// wildcard and link-local addresses are resolved against the beacon's source
+fn parse_socket_address(address: &str, seen_from: Option<SocketAddr>) -> Result<String, MeshError> {
+    let addr = address.parse::<SocketAddr>()?;
+    Ok(match seen_from {
+        Some(src) => resolve_announced(addr, src),
+        None => addr,
+    }
+    .to_string())
+}
+
+// the transports a node knows, endpoints on anything else are dropped
+#[derive(Debug, Clone)]
+pub struct TransportRegistry {
+    transports: HashMap<LinkType, Transport>,
+}
+
+impl Default for TransportRegistry {
+    fn default() -> Self {
+        Self::empty()
+            .with_transport(Transport::wifi())
+            .with_transport(Transport::ble())
+            .with_transport(Transport::tcp())
+    }
+}
+
+impl TransportRegistry {
+    // not even the built-in transports
+    pub fn empty() -> Self {
+        Self {
+            transports: HashMap::new(),
+        }
+    }
+
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.register(transport);
+        self
+    }
+
+    // adds a transport, returns the one it replaced
+    pub fn register(&mut self, transport: Transport) -> Option<Transport> {
+        log::debug!("Registering transport {}", transport.id);
+        self.transports.insert(transport.id.clone(), transport)
+    }
+
+    pub fn get(&self, id: &LinkType) -> Option<&Transport> {
+        self.transports.get(id)
+    }
+
+    pub fn contains(&self, id: &LinkType) -> bool {
+        self.transports.contains_key(id)
+    }
+
+    pub fn capabilities(&self, id: &LinkType) -> Option<TransportCapabilities> {
+        self.get(id).map(|transport| transport.capabilities)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &LinkType> {
+        self.transports.keys()
+    }
+
+    // the address in canonical form, errors for unknown transports and bad addresses
+    pub fn parse_address(
+        &self,
+        id: &LinkType,
+        address: &str,
+        seen_from: Option<SocketAddr>,
+    ) -> Result<String, MeshError> {
+        self.get(id)
+            .ok_or_else(|| MeshError::InvalidAddress(format!("unknown transport {}", id)))?
+            .parse_address(address, seen_from)
+    }
+}
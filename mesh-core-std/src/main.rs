@@ -1,9 +1,16 @@
 use clap::Parser;
-use mesh_core::mesh::MeshMessage;
+use mesh_core::node::MeshNode;
+use mesh_core::reload::ConfigReloader;
+use mesh_core::tcp::tcp_impl::TcpTlsLink;
 use mesh_core::types::args::Args;
-use mesh_core::utils::{generate_certificate_authority, generate_node_certs};
-use mesh_core::{link::link_trait::Link, wifi::wifi_impl::WifiQuicLink};
-use prost::Message;
+use mesh_core::types::ble_types::BleLink;
+use mesh_core::types::config::Config;
+use mesh_core::types::identity::NodeIdentity;
+use mesh_core::types::peer::LinkType;
+use mesh_core::utils::generate_certificate_authority;
+use mesh_core::{wifi::wifi_impl::WifiQuicLink, MeshError};
+use std::sync::Arc;
+use tokio::sync::mpsc;
 use tokio::time::{sleep, Duration};
 
 #[allow(dead_code)]
@@ -15,102 +22,109 @@ async fn task(_name: &str, interval: u64) {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+async fn main() -> Result<(), MeshError> {
     let arguments = Args::parse();
 
     match log4rs::init_file(&arguments.log_config, Default::default()) {
         Ok(()) => log::info!("Logger successfully initialized for Mesh Core!"),
         Err(e) => log::error!("Logger couldn't be initialized for Mesh Core: {}", e),
     }
-    log::info!("Mesh Core Initialized!");
 
-    let (ca_cert, ca_issuer) = generate_certificate_authority();
-    let (_node1_cert, _node1_key) = generate_node_certs(&ca_issuer, "Node 1");
-    let (_node2_cert, _node2_key) = generate_node_certs(&ca_issuer, "Node 2");
+    let config = Config::from_args(&arguments)?;
+    let name = config.node.name.as_str();
+    log::info!(
+        "Mesh Core {} {} starting as {}",
+        config.application.name,
+        config.application.version,
+        name
+    );
+
+    let (ca, issuer) = match config.certificates.load()? {
+        Some(ca) => ca,
+        None => {
+            log::warn!("No CA configured, only nodes in this process will trust each other");
+            let (ca_cert, issuer) = generate_certificate_authority();
+            (ca_cert.der().clone().into_owned(), issuer)
+        }
+    };
+    let mut trusted = vec![ca.clone()];
+    trusted.extend(config.certificates.load_trusted()?);
 
-    for (i, port) in [5000, 5001, 5002].iter().enumerate() {
-        println!("i {}, port {}", i, port);
+    let identity = Arc::new(NodeIdentity::new(&issuer, name));
+    let mut builder =
+        MeshNode::builder(identity.clone(), ca).with_liveness(config.liveness.liveness_config());
+    for link_type in config.priority() {
+        builder = match link_type.as_str() {
+            "wifi" => {
+                let link = WifiQuicLink::new(&config.links.wifi.listen, &trusted, name, &issuer)?;
+                let address = link.pool.local_addr.to_string();
+                builder
+                    .with_link(LinkType::WIFI, Box::new(link))
+                    .with_address(LinkType::WIFI, address)
+            }
+            "tcp" => {
+                let link = TcpTlsLink::new(&config.links.tcp.listen, &trusted, name, &issuer)?;
+                let address = link.local_addr.to_string();
+                builder
+                    .with_link(LinkType::TCP, Box::new(link))
+                    .with_address(LinkType::TCP, address)
+            }
+            _ => {
+                let (service, characteristic) = config.links.ble.uuids()?;
+                let link = BleLink::new(service, characteristic).await?;
+                builder.with_link(LinkType::BLE, Box::new(link))
+            }
+        };
+    }
+    for peer in config.bootstrap_peers()? {
+        builder = builder.with_peer(peer);
+    }
+    if config.discovery.enabled {
+        builder = builder.with_discovery(config.discovery.discovery_config());
+    }
+    if let Some(strategy) = config.routing.strategy(identity.id.clone()) {
+        builder = builder.with_routing(strategy, config.routing.interval());
     }
 
-    let node1 = WifiQuicLink::new(
-        "127.0.0.1:8000",
-        &[ca_cert.der().clone().into_owned()],
-        "node1",
-        &ca_issuer,
-    )?;
-    let n1 = node1.clone();
-    let node2 = WifiQuicLink::new(
-        "127.0.0.1:8001",
-        &[ca_cert.der().clone().into_owned()],
-        "node2",
-        &ca_issuer,
-    )?;
-    let n2 = node2.clone();
+    let node = builder.start().await?;
+    log::info!("Mesh Core Initialized!");
 
-    // node1 server
+    let mut messages = node.subscribe();
     tokio::spawn(async move {
-        let connection = node1.clone().accept().await.unwrap();
-        loop {
-            match connection.receive().await {
-                Ok(data) => {
-                    log::info!("Raw bytes received: {:?}", data);
-                    let msg = MeshMessage::decode(&data[..]).unwrap();
-                    log::info!("Decoded message: {:?}", msg);
-                }
-                Err(e) => {
-                    log::error!("Error while receiving message from client: {}", e);
-                }
-            }
+        while let Ok(message) = messages.recv().await {
+            log::info!("Decoded message: {:?}", message);
         }
     });
 
-    tokio::spawn(async move {
-        let connection = node2.clone().accept().await.unwrap();
-        loop {
-            match connection.receive().await {
-                Ok(data) => {
-                    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
-                    let slice = &data[..end];
-                    let msg = MeshMessage::decode(&slice[..]).unwrap();
-                    log::info!("Decoded message: {:?}", msg);
-                }
-                Err(e) => {
-                    log::error!("Error while receiving message from client: {}", e);
-                }
+    // SIGHUP or a write to the config file reloads it
+    let (reload_tx, mut reload_rx) = mpsc::channel(1);
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let _ = reload_tx.try_send(());
             }
-        }
-    });
-
-    tokio::time::sleep(Duration::from_millis(100)).await;
-
-    // connecting node1 and node2
-    let node1_connection = n1.dial("127.0.0.1:8001").await?;
-    let message = MeshMessage {
-        sender: "Node1".to_string(),
-        content: "Hello Node2".to_string(),
-    };
-    let mut buf = Vec::new();
-    message.encode(&mut buf)?;
-    // send data from node1 to node2
-    match node1_connection.send(&buf).await {
-        Ok(_) => log::info!("Data sent successfully: {:?}", buf),
-        Err(e) => log::error!("Error: {}", e),
+        });
     }
+    let mut poll = tokio::time::interval(config.reload.interval());
+    let watch = config.reload.watch;
+    let mut reloader = ConfigReloader::new(arguments, config)?;
 
-    // connecting node1 and node2
-    let node2_connection = n2.dial("127.0.0.1:8000").await?;
-
-    let message = MeshMessage {
-        sender: "Node2".to_string(),
-        content: "Hello Node1".to_string(),
-    };
-    let mut buf = Vec::new();
-    message.encode(&mut buf)?;
-    // send data from node1 to node2
-    match node2_connection.send(&buf).await {
-        Ok(_) => log::info!("Data sent successfully: {:?}", buf),
-        Err(e) => log::error!("Error: {}", e),
+    loop {
+        let reload = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            Some(()) = reload_rx.recv() => true,
+            _ = poll.tick(), if watch => reloader.changed(),
+        };
+        if reload {
+            if let Err(e) = reloader.reload(&node) {
+                log::error!("Config reload rejected, nothing changed: {}", e);
+            }
+        }
     }
-
+    log::info!("Shutting down");
+    node.shutdown().await;
     Ok(())
 }
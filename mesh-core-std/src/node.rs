@@ -0,0 +1,344 @@
+use crate::{
+    link::{
+        discovery::{broadcast, listener},
+        link_trait::Link,
+        multilink::MultiLinkManager,
+        policy::LinkPolicy,
+        registry::Transport,
+    },
+    mesh::{LinkAddress, MeshMessage, MessageKind},
+    types::{
+        discovery::{DiscoveryConfig, DiscoveryHandle},
+        health::Health,
+        identity::NodeIdentity,
+        peer::{LinkType, LivenessConfig, PeerID, PeerInfo, PeerStore},
+        probe::ProbeConfig,
+        routing::{RoutingLayer, RoutingStrategy},
+    },
+    MeshError,
+};
+use quinn::rustls::pki_types::CertificateDer;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast as channel, mpsc},
+    task::JoinHandle,
+};
+use webpki::anchor_from_trusted_cert;
+
+// what a node is made of, nothing runs until start
+pub struct MeshNodeBuilder {
+    identity: Arc<NodeIdentity>,
+    ca: CertificateDer<'static>,
+    links: HashMap<LinkType, Box<dyn Link + Send + Sync>>,
+    priority: Vec<LinkType>,
+    transports: Vec<(Transport, Box<dyn Link + Send + Sync>)>,
+    addresses: Vec<LinkAddress>,
+    capabilities: Vec<String>,
+    peers: Vec<PeerInfo>,
+    discovery: Option<DiscoveryConfig>,
+    routing: Option<(Box<dyn RoutingStrategy>, Duration)>,
+    probing: Option<ProbeConfig>,
+    policy: Option<Box<dyn LinkPolicy>>,
+    liveness: LivenessConfig,
+    health: Health,
+}
+
+impl MeshNodeBuilder {
+    // links are tried in the order they are added, unless a policy says otherwise
+    pub fn with_link(mut self, link_type: LinkType, link: Box<dyn Link + Send + Sync>) -> Self {
+        if !self.priority.contains(&link_type) {
+            self.priority.push(link_type.clone());
+        }
+        self.links.insert(link_type, link);
+        self
+    }
+
+    // a link over a transport the crate doesn't know, see MultiLinkManager::with_transport
+    pub fn with_transport(
+        mut self,
+        transport: Transport,
+        link: Box<dyn Link + Send + Sync>,
+    ) -> Self {
+        self.transports.push((transport, link));
+        self
+    }
+
+    // an address the node is announced at, as the link's dial on other nodes takes it
+    pub fn with_address(mut self, link_type: LinkType, address: impl Into<String>) -> Self {
+        self.addresses
+            .push(LinkAddress::new(link_type.as_str(), &address.into()));
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Vec<String>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    // a peer known up front, for networks multicast doesn't reach
+    pub fn with_peer(mut self, peer: PeerInfo) -> Self {
+        self.peers.push(peer);
+        self
+    }
+
+    pub fn with_discovery(mut self, config: DiscoveryConfig) -> Self {
+        self.discovery = Some(config);
+        self
+    }
+
+    // relays messages for peers that aren't direct neighbours, the strategy is ticked every interval
+    pub fn with_routing(mut self, strategy: Box<dyn RoutingStrategy>, interval: Duration) -> Self {
+        self.routing = Some((strategy, interval));
+        self
+    }
+
+    pub fn with_probing(mut self, config: ProbeConfig) -> Self {
+        self.probing = Some(config);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: Box<dyn LinkPolicy>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn with_liveness(mut self, liveness: LivenessConfig) -> Self {
+        self.liveness = liveness;
+        self
+    }
+
+    // shared with whatever else the application watches, the node reports its tasks here
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    // starts accepting on every link, discovery, probing and routing as configured
+    pub async fn start(self) -> Result<MeshNode, MeshError> {
+        let id = self.identity.id.clone();
+        let peer_store = Arc::new(Mutex::new(
+            PeerStore::default().with_liveness(self.liveness),
+        ));
+
+        let mut manager = MultiLinkManager::new(self.links, vec![], self.priority)
+            .with_peer_store(peer_store.clone())
+            .with_health(self.health.clone());
+        for (transport, link) in self.transports {
+            manager = manager.with_transport(transport, link);
+        }
+        if let Some(policy) = self.policy {
+            manager = manager.with_policy(policy);
+        }
+        let manager = Arc::new(manager);
+
+        {
+            let mut store = peer_store.lock().unwrap();
+            for peer in self.peers {
+                store.update_store(peer);
+            }
+        }
+
+        // discovery first, so a node that can't bind its sockets doesn't start half way
+        let mut discovery = Vec::new();
+        if let Some(config) = self.discovery {
+            let config = config
+                .with_health(self.health.clone())
+                .with_local_id(self.identity.id.clone());
+            discovery.push(listener(&config, peer_store.clone(), self.ca.clone()).await?);
+            match broadcast(&config, self.identity, self.addresses, self.capabilities).await {
+                Ok(handle) => discovery.push(handle),
+                Err(e) => {
+                    for handle in discovery {
+                        handle.stop().await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let messages = channel::channel(256).0;
+        let mut tasks = vec![manager.watch_peers(), PeerStore::spawn_reaper(&peer_store)];
+        let routing = match self.routing {
+            Some((strategy, interval)) => {
+                let routing = RoutingLayer::with_sender(
+                    id.clone(),
+                    manager.clone(),
+                    peer_store.clone(),
+                    strategy,
+                )
+                .with_health(self.health.clone());
+                let inbox = routing.start(interval);
+                tasks.push(tokio::spawn(deliver(id.clone(), inbox, messages.clone())));
+                Some(routing)
+            }
+            None => {
+                let inbox = manager.start_receiving();
+                tasks.push(tokio::spawn(deliver(id.clone(), inbox, messages.clone())));
+                None
+            }
+        };
+        if let Some(config) = self.probing {
+            tasks.push(manager.start_probing(id.clone(), config));
+        }
+
+        log::info!("Mesh node {} started", id.0);
+        Ok(MeshNode {
+            id,
+            peer_store,
+            manager,
+            health: self.health,
+            ca: self.ca,
+            routing,
+            messages,
+            discovery,
+            tasks,
+        })
+    }
+}
+
+// a running node, links, discovery, the peer store and routing wired together
+pub struct MeshNode {
+    pub id: PeerID,
+    pub peer_store: Arc<Mutex<PeerStore>>,
+    pub manager: Arc<MultiLinkManager>,
+    pub health: Health,
+    ca: CertificateDer<'static>,
+    // none when the node only talks to its neighbours
+    routing: Option<RoutingLayer<Arc<MultiLinkManager>>>,
+    messages: channel::Sender<MeshMessage>,
+    discovery: Vec<DiscoveryHandle>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MeshNode {
+    // the node is named after the identity's certificate, peers are trusted if theirs chain to ca
+    pub fn builder(identity: Arc<NodeIdentity>, ca: CertificateDer<'static>) -> MeshNodeBuilder {
+        MeshNodeBuilder {
+            identity,
+            ca,
+            links: HashMap::new(),
+            priority: Vec::new(),
+            transports: Vec::new(),
+            addresses: Vec::new(),
+            capabilities: Vec::new(),
+            peers: Vec::new(),
+            discovery: None,
+            routing: None,
+            probing: None,
+            policy: None,
+            liveness: LivenessConfig::default(),
+            health: Health::default(),
+        }
+    }
+
+    // straight to the peer if it is a neighbour, relayed when a routing strategy knows a way
+    pub async fn send(&self, peer: &PeerID, data: &[u8]) -> Result<(), MeshError> {
+        match &self.routing {
+            Some(routing) => routing.send(peer.clone(), data).await,
+            None => {
+                let message = MeshMessage::data(&self.id, peer, data.to_vec());
+                self.manager.send(peer, &message).await
+            }
+        }
+    }
+
+    // data messages addressed to this node from now on, a subscriber that falls behind
+    // by more than the channel holds skips ahead
+    pub fn subscribe(&self) -> channel::Receiver<MeshMessage> {
+        self.messages.subscribe()
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peer_store.lock().unwrap().get_all_peers()
+    }
+
+    // what can change while the node runs, the rest is fixed at start
+
+    // a neighbour to dial without waiting for discovery, merged with what is known of it
+    pub fn add_peer(&self, peer: PeerInfo) {
+        self.peer_store.lock().unwrap().update_store(peer);
+    }
+
+    // see MultiLinkManager::set_priority
+    pub fn set_priority(&self, priority: Vec<LinkType>) {
+        self.manager.set_priority(priority);
+    }
+
+    // used from the next reap on
+    pub fn set_liveness(&self, liveness: LivenessConfig) {
+        self.peer_store.lock().unwrap().liveness = liveness;
+    }
+
+    // a node without discovery has nothing to change
+    pub fn set_discovery_interval(&self, interval: Duration, jitter: Duration) {
+        for handle in &self.discovery {
+            handle.set_interval(interval, jitter);
+        }
+    }
+
+    // cas besides the mesh ca whose nodes are trusted by new dials and by discovery,
+    // connections already up are kept, nothing changes if one of them isn't a usable anchor
+    pub fn set_trusted(&self, trusted: Vec<CertificateDer<'static>>) -> Result<(), MeshError> {
+        for ca in &trusted {
+            anchor_from_trusted_cert(ca)?;
+        }
+        let anchors: Vec<CertificateDer<'static>> = std::iter::once(self.ca.clone())
+            .chain(trusted.iter().cloned())
+            .collect();
+        for link in self.manager.links.values() {
+            link.set_trusted(&anchors)?;
+        }
+        for handle in &self.discovery {
+            handle.set_trusted(trusted.clone());
+        }
+        Ok(())
+    }
+
+    // stops announcing first so peers stop dialing us, then everything else,
+    // links close once the last connection is dropped
+    pub async fn shutdown(mut self) {
+        for handle in std::mem::take(&mut self.discovery) {
+            handle.stop().await;
+        }
+        let tasks = std::mem::take(&mut self.tasks);
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        log::info!("Mesh node {} shut down", self.id.0);
+    }
+}
+
+// dropping a node without shutdown still stops it, just without waiting
+impl Drop for MeshNode {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// hands what is addressed to us to subscribers, relaying is up to the routing layer
+async fn deliver(
+    id: PeerID,
+    mut inbox: mpsc::Receiver<MeshMessage>,
+    messages: channel::Sender<MeshMessage>,
+) {
+    while let Some(message) = inbox.recv().await {
+        if message.kind() == MessageKind::Data && message.destination == id.0 {
+            let _ = messages.send(message);
+        } else {
+            log::debug!(
+                "Dropping {:?} message for {}: routing is off",
+                message.kind(),
+                message.destination
+            );
+        }
+    }
+}
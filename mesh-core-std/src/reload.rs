@@ -0,0 +1,163 @@
+use crate::{
+    node::MeshNode,
+    types::{
+        args::Args,
+        config::{Config, PeerSettings},
+        peer::LinkType,
+    },
+    MeshError,
+};
+use quinn::rustls::pki_types::CertificateDer;
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+// what a reload changed on the running node and what it left alone
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    // settings the node runs with from now on
+    pub applied: Vec<String>,
+    // sections that changed in the file but are fixed while the node runs,
+    // the node keeps the values it was started with
+    pub needs_restart: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.needs_restart.is_empty()
+    }
+}
+
+// reads the config of a running node again and applies what can change without a restart:
+// bootstrap peers, link priority, discovery interval and jitter, liveness and trusted cas
+pub struct ConfigReloader {
+    args: Args,
+    // what the node runs with, settings that need a restart keep their values from start
+    running: Config,
+    // contents of certificates.trusted, a file replaced under the same name counts as a change
+    trusted: Vec<CertificateDer<'static>>,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigReloader {
+    // config is what the node was started with, after the flags in args were applied
+    pub fn new(args: Args, config: Config) -> Result<Self, MeshError> {
+        Ok(Self {
+            modified: modified(&args.config),
+            trusted: config.certificates.load_trusted()?,
+            args,
+            running: config,
+        })
+    }
+
+    pub fn running(&self) -> &Config {
+        &self.running
+    }
+
+    // whether the file was written since it was last read, a missing file never changed
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.args.config);
+        let changed = modified.is_some() && modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+
+    // the file again with the same flags on top, nothing is applied unless all of it is valid
+    pub fn reload(&mut self, node: &MeshNode) -> Result<ReloadReport, MeshError> {
+        self.modified = modified(&self.args.config);
+        let config = Config::from_args(&self.args)?;
+        self.apply(node, config)
+    }
+
+    pub fn apply(&mut self, node: &MeshNode, config: Config) -> Result<ReloadReport, MeshError> {
+        config.validate()?;
+        // whatever can fail comes before anything is changed
+        let trusted = config.certificates.load_trusted()?;
+        let peers = config.bootstrap_peers()?;
+
+        let mut report = ReloadReport::default();
+        let mut running = self.running.clone();
+
+        if trusted != self.trusted {
+            node.set_trusted(trusted.clone())?;
+            self.trusted = trusted;
+            report.applied.push("certificates.trusted".to_string());
+        }
+        running.certificates.trusted = config.certificates.trusted.clone();
+
+        if config.peers != running.peers {
+            // peers whose entries changed, ones taken out of the file are left to liveness
+            let entries = |peers: &[PeerSettings], name: &str| -> Vec<PeerSettings> {
+                peers.iter().filter(|p| p.name == name).cloned().collect()
+            };
+            for peer in peers {
+                if entries(&config.peers, &peer.id.0) != entries(&running.peers, &peer.id.0) {
+                    node.add_peer(peer);
+                }
+            }
+            running.peers = config.peers.clone();
+            report.applied.push("peers".to_string());
+        }
+
+        if config.links.priority != running.links.priority {
+            let priority = config.links.priority.iter().cloned().map(LinkType::new);
+            node.set_priority(priority.collect());
+            running.links.priority = config.links.priority.clone();
+            report.applied.push("links.priority".to_string());
+        }
+
+        let (discovery, new) = (&running.discovery, &config.discovery);
+        if (discovery.interval_ms, discovery.jitter_ms) != (new.interval_ms, new.jitter_ms) {
+            node.set_discovery_interval(
+                Duration::from_millis(new.interval_ms),
+                Duration::from_millis(new.jitter_ms),
+            );
+            running.discovery.interval_ms = new.interval_ms;
+            running.discovery.jitter_ms = new.jitter_ms;
+            report
+                .applied
+                .push("discovery.interval_ms, discovery.jitter_ms".to_string());
+        }
+
+        if config.liveness != running.liveness {
+            node.set_liveness(config.liveness.liveness_config());
+            running.liveness = config.liveness.clone();
+            report.applied.push("liveness".to_string());
+        }
+
+        // what's still different can only be what a restart is needed for
+        let sections = [
+            ("application", running.application != config.application),
+            ("node", running.node != config.node),
+            ("certificates", running.certificates != config.certificates),
+            ("links.wifi", running.links.wifi != config.links.wifi),
+            ("links.tcp", running.links.tcp != config.links.tcp),
+            ("links.ble", running.links.ble != config.links.ble),
+            ("discovery", running.discovery != config.discovery),
+            ("routing", running.routing != config.routing),
+            ("reload", running.reload != config.reload),
+        ];
+        report.needs_restart = sections
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(section, _)| section.to_string())
+            .collect();
+
+        if !report.applied.is_empty() {
+            log::info!("Config reloaded: {}", report.applied.join(", "));
+        }
+        if !report.needs_restart.is_empty() {
+            log::warn!(
+                "Config changes to {} only take effect after a restart",
+                report.needs_restart.join(", ")
+            );
+        }
+        self.running = running;
+        Ok(report)
+    }
+}
+
+fn modified(path: impl AsRef<Path>) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,43 @@
+use rcgen::{Issuer, KeyPair};
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    self,
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer},
+    ClientConfig, ServerConfig,
+};
+
+use crate::{utils::generate_node_certs, MeshError};
+
+// same certificates as the quic endpoint: a node certificate issued by the mesh CA for
+// the server side, the CA and our own certificate trusted on the client side
+pub fn make_tls_configs(
+    trusted_peers: &[CertificateDer<'static>],
+    node_name: &str,
+    issuer: &Issuer<'static, KeyPair>,
+) -> Result<(Arc<ServerConfig>, Arc<ClientConfig>), MeshError> {
+    let (node_cert, key_pair) = generate_node_certs(issuer, node_name);
+    let certificate_der = node_cert.der().clone().into_owned();
+    let private_key = PrivatePkcs8KeyDer::from(key_pair.serialize_der());
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate_der.clone()], private_key.into())?;
+
+    let mut trusted = trusted_peers.to_vec();
+    trusted.push(certificate_der);
+    Ok((Arc::new(server_config), make_client_config(&trusted)?))
+}
+
+// client side only, what TcpTlsLink::set_trusted swaps in
+pub fn make_client_config(
+    trusted_peers: &[CertificateDer<'static>],
+) -> Result<Arc<ClientConfig>, MeshError> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in trusted_peers {
+        roots.add(cert.clone())?;
+    }
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(client_config))
+}
@@ -0,0 +1,494 @@
+use crate::{
+    link::{
+        framing::{read_frame, write_frame, DEFAULT_MAX_FRAME_SIZE},
+        link_trait::{Link, LinkConnection},
+    },
+    types::tcp_tls::{
+        DialedConnection, TcpTlsLinkConnection, TlsWriter, DEFAULT_KEEPALIVE_INTERVAL,
+        KEEPALIVE_MISSES, MAX_UNACKED_FRAMES,
+    },
+    wifi::{pool::canonical, wifi_impl::parse_address},
+    MeshError,
+};
+use rcgen::{Issuer, KeyPair};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, RwLock, Weak},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWriteExt, ReadHalf},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot, watch, Mutex, Notify, Semaphore},
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector, TlsStream,
+};
+
+use super::configure::{make_client_config, make_tls_configs};
+
+// payload that fits one segment on common paths once tcp, ip and tls headers are taken off
+pub const TCP_TLS_MTU: usize = 1400;
+
+// first byte of every frame, data frames are acknowledged by the receiver
+const FRAME_DATA: u8 = 0;
+const FRAME_ACK: u8 = 1;
+const FRAME_PING: u8 = 2;
+
+// tls handshakes finished and not yet picked up by accept
+type Handshaken = (TlsStream<TcpStream>, SocketAddr);
+
+// fallback for networks that drop udp, slower to set up than quic and prone to head of
+// line blocking, so it should come after wi-fi in the manager's priority list
+#[derive(Debug, Clone)]
+pub struct TcpTlsLink {
+    pub local_addr: SocketAddr,
+    pub max_frame_size: usize,
+    pub keepalive: Duration,
+    listener: Arc<TcpListener>,
+    server_config: Arc<ServerConfig>,
+    // shared by the clones, replaced by set_trusted
+    client_config: Arc<RwLock<Arc<ClientConfig>>>,
+    // connections we dialed, reused by later dials of the same address and handed out by
+    // accept so what the peer sends back gets read too
+    connections: Arc<std::sync::Mutex<HashMap<SocketAddr, DialedConnection>>>,
+    // one dial at a time per address so concurrent senders share a single handshake
+    dial_locks: Arc<std::sync::Mutex<HashMap<SocketAddr, Arc<Mutex<()>>>>>,
+    // raised whenever a dialed connection was added
+    dialed: Arc<Notify>,
+    // handshakes run on their own tasks so a slow peer doesn't hold up the others
+    handshakes: mpsc::Sender<Handshaken>,
+    handshaken: Arc<Mutex<mpsc::Receiver<Handshaken>>>,
+}
+
+impl TcpTlsLink {
+    pub fn new(
+        addr: &str,
+        trusted_peers: &[CertificateDer<'static>],
+        node_name: &str,
+        issuer: &Issuer<'static, KeyPair>,
+    ) -> Result<Self, MeshError> {
+        let (server_config, client_config) = make_tls_configs(trusted_peers, node_name, issuer)?;
+        let listener = std::net::TcpListener::bind(addr.parse::<SocketAddr>()?)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let (handshakes, handshaken) = mpsc::channel(16);
+
+        Ok(Self {
+            local_addr: listener.local_addr()?,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            keepalive: DEFAULT_KEEPALIVE_INTERVAL,
+            listener: Arc::new(listener),
+            server_config,
+            client_config: Arc::new(RwLock::new(client_config)),
+            connections: Default::default(),
+            dial_locks: Default::default(),
+            dialed: Default::default(),
+            handshakes,
+            handshaken: Arc::new(Mutex::new(handshaken)),
+        })
+    }
+
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    // how often an idle connection is pinged, also decides when a silent peer is given up on
+    pub fn with_keepalive(mut self, keepalive: Duration) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    fn pooled(&self, addr: &SocketAddr) -> Option<TcpTlsLinkConnection> {
+        let mut connections = self.connections.lock().unwrap();
+        match connections.get(addr) {
+            Some(dialed) if !dialed.connection.is_closed() => Some(dialed.connection.clone()),
+            Some(_) => {
+                connections.remove(addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // a connection we dialed that wasn't handed out yet, waits for the next dial if there is none
+    async fn next_dialed(&self) -> TcpTlsLinkConnection {
+        loop {
+            let waiting = self
+                .connections
+                .lock()
+                .unwrap()
+                .values_mut()
+                .find(|dialed| !dialed.accepted && !dialed.connection.is_closed())
+                .map(|dialed| {
+                    dialed.accepted = true;
+                    dialed.connection.clone()
+                });
+            if let Some(connection) = waiting {
+                return connection;
+            }
+            self.dialed.notified().await;
+        }
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<TcpTlsLinkConnection, MeshError> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| MeshError::DialFailed {
+                address: addr.to_string(),
+                reason: e.to_string(),
+            })?;
+        stream.set_nodelay(true)?;
+        let client_config = self.client_config.read().unwrap().clone();
+        let connector = TlsConnector::from(client_config);
+        let server_name = ServerName::try_from("localhost")
+            .map_err(|e| MeshError::InvalidAddress(e.to_string()))?;
+        // tokio-rustls hands back certificate and protocol failures as io errors
+        let stream = connector
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| MeshError::Handshake(e.to_string()))?;
+        log::info!("TLS connection established to remote peer {}", addr);
+        Ok(TcpTlsLinkConnection::new(
+            stream.into(),
+            addr,
+            self.max_frame_size,
+            self.keepalive,
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Link for TcpTlsLink {
+    // reuses a connection we dialed to the address before, dialing only if there is none
+    async fn dial(
+        &self,
+        address: &str,
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        let addr = canonical(&parse_address(address)?);
+        if let Some(connection) = self.pooled(&addr) {
+            return Ok(Box::new(connection));
+        }
+
+        let lock = self
+            .dial_locks
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_default()
+            .clone();
+        let _dialing = lock.lock().await;
+        // someone else may have finished a dial while we waited
+        if let Some(connection) = self.pooled(&addr) {
+            return Ok(Box::new(connection));
+        }
+        let connection = self.connect(addr).await?;
+        self.connections.lock().unwrap().insert(
+            addr,
+            DialedConnection {
+                connection: connection.clone(),
+                accepted: false,
+            },
+        );
+        self.dialed.notify_one();
+        Ok(Box::new(connection))
+    }
+
+    // yields every new connection, whether the peer dialed us or we dialed the peer,
+    // handshakes that fail or stall past the keepalive interval are logged and skipped,
+    // and so are dialed connections that were closed or forgotten in the meantime
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        loop {
+            let (stream, addr) = tokio::select! {
+                connection = self.next_dialed() => return Ok(Box::new(connection)),
+                handshaken = async { self.handshaken.lock().await.recv().await } => {
+                    // we hold a sender ourselves, the channel never closes
+                    let Some((stream, addr)) = handshaken else { continue };
+                    log::info!("TLS connection established to remote peer {}", addr);
+                    return Ok(Box::new(TcpTlsLinkConnection::new(
+                        stream,
+                        addr,
+                        self.max_frame_size,
+                        self.keepalive,
+                    )));
+                }
+                accepted = self.listener.accept() => accepted?,
+            };
+            stream.set_nodelay(true)?;
+
+            let acceptor = TlsAcceptor::from(self.server_config.clone());
+            let (handshakes, keepalive) = (self.handshakes.clone(), self.keepalive);
+            tokio::spawn(async move {
+                match tokio::time::timeout(keepalive, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = handshakes.send((stream.into(), addr)).await;
+                    }
+                    Ok(Err(e)) => log::warn!("TLS handshake with {} failed: {}", addr, e),
+                    Err(_) => log::warn!("TLS handshake with {} timed out", addr),
+                }
+            });
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        TCP_TLS_MTU
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_millis(30)
+    }
+
+    // closes the connection we dialed so a dead peer doesn't hold on to it
+    fn forget(&self, address: &str) {
+        let Ok(addr) = parse_address(address) else {
+            return;
+        };
+        if let Some(dialed) = self.connections.lock().unwrap().remove(&canonical(&addr)) {
+            dialed.connection.close();
+        }
+    }
+
+    fn set_trusted(&self, trusted: &[CertificateDer<'static>]) -> Result<(), MeshError> {
+        *self.client_config.write().unwrap() = make_client_config(trusted)?;
+        Ok(())
+    }
+}
+
+// one frame of the given kind, flushed so it leaves right away
+async fn write_kind(
+    writer: &mut TlsWriter,
+    kind: u8,
+    data: &[u8],
+    max_frame_size: usize,
+) -> Result<(), MeshError> {
+    let mut frame = Vec::with_capacity(data.len() + 1);
+    frame.push(kind);
+    frame.extend_from_slice(data);
+    write_frame(writer, &frame, max_frame_size + 1).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+impl TcpTlsLinkConnection {
+    // starts reading frames and sending keepalives in the background
+    pub fn new(
+        stream: TlsStream<TcpStream>,
+        remote_address: SocketAddr,
+        max_frame_size: usize,
+        keepalive: Duration,
+    ) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, rx) = mpsc::channel(64);
+        let connection = Self {
+            remote_address,
+            max_frame_size,
+            writer: Arc::new(Mutex::new(writer)),
+            incoming: Arc::new(Mutex::new(rx)),
+            pending_acks: Default::default(),
+            in_flight: Arc::new(Semaphore::new(MAX_UNACKED_FRAMES)),
+            closed: Arc::new(watch::channel(false).0),
+        };
+        tokio::spawn(Self::read_frames(
+            reader,
+            connection.writer.clone(),
+            connection.pending_acks.clone(),
+            connection.closed.clone(),
+            max_frame_size,
+            keepalive,
+            tx,
+        ));
+        tokio::spawn(Self::keep_alive(
+            Arc::downgrade(&connection.writer),
+            connection.closed.clone(),
+            keepalive,
+        ));
+        connection
+    }
+
+    // hands data frames to receive and acknowledges them, matches acks to waiting senders,
+    // stops once the stream fails, goes silent, is closed or nobody can receive anymore,
+    // never waits on receive so acks for our own sends get through while nobody drains it
+    async fn read_frames(
+        mut reader: ReadHalf<TlsStream<TcpStream>>,
+        writer: Arc<Mutex<TlsWriter>>,
+        pending_acks: Arc<std::sync::Mutex<VecDeque<oneshot::Sender<()>>>>,
+        closed: Arc<watch::Sender<bool>>,
+        max_frame_size: usize,
+        keepalive: Duration,
+        tx: mpsc::Sender<Result<Vec<u8>, MeshError>>,
+    ) {
+        // acks go out from their own task, a reader waiting on the writer could deadlock with
+        // a peer doing the same while both send large frames
+        let (acks, mut to_ack) = mpsc::unbounded_channel::<()>();
+        let (ack_writer, ack_closed) = (writer.clone(), closed.clone());
+        tokio::spawn(async move {
+            while to_ack.recv().await.is_some() {
+                let mut writer = ack_writer.lock().await;
+                if let Err(e) = write_kind(&mut writer, FRAME_ACK, &[], 0).await {
+                    log::warn!("Failed to acknowledge frame: {}", e);
+                    ack_closed.send_replace(true);
+                    return;
+                }
+            }
+        });
+
+        // data frames are acked once receive has room for them, so a peer that outpaces us
+        // blocks in its own send, and only its sends in flight ever wait here, a peer
+        // keeping more than that in flight is cut off
+        let (deliver, mut to_deliver) =
+            mpsc::channel::<Result<Vec<u8>, MeshError>>(MAX_UNACKED_FRAMES);
+        let receiving = tx.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = to_deliver.recv().await {
+                let data = frame.is_ok();
+                if receiving.send(frame).await.is_err() {
+                    return;
+                }
+                if data {
+                    let _ = acks.send(());
+                }
+            }
+        });
+
+        let mut closing = closed.subscribe();
+        let silence = keepalive * KEEPALIVE_MISSES;
+        let result: Result<(), MeshError> = loop {
+            let frame = tokio::select! {
+                frame = tokio::time::timeout(silence, read_frame(&mut reader, max_frame_size + 1)) => frame,
+                _ = closing.wait_for(|closed| *closed) => break Err(MeshError::closed("connection closed")),
+                _ = tx.closed() => break Ok(()),
+            };
+            let frame = match frame {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(e)) => break Err(e),
+                Err(_) => {
+                    break Err(MeshError::Timeout(format!(
+                        "nothing heard for {:?}",
+                        silence
+                    )))
+                }
+            };
+
+            match frame.split_first() {
+                Some((&FRAME_DATA, data)) => {
+                    if deliver.try_send(Ok(data.to_vec())).is_err() {
+                        break Err(MeshError::other(format!(
+                            "peer has more than {} frames unacknowledged",
+                            MAX_UNACKED_FRAMES
+                        )));
+                    }
+                }
+                Some((&FRAME_ACK, _)) => {
+                    if let Some(waiting) = pending_acks.lock().unwrap().pop_front() {
+                        let _ = waiting.send(());
+                    }
+                }
+                Some((&FRAME_PING, _)) => {}
+                _ => break Err(MeshError::decode("unknown frame kind")),
+            }
+        };
+
+        // senders still waiting for an ack learn the connection is gone, the peer does too
+        closed.send_replace(true);
+        pending_acks.lock().unwrap().clear();
+        tokio::spawn(async move {
+            let _ = writer.lock().await.shutdown().await;
+        });
+        if let Err(e) = result {
+            log::warn!("TLS connection ended: {}", e);
+            let _ = deliver.send(Err(e)).await;
+        }
+    }
+
+    // pings every interval so the peer's reader doesn't give up on a quiet connection
+    async fn keep_alive(
+        writer: Weak<Mutex<TlsWriter>>,
+        closed: Arc<watch::Sender<bool>>,
+        interval: Duration,
+    ) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if *closed.borrow() {
+                return;
+            }
+            let Some(writer) = writer.upgrade() else {
+                return;
+            };
+            let mut writer = writer.lock().await;
+            if let Err(e) = write_kind(&mut writer, FRAME_PING, &[], 0).await {
+                log::warn!("Keepalive failed: {}", e);
+                closed.send_replace(true);
+                return;
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    // stops the background tasks and lets the peer know, pending sends fail
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+        let writer = self.writer.clone();
+        tokio::spawn(async move {
+            let _ = writer.lock().await.shutdown().await;
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl LinkConnection for TcpTlsLinkConnection {
+    // returns once the peer acknowledged the frame, like a quic stream being stopped
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError> {
+        if data.len() > self.max_frame_size {
+            return Err(MeshError::MtuExceeded {
+                size: data.len(),
+                limit: self.max_frame_size,
+            });
+        }
+
+        // held until the peer acknowledged, its reader only has room for so many
+        let _in_flight = self
+            .in_flight
+            .acquire()
+            .await
+            .map_err(|_| MeshError::closed("connection closed"))?;
+        let (ack_tx, ack_rx) = oneshot::channel();
+        {
+            let mut writer = self.writer.lock().await;
+            self.pending_acks.lock().unwrap().push_back(ack_tx);
+            // checked after queueing, the reader clears the queue only after marking it closed
+            if self.is_closed() {
+                return Err(MeshError::closed("connection closed"));
+            }
+            if let Err(e) = write_kind(&mut writer, FRAME_DATA, data, self.max_frame_size).await {
+                self.close();
+                return Err(e);
+            }
+        }
+        ack_rx
+            .await
+            .map_err(|_| MeshError::closed("connection closed before the peer acknowledged"))?;
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        let mut incoming = self.incoming.lock().await;
+        match incoming.recv().await {
+            Some(result) => result,
+            None => Err(MeshError::closed("connection closed")),
+        }
+    }
+
+    // a stream has no packet limit, this is what fits a single segment
+    fn mtu(&self) -> usize {
+        TCP_TLS_MTU
+    }
+}
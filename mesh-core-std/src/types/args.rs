@@ -8,4 +8,12 @@ pub struct Args {
 
     #[arg(short, long, default_value = "log_config.yml")]
     pub log_config: String,
+
+    // overrides [node] name
+    #[arg(short, long)]
+    pub node_name: Option<String>,
+
+    // overrides the port of [links.wifi] listen
+    #[arg(short, long)]
+    pub port: Option<u16>,
 }
@@ -1,9 +1,17 @@
-use crate::MeshError;
+#[cfg(target_os = "linux")]
+use crate::bluetooth::bluez::BluezPeripheralBackend;
+use crate::{
+    bluetooth::{
+        fragment::{Fragmenter, Reassembler, PACKET_DATAGRAM, PACKET_FRAGMENT, PACKET_MTU},
+        peripheral::{CentralSession, PeripheralBackend},
+    },
+    MeshError,
+};
 use btleplug::{
     api::{Characteristic, Manager as _, Peripheral},
     platform::{Adapter, Manager, Peripheral as PlatformPeripheral},
 };
-use std::sync::Arc;
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
 use tokio::{
     sync::{
         mpsc::{self, Receiver},
@@ -11,44 +19,196 @@ use tokio::{
     },
     task,
 };
-use tokio_stream::StreamExt;
+use tokio_stream::{Stream, StreamExt};
 use uuid::Uuid;
 
-pub const HEADER_SIZE: usize = 3; // header ko lagi 2 byte and seq number ko lagi 1
+// largest value one write or notification carries before the att mtu is negotiated,
+// the minimum att mtu of 23 minus its 3 byte header
 pub const DEFAULT_MTU: usize = 20;
 
+// att attribute values never exceed 512 bytes, whatever mtu was negotiated
+pub const MAX_MTU: usize = 512;
+
 // Entry point for ble, like endpoint WifiLink ko jasto
 #[derive(Clone, Debug)]
 pub struct BleLink {
-    // physical ble adaptor for MAC, h/w interface which transmits/receives actual BLE signals
-    pub adapter: Adapter,
+    // physical ble adaptor for MAC, h/w interface which transmits/receives actual BLE signals,
+    // none when the link only runs the peripheral role
+    pub adapter: Option<Adapter>,
+
+    // gatt server used for the peripheral role, accept needs one
+    pub peripheral: Option<Arc<dyn PeripheralBackend>>,
 
     // uuid of service that link wants to interact with
     pub service_uuid: Uuid,
 
     // uuid of the characteristic that link wants to interact with within tyo mathi ko service
     pub characteristic_uuid: Uuid,
+
+    // connections we dialed, reused while still connected since finding a peripheral means
+    // scanning for seconds
+    pub connections: Arc<PeripheralCache<BleLinkConnection>>,
 }
 
 impl BleLink {
+    // both roles on linux, the peripheral one through bluez, elsewhere accept needs a backend
+    // set with with_peripheral
     pub async fn new(service_uuid: Uuid, characteristic_uuid: Uuid) -> Result<Self, MeshError> {
-        let manager = Manager::new().await.unwrap(); // devices list garcha, scans, manages
-                                                     // connection
+        let manager = Manager::new().await?; // devices list garcha, scans, manages connection
         let adapters = manager.adapters().await?;
         let adapter = adapters
             .into_iter()
             .next()
-            .ok_or_else(|| "No BLE adapter found")?;
+            .ok_or_else(|| MeshError::Bluetooth("no BLE adapter found".to_string()))?;
+
+        #[cfg(target_os = "linux")]
+        let peripheral: Option<Arc<dyn PeripheralBackend>> =
+            Some(Arc::new(BluezPeripheralBackend::new().await?));
+        #[cfg(not(target_os = "linux"))]
+        let peripheral = None;
 
         Ok(Self {
-            adapter,
+            adapter: Some(adapter),
+            peripheral,
             service_uuid,
             characteristic_uuid,
+            connections: Default::default(),
         })
     }
+
+    // peripheral role only, dial fails without a central adapter
+    pub fn with_backend(
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+        peripheral: Arc<dyn PeripheralBackend>,
+    ) -> Self {
+        Self {
+            adapter: None,
+            peripheral: Some(peripheral),
+            service_uuid,
+            characteristic_uuid,
+            connections: Default::default(),
+        }
+    }
+
+    pub fn with_peripheral(mut self, peripheral: Arc<dyn PeripheralBackend>) -> Self {
+        self.peripheral = Some(peripheral);
+        self
+    }
 }
 
+// connections keyed by peripheral address
 #[derive(Debug)]
+pub struct PeripheralCache<C> {
+    connections: std::sync::Mutex<HashMap<String, C>>,
+}
+
+impl<C> Default for PeripheralCache<C> {
+    fn default() -> Self {
+        Self {
+            connections: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C: Clone> PeripheralCache<C> {
+    // the connection to address if connected says it is still up, a dropped one is forgotten
+    pub async fn get<F, Fut>(&self, address: &str, connected: F) -> Option<C>
+    where
+        F: FnOnce(C) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let connection = self.connections.lock().unwrap().get(address).cloned()?;
+        if connected(connection.clone()).await {
+            return Some(connection);
+        }
+        self.connections.lock().unwrap().remove(address);
+        None
+    }
+
+    pub fn insert(&self, address: &str, connection: C) {
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), connection);
+    }
+
+    pub fn remove(&self, address: &str) -> Option<C> {
+        self.connections.lock().unwrap().remove(address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// packets written by one side of a ble connection, as seen by the other
+pub type PacketStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+// a datagram is a single packet, so it has to fit the mtu along with its type byte
+pub fn datagram_packet(data: &[u8], mtu: usize) -> Result<Vec<u8>, MeshError> {
+    let max = mtu - 1;
+    if data.len() > max {
+        return Err(MeshError::MtuExceeded {
+            size: data.len(),
+            limit: max,
+        });
+    }
+
+    let mut packet = Vec::with_capacity(data.len() + 1);
+    packet.push(PACKET_DATAGRAM);
+    packet.extend_from_slice(data);
+    Ok(packet)
+}
+
+pub fn mtu_packet(mtu: usize) -> Vec<u8> {
+    let mtu = mtu.clamp(DEFAULT_MTU, MAX_MTU) as u16;
+    let mut packet = vec![PACKET_MTU];
+    packet.extend_from_slice(&mtu.to_le_bytes());
+    packet
+}
+
+// reassembles fragments into messages on tx, hands datagrams to datagram_tx and
+// applies mtu announcements to the connection's fragmenter
+pub async fn reassemble(
+    mut packets: PacketStream,
+    tx: mpsc::Sender<Vec<u8>>,
+    datagram_tx: mpsc::Sender<Vec<u8>>,
+    fragmenter: Arc<Fragmenter>,
+) {
+    let mut reassembler = Reassembler::default();
+
+    while let Some(packet) = packets.next().await {
+        match packet.first() {
+            // datagrams bypass reassembly, a full queue just drops them
+            Some(&PACKET_DATAGRAM) => {
+                let _ = datagram_tx.try_send(packet[1..].to_vec());
+            }
+            Some(&PACKET_FRAGMENT) => match reassembler.push(&packet) {
+                Ok(Some(message)) => {
+                    if tx.send(message).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Dropping BLE fragment: {}", e),
+            },
+            Some(&PACKET_MTU) if packet.len() >= 3 => {
+                let mtu = u16::from_le_bytes([packet[1], packet[2]]) as usize;
+                let mtu = mtu.clamp(DEFAULT_MTU, MAX_MTU);
+                log::info!("BLE connection negotiated a {} byte mtu", mtu);
+                fragmenter.set_mtu(mtu);
+            }
+            other => log::warn!("Unknown BLE packet type {:?}", other),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct BleLinkConnection {
     // connected peripheral we talk to
     pub peripheral: Arc<PlatformPeripheral>,
@@ -56,8 +216,14 @@ pub struct BleLinkConnection {
     // characteristic where we send/receive data
     pub characteristic: Characteristic,
 
+    // splits outgoing messages to fit the mtu, which the peripheral announces after we subscribe
+    pub fragmenter: Arc<Fragmenter>,
+
     // channel to send message to receive
     pub rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+
+    // single packet datagrams, delivered as they arrive
+    pub datagram_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
 }
 
 impl BleLinkConnection {
@@ -66,66 +232,78 @@ impl BleLinkConnection {
         characteristic: Characteristic,
     ) -> Result<Self, MeshError> {
         let (tx, rx) = mpsc::channel::<Vec<u8>>(32);
+        let (datagram_tx, datagram_rx) = mpsc::channel::<Vec<u8>>(32);
         let peripheral = Arc::new(peripheral);
         let c = characteristic.clone();
+        // btleplug doesn't expose the att mtu, so start small until the peripheral tells us
+        let fragmenter = Arc::new(Fragmenter::new(DEFAULT_MTU));
 
+        // taken before subscribing so the first notifications aren't missed, and before
+        // spawning so a peripheral that can't deliver notifications fails the dial
+        let events = peripheral.notifications().await?;
         peripheral.subscribe(&c).await?;
-        task::spawn(Self::notification_task(peripheral.clone(), c.clone(), tx));
+        // the stream carries every characteristic of the peripheral, we only want ours
+        let uuid = c.uuid;
+        let packets = events.filter_map(move |event| (event.uuid == uuid).then_some(event.value));
+        task::spawn(Self::notification_task(
+            Box::pin(packets),
+            tx,
+            datagram_tx,
+            fragmenter.clone(),
+        ));
         Ok(Self {
             peripheral,
             characteristic: c,
+            fragmenter,
             rx: Arc::new(Mutex::new(rx)),
+            datagram_rx: Arc::new(Mutex::new(datagram_rx)),
         })
     }
 
     pub async fn notification_task(
-        peripheral: Arc<PlatformPeripheral>,
-        characteristic: Characteristic,
+        packets: PacketStream,
         tx: mpsc::Sender<Vec<u8>>,
+        datagram_tx: mpsc::Sender<Vec<u8>>,
+        fragmenter: Arc<Fragmenter>,
     ) {
-        let mut buffer = Vec::new();
-        let mut expected_length: Option<usize> = None;
-        let mut last_sequence: Option<u8> = None;
+        reassemble(packets, tx, datagram_tx, fragmenter).await;
+    }
+}
 
-        let mut events = peripheral.notifications().await.unwrap();
+// connection accepted in the peripheral role, one per subscribed central
+#[derive(Debug)]
+pub struct BlePeripheralConnection {
+    // central on the other end, we notify it and it writes to us
+    pub central: Arc<dyn CentralSession>,
 
-        while let Some(event) = events.next().await {
-            let chunk = event.value;
+    // splits outgoing messages to fit the mtu the backend negotiated
+    pub fragmenter: Arc<Fragmenter>,
 
-            if chunk.len() < HEADER_SIZE {
-                continue;
-            }
+    // channel to send message to receive
+    pub rx: Arc<Mutex<Receiver<Vec<u8>>>>,
 
-            let len = u16::from_le_bytes([chunk[0], chunk[1]]);
-            let seq = chunk[2];
-
-            let payload = &chunk[HEADER_SIZE..];
-
-            if expected_length.is_none() {
-                buffer.clear();
-                buffer.extend_from_slice(&payload);
-                expected_length = Some(len.into());
-                last_sequence = Some(seq);
-            } else {
-                if let Some(last) = last_sequence {
-                    if last != seq.wrapping_add(1) {
-                        log::error!("Fragment sequence error: last {} current {}", last, seq);
-                        buffer.clear();
-                        expected_length = None;
-                        last_sequence = None;
-                        continue;
-                    }
-                }
-                buffer.extend_from_slice(payload);
-                last_sequence = Some(seq);
-            }
-        }
+    // single packet datagrams, delivered as they arrive
+    pub datagram_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+}
 
-        if let Some(total) = expected_length {
-            if buffer.len() >= total {
-                let _ = tx.send(buffer.clone()).await;
-                buffer.clear();
-            }
-        }
+impl BlePeripheralConnection {
+    pub async fn new(central: Box<dyn CentralSession>) -> Result<Self, MeshError> {
+        let (tx, rx) = mpsc::channel::<Vec<u8>>(32);
+        let (datagram_tx, datagram_rx) = mpsc::channel::<Vec<u8>>(32);
+        let central: Arc<dyn CentralSession> = Arc::from(central);
+        let mtu = central.mtu().clamp(DEFAULT_MTU, MAX_MTU);
+        let fragmenter = Arc::new(Fragmenter::new(mtu));
+
+        let writes = central.writes().await?;
+        task::spawn(reassemble(writes, tx, datagram_tx, fragmenter.clone()));
+
+        // the central can't read the att mtu itself, so tell it what we negotiated
+        central.notify(&mtu_packet(mtu)).await?;
+        Ok(Self {
+            central,
+            fragmenter,
+            rx: Arc::new(Mutex::new(rx)),
+            datagram_rx: Arc::new(Mutex::new(datagram_rx)),
+        })
     }
 }
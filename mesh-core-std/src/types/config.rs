@@ -0,0 +1,529 @@
+use crate::{
+    link::registry::TransportRegistry,
+    types::{
+        args::Args,
+        discovery::{DiscoveryConfig, DEFAULT_GROUP, DEFAULT_PORT},
+        distance_vector::RoutingTable,
+        on_demand::{OnDemandRouting, DEFAULT_ROUTE_LIFETIME},
+        peer::{LinkEndpoint, LinkType, LivenessConfig, PeerID, PeerInfo},
+        routing::RoutingStrategy,
+    },
+    utils::load_certificate_authority,
+    MeshError,
+};
+use quinn::rustls::pki_types::{pem::PemObject, CertificateDer};
+use rcgen::{Issuer, KeyPair};
+use serde::Deserialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use uuid::Uuid;
+
+// where the binary looks when no --config is given, running without it is fine
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// what config.toml holds, every section and field may be left out
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub application: ApplicationSettings,
+    pub node: NodeSettings,
+    pub certificates: CertificateSettings,
+    pub links: LinkSettings,
+    pub discovery: DiscoverySettings,
+    pub routing: RoutingSettings,
+    pub liveness: LivenessSettings,
+    pub reload: ReloadSettings,
+    // neighbours dialed without waiting for discovery
+    pub peers: Vec<PeerSettings>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApplicationSettings {
+    pub name: String,
+    pub version: String,
+}
+
+impl Default for ApplicationSettings {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeSettings {
+    // the node's identity, its peer id and the name in its certificate
+    pub name: String,
+}
+
+impl Default for NodeSettings {
+    fn default() -> Self {
+        Self {
+            name: "node".to_string(),
+        }
+    }
+}
+
+// pem files of the mesh ca, both or neither, without them a throwaway ca is made and only
+// nodes in the same process trust each other
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CertificateSettings {
+    pub ca_cert: Option<PathBuf>,
+    pub ca_key: Option<PathBuf>,
+    // pem files of other cas whose nodes are trusted too, e.g. while moving to a new ca
+    pub trusted: Vec<PathBuf>,
+}
+
+impl CertificateSettings {
+    pub fn load(
+        &self,
+    ) -> Result<Option<(CertificateDer<'static>, Issuer<'static, KeyPair>)>, MeshError> {
+        let (Some(cert), Some(key)) = (&self.ca_cert, &self.ca_key) else {
+            return Ok(None);
+        };
+        let read = |path: &Path| {
+            std::fs::read_to_string(path)
+                .map_err(|e| MeshError::Config(format!("{}: {}", path.display(), e)))
+        };
+        load_certificate_authority(&read(cert)?, &read(key)?).map(Some)
+    }
+
+    pub fn load_trusted(&self) -> Result<Vec<CertificateDer<'static>>, MeshError> {
+        self.trusted
+            .iter()
+            .map(|path| {
+                CertificateDer::from_pem_file(path)
+                    .map_err(|e| MeshError::Config(format!("{}: {}", path.display(), e)))
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkSettings {
+    // the order links are tried in, enabled links left out follow in the order wifi, tcp, ble
+    pub priority: Vec<String>,
+    pub wifi: WifiSettings,
+    pub tcp: TcpSettings,
+    pub ble: BleSettings,
+}
+
+impl Default for LinkSettings {
+    fn default() -> Self {
+        Self {
+            priority: ["wifi", "tcp", "ble"].map(String::from).to_vec(),
+            wifi: WifiSettings::default(),
+            tcp: TcpSettings::default(),
+            ble: BleSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WifiSettings {
+    pub enabled: bool,
+    // also what discovery announces, an unspecified ip is filled in by whoever hears it
+    pub listen: String,
+}
+
+impl Default for WifiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: "0.0.0.0:5000".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpSettings {
+    pub enabled: bool,
+    pub listen: String,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "0.0.0.0:5100".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BleSettings {
+    pub enabled: bool,
+    pub service_uuid: String,
+    pub characteristic_uuid: String,
+}
+
+impl Default for BleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            service_uuid: "6e400001-b5a3-f393-e0a9-e50e24dcca9e".to_string(),
+            characteristic_uuid: "6e400002-b5a3-f393-e0a9-e50e24dcca9e".to_string(),
+        }
+    }
+}
+
+impl BleSettings {
+    pub fn uuids(&self) -> Result<(Uuid, Uuid), MeshError> {
+        let parse = |uuid: &str| {
+            Uuid::parse_str(uuid)
+                .map_err(|e| MeshError::Config(format!("ble uuid {}: {}", uuid, e)))
+        };
+        Ok((
+            parse(&self.service_uuid)?,
+            parse(&self.characteristic_uuid)?,
+        ))
+    }
+}
+
+// see DiscoveryConfig, durations are in milliseconds
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoverySettings {
+    pub enabled: bool,
+    pub group: Ipv4Addr,
+    pub port: u16,
+    pub interface: Ipv4Addr,
+    pub ipv6_interfaces: Vec<u32>,
+    pub ttl: u32,
+    pub loopback: bool,
+    pub interval_ms: u64,
+    pub jitter_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        let defaults = DiscoveryConfig::default();
+        Self {
+            enabled: true,
+            group: DEFAULT_GROUP,
+            port: DEFAULT_PORT,
+            interface: defaults.interface,
+            ipv6_interfaces: defaults.ipv6_interfaces,
+            ttl: defaults.ttl,
+            loopback: defaults.loopback,
+            interval_ms: defaults.interval.as_millis() as u64,
+            jitter_ms: defaults.jitter.as_millis() as u64,
+            max_backoff_ms: defaults.max_backoff.as_millis() as u64,
+        }
+    }
+}
+
+impl DiscoverySettings {
+    pub fn discovery_config(&self) -> DiscoveryConfig {
+        let mut config = DiscoveryConfig::default()
+            .with_group(self.group, self.port)
+            .with_interface(self.interface)
+            .with_ttl(self.ttl)
+            .with_loopback(self.loopback)
+            .with_interval(
+                Duration::from_millis(self.interval_ms),
+                Duration::from_millis(self.jitter_ms),
+            )
+            .with_max_backoff(Duration::from_millis(self.max_backoff_ms));
+        if !self.ipv6_interfaces.is_empty() {
+            let group = config.group_v6;
+            config = config.with_ipv6(group, self.ipv6_interfaces.clone());
+        }
+        config
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingKind {
+    // only direct neighbours are reachable
+    #[default]
+    None,
+    DistanceVector,
+    OnDemand,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingSettings {
+    pub strategy: RoutingKind,
+    // how often the strategy ticks, e.g. advertises its table
+    pub interval_ms: u64,
+    // how long on demand routes are kept without being used
+    pub route_lifetime_ms: u64,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        Self {
+            strategy: RoutingKind::None,
+            interval_ms: 1000,
+            route_lifetime_ms: DEFAULT_ROUTE_LIFETIME.as_millis() as u64,
+        }
+    }
+}
+
+impl RoutingSettings {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn strategy(&self, id: PeerID) -> Option<Box<dyn RoutingStrategy>> {
+        match self.strategy {
+            RoutingKind::None => None,
+            RoutingKind::DistanceVector => Some(Box::new(RoutingTable::new(id))),
+            RoutingKind::OnDemand => Some(Box::new(OnDemandRouting::with_lifetime(
+                id,
+                Duration::from_millis(self.route_lifetime_ms),
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LivenessSettings {
+    pub suspect_after_ms: u64,
+    pub dead_after_ms: u64,
+}
+
+impl Default for LivenessSettings {
+    fn default() -> Self {
+        let defaults = LivenessConfig::default();
+        Self {
+            suspect_after_ms: defaults.suspect_after.as_millis() as u64,
+            dead_after_ms: defaults.dead_after.as_millis() as u64,
+        }
+    }
+}
+
+impl LivenessSettings {
+    pub fn liveness_config(&self) -> LivenessConfig {
+        LivenessConfig::new(
+            Duration::from_millis(self.suspect_after_ms),
+            Duration::from_millis(self.dead_after_ms),
+        )
+    }
+}
+
+// how the binary notices a changed config, a SIGHUP reloads it as well
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadSettings {
+    pub watch: bool,
+    // how often the file's modification time is checked
+    pub interval_ms: u64,
+}
+
+impl Default for ReloadSettings {
+    fn default() -> Self {
+        Self {
+            watch: true,
+            interval_ms: 5000,
+        }
+    }
+}
+
+impl ReloadSettings {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+// one endpoint of a bootstrap peer, a peer reachable over several links is listed once per link
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PeerSettings {
+    pub name: String,
+    pub link: String,
+    pub address: String,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, MeshError> {
+        toml::from_str(text).map_err(|e| MeshError::Config(e.to_string()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MeshError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| MeshError::Config(format!("{}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| MeshError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    // the file --config points at with the other flags on top, checked before it's returned,
+    // a missing file is only an error when it was asked for
+    pub fn from_args(args: &Args) -> Result<Self, MeshError> {
+        let path = Path::new(&args.config);
+        let mut config = if args.config == DEFAULT_CONFIG_PATH && !path.exists() {
+            log::warn!("No {} found, running with defaults", DEFAULT_CONFIG_PATH);
+            Self::default()
+        } else {
+            Self::load(path)?
+        };
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(name) = &args.node_name {
+            self.node.name = name.clone();
+        }
+        if let Some(port) = args.port {
+            let listen = &mut self.links.wifi.listen;
+            *listen = match listen.parse::<SocketAddr>() {
+                Ok(mut addr) => {
+                    addr.set_port(port);
+                    addr.to_string()
+                }
+                Err(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port).to_string(),
+            };
+        }
+    }
+
+    // every problem at once, so a config doesn't have to be fixed one line per restart
+    pub fn validate(&self) -> Result<(), MeshError> {
+        let mut problems = Vec::new();
+
+        if self.node.name.trim().is_empty() {
+            problems.push("node.name is empty".to_string());
+        }
+        if self.certificates.ca_cert.is_some() != self.certificates.ca_key.is_some() {
+            problems.push("certificates.ca_cert and certificates.ca_key go together".to_string());
+        }
+
+        let links = &self.links;
+        if !links.wifi.enabled && !links.tcp.enabled && !links.ble.enabled {
+            problems.push("no link is enabled".to_string());
+        }
+        for (name, enabled, listen) in [
+            ("wifi", links.wifi.enabled, &links.wifi.listen),
+            ("tcp", links.tcp.enabled, &links.tcp.listen),
+        ] {
+            if enabled && listen.parse::<SocketAddr>().is_err() {
+                problems.push(format!(
+                    "links.{}.listen {:?} is not an ip:port",
+                    name, listen
+                ));
+            }
+        }
+        if links.ble.enabled {
+            if let Err(e) = links.ble.uuids() {
+                problems.push(format!("links.ble: {}", e));
+            }
+        }
+        for (i, name) in links.priority.iter().enumerate() {
+            if !["wifi", "tcp", "ble"].contains(&name.as_str()) {
+                problems.push(format!("links.priority names unknown link {:?}", name));
+            } else if links.priority[..i].contains(name) {
+                problems.push(format!("links.priority lists {} twice", name));
+            }
+        }
+
+        let discovery = &self.discovery;
+        if discovery.enabled {
+            if !discovery.group.is_multicast() {
+                problems.push(format!(
+                    "discovery.group {} is not a multicast address",
+                    discovery.group
+                ));
+            }
+            if discovery.port == 0 {
+                problems.push("discovery.port is 0".to_string());
+            }
+            if discovery.ttl == 0 {
+                problems.push("discovery.ttl is 0".to_string());
+            }
+            if discovery.interval_ms == 0 {
+                problems.push("discovery.interval_ms is 0".to_string());
+            }
+        }
+
+        if self.routing.strategy != RoutingKind::None && self.routing.interval_ms == 0 {
+            problems.push("routing.interval_ms is 0".to_string());
+        }
+        if self.routing.strategy == RoutingKind::OnDemand && self.routing.route_lifetime_ms == 0 {
+            problems.push("routing.route_lifetime_ms is 0".to_string());
+        }
+
+        let liveness = &self.liveness;
+        if liveness.suspect_after_ms == 0 || liveness.dead_after_ms <= liveness.suspect_after_ms {
+            problems.push("liveness needs 0 < suspect_after_ms < dead_after_ms".to_string());
+        }
+
+        if self.reload.watch && self.reload.interval_ms == 0 {
+            problems.push("reload.interval_ms is 0".to_string());
+        }
+
+        let registry = TransportRegistry::default();
+        for peer in &self.peers {
+            if peer.name.trim().is_empty() {
+                problems.push("a peer has no name".to_string());
+            } else if peer.name == self.node.name {
+                problems.push(format!("peer {} is this node", peer.name));
+            }
+            let link = LinkType::new(peer.link.clone());
+            if let Err(e) = registry.parse_address(&link, &peer.address, None) {
+                problems.push(format!("peer {}: {}", peer.name, e));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(MeshError::Config(problems.join("; ")))
+        }
+    }
+
+    // enabled links in the order they're tried
+    pub fn priority(&self) -> Vec<LinkType> {
+        let enabled = |link: &LinkType| match link.as_str() {
+            "wifi" => self.links.wifi.enabled,
+            "tcp" => self.links.tcp.enabled,
+            "ble" => self.links.ble.enabled,
+            _ => false,
+        };
+        let mut priority: Vec<LinkType> = Vec::new();
+        let configured = self
+            .links
+            .priority
+            .iter()
+            .map(|name| LinkType::new(name.clone()));
+        for link in configured.chain([LinkType::WIFI, LinkType::TCP, LinkType::BLE]) {
+            if enabled(&link) && !priority.contains(&link) {
+                priority.push(link);
+            }
+        }
+        priority
+    }
+
+    // bootstrap peers with their endpoints gathered, addresses as the peer store keeps them
+    pub fn bootstrap_peers(&self) -> Result<Vec<PeerInfo>, MeshError> {
+        let registry = TransportRegistry::default();
+        let mut peers: Vec<PeerInfo> = Vec::new();
+        for peer in &self.peers {
+            let link = LinkType::new(peer.link.clone());
+            let address = registry.parse_address(&link, &peer.address, None)?;
+            let id = PeerID(peer.name.clone());
+            match peers.iter_mut().find(|info| info.id == id) {
+                Some(info) => info.endpoints.push(LinkEndpoint::new(link, address)),
+                None => peers.push(PeerInfo::new(id).with_endpoint(link, address)),
+            }
+        }
+        Ok(peers)
+    }
+}
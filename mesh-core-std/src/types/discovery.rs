@@ -0,0 +1,174 @@
+use quinn::rustls::pki_types::CertificateDer;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::{health::Health, peer::PeerID};
+use crate::link::discovery::AnnouncementVerifier;
+
+pub const DEFAULT_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 1);
+// link-local scope, so beacons never leave the segment they were sent on
+pub const DEFAULT_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x6d65, 0x7368);
+pub const DEFAULT_PORT: u16 = 4000;
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+// where and how often beacons are sent, nodes only see each other with matching group and port
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    pub ipv4: bool,
+    pub group: Ipv4Addr,
+    pub port: u16,
+    pub group_v6: Ipv6Addr,
+    // interface indexes to beacon on over ipv6, link-local groups are per interface so each one
+    // is joined separately, empty leaves ipv6 off
+    pub ipv6_interfaces: Vec<u32>,
+    // hops a beacon may cross, 1 keeps it on the local network
+    pub ttl: u32,
+    // whether our own beacons come back to listeners on this host
+    pub loopback: bool,
+    // local ipv4 interface address to send and listen on, unspecified lets the os pick
+    pub interface: Ipv4Addr,
+    pub interval: Duration,
+    // up to this much is added to every interval so nodes started together don't beacon in step
+    pub jitter: Duration,
+    // failing sends and receives retry with a doubling delay capped here
+    pub max_backoff: Duration,
+    // where the broadcast and listener tasks report failing sockets
+    pub health: Health,
+    // the listening node, beacons with its id are its own and skipped
+    pub local_id: Option<PeerID>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            ipv4: true,
+            group: DEFAULT_GROUP,
+            port: DEFAULT_PORT,
+            group_v6: DEFAULT_GROUP_V6,
+            ipv6_interfaces: Vec::new(),
+            ttl: 1,
+            loopback: true,
+            interface: Ipv4Addr::UNSPECIFIED,
+            interval: DEFAULT_INTERVAL,
+            jitter: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            health: Health::default(),
+            local_id: None,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn with_group(mut self, group: Ipv4Addr, port: u16) -> Self {
+        self.group = group;
+        self.port = port;
+        self
+    }
+
+    pub fn with_ipv4(mut self, ipv4: bool) -> Self {
+        self.ipv4 = ipv4;
+        self
+    }
+
+    pub fn with_ipv6(mut self, group: Ipv6Addr, interfaces: Vec<u32>) -> Self {
+        self.group_v6 = group;
+        self.ipv6_interfaces = interfaces;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_loopback(mut self, loopback: bool) -> Self {
+        self.loopback = loopback;
+        self
+    }
+
+    pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration, jitter: Duration) -> Self {
+        self.interval = interval;
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_local_id(mut self, id: PeerID) -> Self {
+        self.local_id = Some(id);
+        self
+    }
+
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    // the interval plus a random share of the jitter
+    pub fn next_delay(&self) -> Duration {
+        let jitter = self.jitter.as_millis() as u64;
+        if jitter == 0 {
+            return self.interval;
+        }
+        // RandomState is seeded randomly per instance, good enough to spread beacons
+        let random = RandomState::new().build_hasher().finish();
+        self.interval + Duration::from_millis(random % (jitter + 1))
+    }
+
+    pub fn backoff(&self, delay: Duration) -> Duration {
+        (delay * 2).min(self.max_backoff)
+    }
+}
+
+// stops the discovery tasks it was returned with and passes them settings that change live
+#[derive(Debug)]
+pub struct DiscoveryHandle {
+    pub(crate) stop_tx: watch::Sender<bool>,
+    pub(crate) tasks: Vec<JoinHandle<()>>,
+    // interval and jitter of the broadcast task
+    pub(crate) timing: Option<watch::Sender<(Duration, Duration)>>,
+    // what the listener tasks check beacons with
+    pub(crate) verifier: Option<Arc<Mutex<AnnouncementVerifier>>>,
+}
+
+impl DiscoveryHandle {
+    // only a broadcast handle has beacons to pace
+    pub fn set_interval(&self, interval: Duration, jitter: Duration) {
+        if let Some(timing) = &self.timing {
+            let _ = timing.send((interval, jitter));
+        }
+    }
+
+    // cas besides the mesh ca that beacons may be signed under, only a listener handle checks them
+    pub fn set_trusted(&self, trusted: Vec<CertificateDer<'static>>) {
+        if let Some(verifier) = &self.verifier {
+            verifier.lock().unwrap().set_trusted(trusted);
+        }
+    }
+
+    // signals the tasks and waits until they have exited
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(true);
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tasks.iter().all(|task| task.is_finished())
+    }
+}
@@ -0,0 +1,157 @@
+use super::peer::PeerID;
+use crate::mesh::{RouteAdvertisement, RouteUpdate};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// metric at which a destination is considered unreachable (same as RIP/DSDV)
+pub const INFINITY_METRIC: u32 = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteEntry {
+    pub destination: PeerID,
+    pub next_hop: PeerID,
+    pub metric: u32,
+    // destination sequence number, even = originated by destination, odd = broken route
+    pub sequence: u64,
+    pub updated_at: Instant,
+}
+
+impl RouteEntry {
+    pub fn is_reachable(&self) -> bool {
+        self.metric < INFINITY_METRIC
+    }
+}
+
+// DSDV style next hop table, one entry per known destination
+#[derive(Debug)]
+pub struct RoutingTable {
+    pub id: PeerID,
+    pub sequence: u64,
+    pub routes: HashMap<PeerID, RouteEntry>,
+}
+
+impl RoutingTable {
+    pub fn new(id: PeerID) -> Self {
+        Self {
+            id,
+            sequence: 0,
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn next_hop(&self, destination: &PeerID) -> Option<PeerID> {
+        self.routes
+            .get(destination)
+            .filter(|route| route.is_reachable())
+            .map(|route| route.next_hop.clone())
+    }
+
+    pub fn get_route(&self, destination: &PeerID) -> Option<&RouteEntry> {
+        self.routes.get(destination)
+    }
+
+    // builds the vector we send to our neighbours, our own sequence moves forward every time
+    pub fn advertisement(&mut self) -> RouteAdvertisement {
+        self.sequence += 2;
+
+        let mut routes = vec![RouteUpdate {
+            destination: self.id.0.clone(),
+            metric: 0,
+            sequence: self.sequence,
+        }];
+        routes.extend(self.routes.values().map(|route| RouteUpdate {
+            destination: route.destination.0.clone(),
+            metric: route.metric,
+            sequence: route.sequence,
+        }));
+
+        RouteAdvertisement {
+            origin: self.id.0.clone(),
+            routes,
+        }
+    }
+
+    // merges a neighbour's vector, returns true if our table changed
+    pub fn apply_advertisement(&mut self, advertisement: &RouteAdvertisement) -> bool {
+        let neighbour = PeerID(advertisement.origin.clone());
+        if neighbour == self.id {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut changed = false;
+
+        for update in &advertisement.routes {
+            let destination = PeerID(update.destination.clone());
+            if destination == self.id {
+                continue;
+            }
+
+            let metric = update.metric.saturating_add(1).min(INFINITY_METRIC);
+            let candidate = RouteEntry {
+                destination: destination.clone(),
+                next_hop: neighbour.clone(),
+                metric,
+                sequence: update.sequence,
+                updated_at: now,
+            };
+
+            match self.routes.get_mut(&destination) {
+                None => {
+                    if candidate.is_reachable() {
+                        self.routes.insert(destination, candidate);
+                        changed = true;
+                    }
+                }
+                Some(current) => {
+                    let fresher = candidate.sequence > current.sequence;
+                    let shorter =
+                        candidate.sequence == current.sequence && candidate.metric < current.metric;
+                    // our current next hop always has the final say about its own path
+                    let from_next_hop =
+                        current.next_hop == neighbour && candidate.sequence >= current.sequence;
+
+                    if fresher || shorter || from_next_hop {
+                        changed |= current.next_hop != candidate.next_hop
+                            || current.metric != candidate.metric
+                            || current.sequence != candidate.sequence;
+                        *current = candidate;
+                    }
+                }
+            }
+        }
+
+        changed
+    }
+
+    // a direct neighbour disappeared, every route through it becomes broken
+    pub fn invalidate_next_hop(&mut self, next_hop: &PeerID) -> bool {
+        let mut changed = false;
+        for route in self.routes.values_mut() {
+            if &route.next_hop == next_hop && route.is_reachable() {
+                route.metric = INFINITY_METRIC;
+                route.sequence |= 1;
+                route.updated_at = Instant::now();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // breaks routes that haven't been refreshed and drops broken ones that are long gone
+    pub fn expire(&mut self, timeout: Duration) {
+        let now = Instant::now();
+        for route in self.routes.values_mut() {
+            if route.is_reachable() && now.duration_since(route.updated_at) > timeout {
+                route.metric = INFINITY_METRIC;
+                route.sequence |= 1;
+                route.updated_at = now;
+            }
+        }
+        self.routes.retain(|_, route| {
+            route.is_reachable() || now.duration_since(route.updated_at) <= timeout
+        });
+    }
+}
@@ -0,0 +1,62 @@
+use tokio::sync::broadcast;
+
+// how a background task is doing, sent when that changes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HealthStatus {
+    // working again after it was degraded
+    Recovered,
+    // failing but still retrying
+    Degraded(String),
+    // gave up, only a restart of the component brings it back
+    Stopped(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthEvent {
+    // the task reporting, e.g. "discovery listener" or "wifi accept"
+    pub component: String,
+    pub status: HealthStatus,
+}
+
+// where background tasks report failures instead of panicking or exiting quietly,
+// clones share one channel so a node can hand the same one to all of its parts
+#[derive(Clone, Debug)]
+pub struct Health {
+    events: broadcast::Sender<HealthEvent>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self { events }
+    }
+}
+
+impl Health {
+    pub fn subscribe(&self) -> broadcast::Receiver<HealthEvent> {
+        self.events.subscribe()
+    }
+
+    // logged as well, so nothing is lost while nobody subscribes
+    pub fn report(&self, component: impl Into<String>, status: HealthStatus) {
+        let component = component.into();
+        match &status {
+            HealthStatus::Recovered => log::info!("{} recovered", component),
+            HealthStatus::Degraded(reason) => log::warn!("{} degraded: {}", component, reason),
+            HealthStatus::Stopped(reason) => log::error!("{} stopped: {}", component, reason),
+        }
+        let _ = self.events.send(HealthEvent { component, status });
+    }
+
+    pub fn degraded(&self, component: impl Into<String>, reason: impl ToString) {
+        self.report(component, HealthStatus::Degraded(reason.to_string()));
+    }
+
+    pub fn stopped(&self, component: impl Into<String>, reason: impl ToString) {
+        self.report(component, HealthStatus::Stopped(reason.to_string()));
+    }
+
+    pub fn recovered(&self, component: impl Into<String>) {
+        self.report(component, HealthStatus::Recovered);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{types::peer::PeerID, utils::generate_node_certs, MeshError};
+use quinn::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rcgen::{Issuer, KeyPair, SigningKey};
+use webpki::{anchor_from_trusted_cert, EndEntityCert, KeyUsage, ALL_VERIFICATION_ALGS};
+
+// a node's certificate from the mesh CA and the key that signs on its behalf
+#[derive(Debug)]
+pub struct NodeIdentity {
+    pub id: PeerID,
+    pub certificate: CertificateDer<'static>,
+    key_pair: KeyPair,
+}
+
+impl NodeIdentity {
+    pub fn new(issuer: &Issuer<'static, KeyPair>, node_name: &str) -> Self {
+        let (certificate, key_pair) = generate_node_certs(issuer, node_name);
+        Self {
+            id: PeerID(node_name.to_string()),
+            certificate: certificate.der().clone().into_owned(),
+            key_pair,
+        }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>, MeshError> {
+        self.key_pair
+            .sign(message)
+            .map_err(|e| MeshError::other(e.to_string()))
+    }
+}
+
+// checks that certificate chains to the mesh CA, is issued to node_id and made signature
+pub fn verify_signed(
+    ca: &CertificateDer<'_>,
+    certificate: &[u8],
+    node_id: &PeerID,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), MeshError> {
+    let anchors = [anchor_from_trusted_cert(ca)?];
+    let certificate = CertificateDer::from(certificate);
+    let certificate = EndEntityCert::try_from(&certificate)?;
+
+    certificate.verify_for_usage(
+        ALL_VERIFICATION_ALGS,
+        &anchors,
+        &[],
+        UnixTime::now(),
+        KeyUsage::server_auth(),
+        None,
+        None,
+    )?;
+    certificate.verify_is_valid_for_subject_name(
+        &ServerName::try_from(node_id.0.as_str())
+            .map_err(|e| MeshError::Handshake(e.to_string()))?,
+    )?;
+
+    let valid = ALL_VERIFICATION_ALGS.iter().any(|alg| {
+        certificate
+            .verify_signature(*alg, message, signature)
+            .is_ok()
+    });
+    if !valid {
+        return Err(MeshError::Handshake(format!(
+            "bad signature from {}",
+            node_id.0
+        )));
+    }
+    Ok(())
+}
@@ -1,5 +1,14 @@
 pub mod args;
 pub mod ble_types;
+pub mod config;
+pub mod discovery;
+pub mod distance_vector;
+pub mod health;
+pub mod identity;
+pub mod multipath;
+pub mod on_demand;
 pub mod peer;
+pub mod probe;
 pub mod routing;
+pub mod tcp_tls;
 pub mod wifi_quic;
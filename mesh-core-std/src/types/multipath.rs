@@ -0,0 +1,207 @@
+use crate::{mesh::MeshMessage, MeshError};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// extension carrying [index u16 le, count u16 le] on every stripe of a striped message
+pub const STRIPE_EXTENSION: &str = "stripe";
+// how long a delivered message id is remembered to drop its copies
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(30);
+pub const MAX_STRIPES: usize = 16;
+// striped messages put together at once, the stalest one is dropped to make room
+pub const MAX_PENDING_MESSAGES: usize = 64;
+
+// how a single message is spread over the links to a peer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SendMode {
+    // best link, the next one only if it fails
+    #[default]
+    Single,
+    // a copy over every link at once, for messages that must get through
+    Redundant,
+    // the payload split across links by capacity, for bulk data
+    Striped,
+}
+
+// cuts the payload into one stripe per weight, sized by the weights, never an empty stripe
+pub fn split_stripes(
+    message: &MeshMessage,
+    weights: &[f64],
+) -> Result<Vec<MeshMessage>, MeshError> {
+    let count = weights.len().min(message.payload.len()).min(MAX_STRIPES);
+    if count == 0 {
+        return Err(MeshError::other("nothing to stripe"));
+    }
+    let weights = &weights[..count];
+    let total: f64 = weights.iter().sum();
+    let len = message.payload.len();
+
+    let mut stripes = Vec::with_capacity(count);
+    let mut start = 0;
+    let mut share = 0.0;
+    for (index, weight) in weights.iter().enumerate() {
+        share += weight;
+        let end = if index + 1 == count {
+            len
+        } else {
+            // leave at least a byte for every stripe still to come
+            let end = (len as f64 * share / total).round() as usize;
+            end.clamp(start + 1, len - (count - index - 1))
+        };
+
+        let mut header = Vec::with_capacity(4);
+        header.extend_from_slice(&(index as u16).to_le_bytes());
+        header.extend_from_slice(&(count as u16).to_le_bytes());
+        let mut stripe = message.clone();
+        stripe.payload = message.payload[start..end].to_vec();
+        stripe
+            .extensions
+            .insert(STRIPE_EXTENSION.to_string(), header);
+        stripes.push(stripe);
+        start = end;
+    }
+    Ok(stripes)
+}
+
+fn stripe_header(message: &MeshMessage) -> Result<Option<(usize, usize)>, MeshError> {
+    let Some(header) = message.extensions.get(STRIPE_EXTENSION) else {
+        return Ok(None);
+    };
+    if header.len() != 4 {
+        return Err(MeshError::decode("malformed stripe header"));
+    }
+    let index = u16::from_le_bytes([header[0], header[1]]) as usize;
+    let count = u16::from_le_bytes([header[2], header[3]]) as usize;
+    if count == 0 || count > MAX_STRIPES || index >= count {
+        return Err(MeshError::decode(format!(
+            "stripe {} of {} is out of range",
+            index, count
+        )));
+    }
+    Ok(Some((index, count)))
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    stripes: Vec<Option<MeshMessage>>,
+    started: Instant,
+    updated_at: Instant,
+}
+
+// receiving end of multipath, hands each message out once no matter how many links
+// brought it and puts striped messages back together
+#[derive(Debug)]
+pub struct MultipathReceiver {
+    pub window: Duration,
+    delivered: HashMap<(String, u64), Instant>,
+    partial: HashMap<(String, u64), PartialMessage>,
+}
+
+impl Default for MultipathReceiver {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+impl MultipathReceiver {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            delivered: HashMap::new(),
+            partial: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, message: MeshMessage) -> Result<Option<MeshMessage>, MeshError> {
+        self.push_at(message, Instant::now())
+    }
+
+    // the whole message the first time it is complete, None for copies and missing stripes
+    pub fn push_at(
+        &mut self,
+        message: MeshMessage,
+        now: Instant,
+    ) -> Result<Option<MeshMessage>, MeshError> {
+        self.expire(now);
+        let key = (message.source.clone(), message.id);
+        if self.delivered.contains_key(&key) {
+            log::debug!("Dropping copy of message {} from {}", key.1, key.0);
+            return Ok(None);
+        }
+
+        let Some((index, count)) = stripe_header(&message)? else {
+            self.delivered.insert(key, now);
+            return Ok(Some(message));
+        };
+
+        if !self.partial.contains_key(&key) && self.partial.len() >= MAX_PENDING_MESSAGES {
+            self.drop_stalest();
+        }
+        let partial = self
+            .partial
+            .entry(key.clone())
+            .or_insert_with(|| PartialMessage {
+                stripes: vec![None; count],
+                started: now,
+                updated_at: now,
+            });
+        if partial.stripes.len() != count {
+            self.partial.remove(&key);
+            return Err(MeshError::decode(format!(
+                "stripe count of message {} changed",
+                key.1
+            )));
+        }
+        partial.stripes[index] = Some(message);
+        partial.updated_at = now;
+        if partial.stripes.iter().any(|stripe| stripe.is_none()) {
+            return Ok(None);
+        }
+
+        let stripes = self
+            .partial
+            .remove(&key)
+            .map(|p| p.stripes)
+            .unwrap_or_default();
+        let mut stripes = stripes.into_iter().flatten();
+        let Some(mut whole) = stripes.next() else {
+            return Ok(None);
+        };
+        for stripe in stripes {
+            whole.payload.extend_from_slice(&stripe.payload);
+        }
+        whole.extensions.remove(STRIPE_EXTENSION);
+        self.delivered.insert(key, now);
+        Ok(Some(whole))
+    }
+
+    // forgets delivered ids and gives up on stripes older than the window
+    pub fn expire(&mut self, now: Instant) {
+        let window = self.window;
+        self.delivered
+            .retain(|_, at| now.saturating_duration_since(*at) < window);
+        self.partial
+            .retain(|_, partial| now.saturating_duration_since(partial.started) < window);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    fn drop_stalest(&mut self) {
+        let stalest = self
+            .partial
+            .iter()
+            .min_by_key(|(_, partial)| partial.updated_at)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = stalest {
+            log::warn!(
+                "Too many incomplete striped messages, dropping {} from {}",
+                key.1,
+                key.0
+            );
+            self.partial.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use super::{
+    peer::PeerID,
+    routing::{Outgoing, RoutingStrategy},
+};
+use crate::mesh::{routing_frame::Frame, RouteError, RouteReply, RouteRequest};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// how long a discovered route stays cached without being used
+pub const DEFAULT_ROUTE_LIFETIME: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedRoute {
+    pub next_hop: PeerID,
+    pub hop_count: u32,
+    pub sequence: u64,
+    pub valid: bool,
+    pub expires_at: Instant,
+}
+
+// AODV style routing, routes are only looked for when somebody needs them
+#[derive(Debug)]
+pub struct OnDemandRouting {
+    pub id: PeerID,
+    pub sequence: u64,
+    pub request_id: u32,
+    pub route_lifetime: Duration,
+    pub routes: HashMap<PeerID, CachedRoute>,
+    // (origin, request id) of requests we already handled, floods are only relayed once
+    pub seen_requests: HashMap<(PeerID, u32), Instant>,
+}
+
+impl OnDemandRouting {
+    pub fn new(id: PeerID) -> Self {
+        Self::with_lifetime(id, DEFAULT_ROUTE_LIFETIME)
+    }
+
+    pub fn with_lifetime(id: PeerID, route_lifetime: Duration) -> Self {
+        Self {
+            id,
+            sequence: 0,
+            request_id: 0,
+            route_lifetime,
+            routes: HashMap::new(),
+            seen_requests: HashMap::new(),
+        }
+    }
+
+    pub fn get_route(&self, destination: &PeerID) -> Option<&CachedRoute> {
+        self.routes.get(destination)
+    }
+
+    // keeps the fresher or, for the same sequence, the shorter route
+    fn update_route(
+        &mut self,
+        destination: PeerID,
+        next_hop: PeerID,
+        hop_count: u32,
+        sequence: u64,
+    ) {
+        if destination == self.id {
+            return;
+        }
+
+        let candidate = CachedRoute {
+            next_hop,
+            hop_count,
+            sequence,
+            valid: true,
+            expires_at: Instant::now() + self.route_lifetime,
+        };
+        match self.routes.get_mut(&destination) {
+            Some(current)
+                if current.valid
+                    && (current.sequence > sequence
+                        || (current.sequence == sequence && current.hop_count <= hop_count)) =>
+            {
+                // still refresh the lifetime when the same route is confirmed again
+                if current.next_hop == candidate.next_hop {
+                    current.expires_at = candidate.expires_at;
+                }
+            }
+            _ => {
+                self.routes.insert(destination, candidate);
+            }
+        }
+    }
+
+    fn valid_route(&self, destination: &PeerID) -> Option<&CachedRoute> {
+        self.routes
+            .get(destination)
+            .filter(|route| route.valid && route.expires_at > Instant::now())
+    }
+
+    fn handle_request(&mut self, from: &PeerID, request: RouteRequest) -> Vec<Outgoing> {
+        let origin = PeerID(request.origin.clone());
+        if origin == self.id {
+            return vec![];
+        }
+        let key = (origin.clone(), request.request_id);
+        if self.seen_requests.contains_key(&key) {
+            return vec![];
+        }
+        self.seen_requests.insert(key, Instant::now());
+
+        // reverse path towards the origin, used by the reply
+        self.update_route(
+            origin,
+            from.clone(),
+            request.hop_count + 1,
+            request.origin_sequence,
+        );
+
+        if request.destination == self.id.0 {
+            self.sequence = self.sequence.max(request.destination_sequence) + 1;
+            let reply = RouteReply {
+                origin: request.origin,
+                destination: self.id.0.clone(),
+                destination_sequence: self.sequence,
+                hop_count: 0,
+            };
+            return vec![Outgoing::Unicast(from.clone(), Frame::Reply(reply))];
+        }
+
+        let destination = PeerID(request.destination.clone());
+        if let Some(route) = self.valid_route(&destination) {
+            if route.sequence >= request.destination_sequence {
+                // we know a fresh enough route, answer on behalf of the destination
+                let reply = RouteReply {
+                    origin: request.origin,
+                    destination: request.destination,
+                    destination_sequence: route.sequence,
+                    hop_count: route.hop_count,
+                };
+                return vec![Outgoing::Unicast(from.clone(), Frame::Reply(reply))];
+            }
+        }
+
+        vec![Outgoing::Broadcast(Frame::Request(RouteRequest {
+            hop_count: request.hop_count + 1,
+            ..request
+        }))]
+    }
+
+    fn handle_reply(&mut self, from: &PeerID, reply: RouteReply) -> Vec<Outgoing> {
+        self.update_route(
+            PeerID(reply.destination.clone()),
+            from.clone(),
+            reply.hop_count + 1,
+            reply.destination_sequence,
+        );
+
+        let origin = PeerID(reply.origin.clone());
+        if origin == self.id {
+            return vec![];
+        }
+        match self.valid_route(&origin) {
+            Some(route) => vec![Outgoing::Unicast(
+                route.next_hop.clone(),
+                Frame::Reply(RouteReply {
+                    hop_count: reply.hop_count + 1,
+                    ..reply
+                }),
+            )],
+            None => {
+                log::warn!("No reverse route to {} for route reply", origin.0);
+                vec![]
+            }
+        }
+    }
+
+    // marks routes as broken, returns the destinations that were reachable until now
+    fn invalidate(&mut self, broken: impl Fn(&PeerID, &CachedRoute) -> bool) -> Vec<String> {
+        let deleted_at = Instant::now() + self.route_lifetime;
+        let mut unreachable = vec![];
+        for (destination, route) in self.routes.iter_mut() {
+            if route.valid && broken(destination, route) {
+                route.valid = false;
+                // a newer route must beat the one we just lost, so it is kept for another
+                // lifetime to put its sequence into the next request
+                route.sequence += 1;
+                route.expires_at = deleted_at;
+                unreachable.push(destination.0.clone());
+            }
+        }
+        unreachable
+    }
+
+    fn route_error(unreachable: Vec<String>) -> Vec<Outgoing> {
+        if unreachable.is_empty() {
+            return vec![];
+        }
+        vec![Outgoing::Broadcast(Frame::Error(RouteError {
+            unreachable,
+        }))]
+    }
+}
+
+impl RoutingStrategy for OnDemandRouting {
+    fn next_hop(&mut self, destination: &PeerID) -> Option<PeerID> {
+        let lifetime = self.route_lifetime;
+        let route = self
+            .routes
+            .get_mut(destination)
+            .filter(|route| route.valid && route.expires_at > Instant::now())?;
+        // active routes are kept alive
+        route.expires_at = Instant::now() + lifetime;
+        Some(route.next_hop.clone())
+    }
+
+    fn on_control(&mut self, from: &PeerID, frame: Frame) -> Vec<Outgoing> {
+        match frame {
+            Frame::Request(request) => self.handle_request(from, request),
+            Frame::Reply(reply) => self.handle_reply(from, reply),
+            Frame::Error(error) => {
+                let unreachable = self.invalidate(|destination, route| {
+                    &route.next_hop == from && error.unreachable.contains(&destination.0)
+                });
+                Self::route_error(unreachable)
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_route_missing(&mut self, destination: &PeerID) -> Vec<Outgoing> {
+        self.sequence += 1;
+        self.request_id = self.request_id.wrapping_add(1);
+        self.seen_requests
+            .insert((self.id.clone(), self.request_id), Instant::now());
+
+        let destination_sequence = self
+            .routes
+            .get(destination)
+            .map(|route| route.sequence)
+            .unwrap_or(0);
+        vec![Outgoing::Broadcast(Frame::Request(RouteRequest {
+            origin: self.id.0.clone(),
+            origin_sequence: self.sequence,
+            destination: destination.0.clone(),
+            destination_sequence,
+            request_id: self.request_id,
+            hop_count: 0,
+        }))]
+    }
+
+    fn on_link_broken(&mut self, neighbour: &PeerID) -> Vec<Outgoing> {
+        let unreachable = self.invalidate(|destination, route| {
+            &route.next_hop == neighbour || destination == neighbour
+        });
+        Self::route_error(unreachable)
+    }
+
+    fn on_tick(&mut self, _interval: Duration) -> Vec<Outgoing> {
+        let now = Instant::now();
+        let lifetime = self.route_lifetime;
+        for route in self.routes.values_mut() {
+            // unused routes go invalid first and are only deleted a lifetime later
+            if route.valid && route.expires_at <= now {
+                route.valid = false;
+                route.expires_at = now + lifetime;
+            }
+        }
+        self.routes.retain(|_, route| route.expires_at > now);
+        self.seen_requests
+            .retain(|_, seen| now.duration_since(*seen) < lifetime);
+        vec![]
+    }
+}
@@ -1,40 +1,395 @@
-use std::{collections::HashMap, net::SocketAddr, time::Instant};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{sync::broadcast, task::JoinHandle};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub enum LinkType {
-    Wifi,
-    Ble,
+use super::probe::LinkMetrics;
+use crate::{link::registry::TransportRegistry, MeshError};
+
+// id of a transport, the built-in ones are below, others come from the transport registry
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LinkType(Cow<'static, str>);
+
+impl LinkType {
+    pub const WIFI: LinkType = LinkType(Cow::Borrowed("wifi"));
+    pub const BLE: LinkType = LinkType(Cow::Borrowed("ble"));
+    pub const TCP: LinkType = LinkType(Cow::Borrowed("tcp"));
+
+    pub fn new(id: impl Into<Cow<'static, str>>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for LinkType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct PeerID(pub String);
 
+// how recently we heard from a peer, dead peers are dropped from the store
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PeerState {
+    #[default]
+    Alive,
+    Suspect,
+    Dead,
+}
+
+// one address a peer can be reached at over one link, a peer may have several per link
+#[derive(Debug, Clone)]
+pub struct LinkEndpoint {
+    pub link: LinkType,
+    // in whatever form the link's dial takes
+    pub address: String,
+    pub last_seen: Instant,
+    pub metrics: LinkMetrics,
+}
+
+impl LinkEndpoint {
+    pub fn new(link: LinkType, address: impl Into<String>) -> Self {
+        Self {
+            link,
+            address: address.into(),
+            last_seen: Instant::now(),
+            metrics: LinkMetrics::default(),
+        }
+    }
+
+    pub fn is(&self, link: &LinkType, address: &str) -> bool {
+        self.link == *link && self.address == address
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PeerInfo {
     pub id: PeerID,
-    pub wifi_addr: Option<SocketAddr>,
-    pub ble_addr: Option<String>,
+    pub endpoints: Vec<LinkEndpoint>,
     pub last_seen: Instant,
     pub rtt_ms: Option<u32>,
     pub mtu: Option<usize>,
     pub loss_percent: Option<f32>,
+    // features the node announced, e.g. whether it accepts ble connections
+    pub capabilities: Vec<String>,
+    pub state: PeerState,
+}
+
+impl PeerInfo {
+    pub fn new(id: PeerID) -> Self {
+        Self {
+            id,
+            endpoints: Vec::new(),
+            last_seen: Instant::now(),
+            rtt_ms: None,
+            mtu: None,
+            loss_percent: None,
+            capabilities: Vec::new(),
+            state: PeerState::Alive,
+        }
+    }
+
+    pub fn with_endpoint(mut self, link: LinkType, address: impl Into<String>) -> Self {
+        self.endpoints.push(LinkEndpoint::new(link, address));
+        self
+    }
+
+    pub fn endpoints_on<'a>(
+        &'a self,
+        link: &'a LinkType,
+    ) -> impl Iterator<Item = &'a LinkEndpoint> {
+        self.endpoints
+            .iter()
+            .filter(move |endpoint| endpoint.link == *link)
+    }
+
+    pub fn endpoint(&self, link: &LinkType, address: &str) -> Option<&LinkEndpoint> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.is(link, address))
+    }
+
+    pub fn endpoint_mut(&mut self, link: &LinkType, address: &str) -> Option<&mut LinkEndpoint> {
+        self.endpoints
+            .iter_mut()
+            .find(|endpoint| endpoint.is(link, address))
+    }
+
+    // rtt and loss of the fastest endpoint, or the loss of any if none got through yet
+    fn summarise(&mut self) {
+        let best = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.metrics.srtt.is_some())
+            .min_by_key(|endpoint| endpoint.metrics.srtt);
+        match best {
+            Some(best) => {
+                self.rtt_ms = best.metrics.rtt_ms();
+                self.loss_percent = Some(best.metrics.loss_percent());
+            }
+            None => {
+                self.loss_percent = self
+                    .endpoints
+                    .iter()
+                    .filter(|endpoint| endpoint.metrics.probes_sent > 0)
+                    .map(|endpoint| endpoint.metrics.loss_percent())
+                    .reduce(f32::min)
+                    .or(self.loss_percent);
+            }
+        }
+    }
 }
 
-#[derive(Default, Debug)]
+// what subscribers hear about peers coming and going, with the peer as last seen
+#[derive(Clone, Debug)]
+pub enum PeerEvent {
+    Joined(PeerInfo),
+    Left(PeerInfo),
+}
+
+impl PeerEvent {
+    pub fn peer_id(&self) -> &PeerID {
+        match self {
+            PeerEvent::Joined(peer) | PeerEvent::Left(peer) => &peer.id,
+        }
+    }
+}
+
+// silence after which a peer becomes suspect and then dead
+#[derive(Clone, Copy, Debug)]
+pub struct LivenessConfig {
+    pub suspect_after: Duration,
+    pub dead_after: Duration,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            suspect_after: Duration::from_secs(10),
+            dead_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl LivenessConfig {
+    pub fn new(suspect_after: Duration, dead_after: Duration) -> Self {
+        Self {
+            suspect_after,
+            dead_after,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct PeerStore {
     pub peers: HashMap<PeerID, PeerInfo>,
+    pub liveness: LivenessConfig,
+    // transports endpoints may be on, addresses are stored the way their parser puts them
+    pub registry: TransportRegistry,
+    events: broadcast::Sender<PeerEvent>,
+}
+
+impl Default for PeerStore {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            peers: HashMap::new(),
+            liveness: LivenessConfig::default(),
+            registry: TransportRegistry::default(),
+            events,
+        }
+    }
 }
 
 impl PeerStore {
-    pub fn update_store(&mut self, info: PeerInfo) {
-        self.peers.insert(info.clone().id, info);
+    pub fn with_liveness(mut self, liveness: LivenessConfig) -> Self {
+        self.liveness = liveness;
+        self
+    }
+
+    pub fn with_registry(mut self, registry: TransportRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    // records a sighting, a peer we didn't know or had lost counts as joined,
+    // what was measured about a known peer is kept unless the sighting brings its own,
+    // endpoints it no longer mentions stay until they go stale
+    pub fn update_store(&mut self, mut info: PeerInfo) {
+        info.state = PeerState::Alive;
+        let registry = &self.registry;
+        info.endpoints.retain_mut(|endpoint| {
+            match registry.parse_address(&endpoint.link, &endpoint.address, None) {
+                Ok(address) => {
+                    endpoint.address = address;
+                    true
+                }
+                Err(e) => {
+                    log::debug!("Dropping endpoint of {}: {}", info.id.0, e);
+                    false
+                }
+            }
+        });
+        let previous = self.peers.get(&info.id);
+        if let Some(previous) = previous {
+            info.rtt_ms = info.rtt_ms.or(previous.rtt_ms);
+            info.mtu = info.mtu.or(previous.mtu);
+            info.loss_percent = info.loss_percent.or(previous.loss_percent);
+            for old in &previous.endpoints {
+                match info.endpoint_mut(&old.link, &old.address) {
+                    Some(endpoint) => endpoint.metrics = old.metrics.clone(),
+                    None => info.endpoints.push(old.clone()),
+                }
+            }
+        }
+        let joined = previous.is_none();
+        self.peers.insert(info.id.clone(), info.clone());
+        if joined {
+            log::info!("Peer {} joined", info.id.0);
+            let _ = self.events.send(PeerEvent::Joined(info));
+        }
+    }
+
+    // marks a known peer as heard from just now
+    pub fn touch(&mut self, id: &PeerID) {
+        if let Some(peer) = self.peers.get_mut(id) {
+            peer.last_seen = Instant::now();
+            peer.state = PeerState::Alive;
+        }
+    }
+
+    // stores a probe result and refreshes the peer's summary from its best endpoint
+    pub fn update_metrics(
+        &mut self,
+        id: &PeerID,
+        link: &LinkType,
+        address: &str,
+        metrics: LinkMetrics,
+    ) {
+        let Some(peer) = self.peers.get_mut(id) else {
+            return;
+        };
+        let Some(endpoint) = peer.endpoint_mut(link, address) else {
+            return;
+        };
+        if metrics.consecutive_failures == 0 {
+            endpoint.last_seen = Instant::now();
+        }
+        endpoint.metrics = metrics;
+        peer.summarise();
+    }
+
+    // a send to the endpoint succeeded or failed
+    pub fn record_delivery(
+        &mut self,
+        id: &PeerID,
+        link: &LinkType,
+        address: &str,
+        delivered: bool,
+    ) {
+        let Some(endpoint) = self
+            .peers
+            .get_mut(id)
+            .and_then(|peer| peer.endpoint_mut(link, address))
+        else {
+            return;
+        };
+        endpoint.metrics.record_delivery(delivered);
+        if delivered {
+            endpoint.last_seen = Instant::now();
+        }
     }
 
     pub fn get_peer(&self, id: PeerID) -> Option<&PeerInfo> {
         self.peers.get(&id)
     }
 
+    // same as get_peer for callers that can't go on without the peer
+    pub fn peer(&self, id: &PeerID) -> Result<&PeerInfo, MeshError> {
+        self.peers
+            .get(id)
+            .ok_or_else(|| MeshError::UnknownPeer(id.clone()))
+    }
+
     pub fn get_all_peers(&self) -> Vec<PeerInfo> {
         self.peers.values().cloned().collect()
     }
+
+    pub fn state(&self, id: &PeerID) -> PeerState {
+        self.peers
+            .get(id)
+            .map(|peer| peer.state)
+            .unwrap_or(PeerState::Dead)
+    }
+
+    // joined/left events from now on, a lagging subscriber misses the oldest ones
+    pub fn subscribe(&self) -> broadcast::Receiver<PeerEvent> {
+        self.events.subscribe()
+    }
+
+    // applies the thresholds as of now, dead peers are removed and reported as left,
+    // returns every peer whose state changed
+    pub fn reap(&mut self, now: Instant) -> Vec<(PeerID, PeerState)> {
+        let liveness = self.liveness;
+        let mut changed = Vec::new();
+
+        for peer in self.peers.values_mut() {
+            let silent = now.saturating_duration_since(peer.last_seen);
+            let state = if silent >= liveness.dead_after {
+                PeerState::Dead
+            } else if silent >= liveness.suspect_after {
+                PeerState::Suspect
+            } else {
+                PeerState::Alive
+            };
+            // a peer on its way out keeps its endpoints so Left can say what to close
+            if state != PeerState::Dead {
+                peer.endpoints.retain(|endpoint| {
+                    now.saturating_duration_since(endpoint.last_seen) < liveness.dead_after
+                });
+            }
+            if state != peer.state {
+                log::info!("Peer {} is now {:?}", peer.id.0, state);
+                peer.state = state;
+                changed.push((peer.id.clone(), state));
+            }
+        }
+
+        for (id, state) in &changed {
+            if *state == PeerState::Dead {
+                if let Some(peer) = self.peers.remove(id) {
+                    let _ = self.events.send(PeerEvent::Left(peer));
+                }
+            }
+        }
+        changed
+    }
+
+    // one reaper per store, it exits once the store is dropped
+    pub fn spawn_reaper(store: &Arc<Mutex<PeerStore>>) -> JoinHandle<()> {
+        tokio::spawn(Self::reaper(Arc::downgrade(store)))
+    }
+
+    async fn reaper(store: Weak<Mutex<PeerStore>>) {
+        loop {
+            let interval = match store.upgrade() {
+                Some(store) => {
+                    let mut store = store.lock().unwrap();
+                    store.reap(Instant::now());
+                    store.liveness.suspect_after / 2
+                }
+                None => return,
+            };
+            tokio::time::sleep(interval.max(Duration::from_millis(100))).await;
+        }
+    }
 }
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// ble scans for seconds before it connects, so dials get far longer than the probe itself
+pub const DEFAULT_PROBE_DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how often peers are probed and how quickly the averages follow new samples
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeConfig {
+    pub interval: Duration,
+    // a probe not acknowledged within this counts as lost
+    pub timeout: Duration,
+    // a peer not dialed within this counts as lost, the dial isn't part of the round trip
+    pub dial_timeout: Duration,
+    // weights of a new sample, the defaults are the ones TCP uses for srtt and rttvar
+    pub rtt_alpha: f64,
+    pub jitter_beta: f64,
+    pub loss_alpha: f64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_PROBE_INTERVAL,
+            timeout: DEFAULT_PROBE_TIMEOUT,
+            dial_timeout: DEFAULT_PROBE_DIAL_TIMEOUT,
+            rtt_alpha: 0.125,
+            jitter_beta: 0.25,
+            loss_alpha: 0.1,
+        }
+    }
+}
+
+impl ProbeConfig {
+    pub fn with_interval(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.interval = interval;
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_dial_timeout(mut self, dial_timeout: Duration) -> Self {
+        self.dial_timeout = dial_timeout;
+        self
+    }
+
+    pub fn with_weights(mut self, rtt_alpha: f64, jitter_beta: f64, loss_alpha: f64) -> Self {
+        self.rtt_alpha = rtt_alpha;
+        self.jitter_beta = jitter_beta;
+        self.loss_alpha = loss_alpha;
+        self
+    }
+}
+
+// smoothed quality of one link to one peer
+#[derive(Debug, Clone, Default)]
+pub struct LinkMetrics {
+    pub srtt: Option<Duration>,
+    // smoothed deviation of samples from srtt
+    pub jitter: Duration,
+    // smoothed share of lost probes, 0.0 to 1.0
+    pub loss: f64,
+    pub probes_sent: u64,
+    pub probes_lost: u64,
+    pub last_probe: Option<Instant>,
+    // probes and sends that failed since the last one that got through
+    pub consecutive_failures: u32,
+}
+
+impl LinkMetrics {
+    pub fn record_rtt(&mut self, rtt: Duration, config: &ProbeConfig) {
+        match self.srtt {
+            None => {
+                // first sample, as in RFC 6298
+                self.srtt = Some(rtt);
+                self.jitter = rtt / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.abs_diff(rtt);
+                self.jitter = ewma(self.jitter, deviation, config.jitter_beta);
+                self.srtt = Some(ewma(srtt, rtt, config.rtt_alpha));
+            }
+        }
+        self.loss *= 1.0 - config.loss_alpha;
+        self.consecutive_failures = 0;
+        self.probes_sent += 1;
+        self.last_probe = Some(Instant::now());
+    }
+
+    pub fn record_loss(&mut self, config: &ProbeConfig) {
+        self.loss = self.loss * (1.0 - config.loss_alpha) + config.loss_alpha;
+        self.probes_sent += 1;
+        self.probes_lost += 1;
+        self.consecutive_failures += 1;
+        self.last_probe = Some(Instant::now());
+    }
+
+    // outcome of a regular send, only feeds the failure streak
+    pub fn record_delivery(&mut self, delivered: bool) {
+        if delivered {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures += 1;
+        }
+    }
+
+    pub fn rtt_ms(&self) -> Option<u32> {
+        self.srtt.map(|srtt| srtt.as_millis() as u32)
+    }
+
+    pub fn loss_percent(&self) -> f32 {
+        (self.loss * 100.0) as f32
+    }
+}
+
+fn ewma(average: Duration, sample: Duration, weight: f64) -> Duration {
+    average.mul_f64(1.0 - weight) + sample.mul_f64(weight)
+}
@@ -1,27 +1,461 @@
-use super::peer::{PeerID, PeerStore};
-use crate::{link::link_trait::Link, MeshError};
-use std::sync::{Arc, Mutex};
+use super::{
+    distance_vector::RoutingTable,
+    health::Health,
+    peer::{LinkType, PeerEvent, PeerID, PeerStore},
+};
+use crate::{
+    envelope::{decode_message, encode_message},
+    link::link_trait::{Link, LinkConnection},
+    mesh::{routing_frame::Frame, MeshMessage, MessageKind, RoutingFrame},
+    MeshError,
+};
+use async_trait::async_trait;
+use prost::Message;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc, Notify},
+    time::Instant,
+};
 
-pub struct RoutingLayer<L: Link + Clone + Send + Sync + 'static> {
+// how long send waits for an on-demand strategy to discover a route
+pub const ROUTE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// control frame produced by a strategy, the routing layer takes care of delivering it
+#[derive(Clone, Debug)]
+pub enum Outgoing {
+    // to every direct neighbour
+    Broadcast(Frame),
+    // to a single direct neighbour
+    Unicast(PeerID, Frame),
+}
+
+// decides how routes are learnt, proactive tables and on-demand discovery both fit here
+pub trait RoutingStrategy: Send {
+    // neighbour to hand a message for destination to, if a route is known
+    fn next_hop(&mut self, destination: &PeerID) -> Option<PeerID>;
+
+    // a control frame arrived from the direct neighbour `from`
+    fn on_control(&mut self, from: &PeerID, frame: Frame) -> Vec<Outgoing>;
+
+    // a message has to be sent but there is no route for it yet
+    fn on_route_missing(&mut self, destination: &PeerID) -> Vec<Outgoing>;
+
+    // delivering a frame to a direct neighbour failed
+    fn on_link_broken(&mut self, neighbour: &PeerID) -> Vec<Outgoing>;
+
+    // called every `interval` by the routing layer
+    fn on_tick(&mut self, interval: Duration) -> Vec<Outgoing>;
+}
+
+impl RoutingStrategy for RoutingTable {
+    fn next_hop(&mut self, destination: &PeerID) -> Option<PeerID> {
+        RoutingTable::next_hop(self, destination)
+    }
+
+    fn on_control(&mut self, _from: &PeerID, frame: Frame) -> Vec<Outgoing> {
+        if let Frame::Advertisement(advertisement) = frame {
+            if self.apply_advertisement(&advertisement) {
+                log::debug!("Routing table updated from {}", advertisement.origin);
+            }
+        }
+        vec![]
+    }
+
+    fn on_route_missing(&mut self, _destination: &PeerID) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_link_broken(&mut self, neighbour: &PeerID) -> Vec<Outgoing> {
+        // the next advertisement carries the broken routes
+        self.invalidate_next_hop(neighbour);
+        vec![]
+    }
+
+    fn on_tick(&mut self, interval: Duration) -> Vec<Outgoing> {
+        // routes not refreshed for a few rounds are considered broken
+        self.expire(interval * 3);
+        vec![Outgoing::Broadcast(Frame::Advertisement(
+            self.advertisement(),
+        ))]
+    }
+}
+
+// how the routing layer reaches its direct neighbours, a single link or all the links of a
+// MultiLinkManager
+#[async_trait]
+pub trait NeighbourSender: Clone + Send + Sync + 'static {
+    // peers a message can be handed to directly
+    fn neighbours(&self) -> Vec<PeerID>;
+
+    fn is_neighbour(&self, id: &PeerID) -> bool;
+
+    async fn send_to(&self, neighbour: &PeerID, message: &MeshMessage) -> Result<(), MeshError>;
+
+    // messages from neighbours until the receiver is dropped, whatever stops receiving
+    // is reported to health
+    fn incoming(&self, health: &Health) -> mpsc::Receiver<MeshMessage>;
+}
+
+// one link, neighbours are the peers in the store with an endpoint on it
+#[derive(Clone)]
+pub struct SingleLink<L: Link + Clone + Send + Sync + 'static> {
     pub link: L,
+    pub link_type: LinkType,
     pub peer_store: Arc<Mutex<PeerStore>>,
 }
 
-impl<L: Link + Clone + Send + Sync + 'static> RoutingLayer<L> {
-    pub fn new(link: L, peer_store: Arc<Mutex<PeerStore>>) -> Self {
-        Self { link, peer_store }
+impl<L: Link + Clone + Send + Sync + 'static> SingleLink<L> {
+    fn address(&self, id: &PeerID) -> Option<String> {
+        let store = self.peer_store.lock().unwrap();
+        let peer = store.get_peer(id.clone())?;
+        let endpoint = peer.endpoints_on(&self.link_type).next()?;
+        Some(endpoint.address.clone())
     }
 
-    pub async fn send(&self, peer_id: PeerID, data: &[u8]) -> Result<(), MeshError> {
+    async fn receive_loop(
+        connection: Box<dyn LinkConnection + Send + Sync>,
+        tx: mpsc::Sender<MeshMessage>,
+    ) {
+        loop {
+            let data = tokio::select! {
+                received = connection.receive() => match received {
+                    Ok(data) => data,
+                    Err(_) => return,
+                },
+                _ = tx.closed() => return,
+            };
+            match decode_message(&data) {
+                Ok(message) => {
+                    if tx.send(message).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => log::warn!("Dropping incoming message: {}", e),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<L: Link + Clone + Send + Sync + 'static> NeighbourSender for SingleLink<L> {
+    fn neighbours(&self) -> Vec<PeerID> {
         let store = self.peer_store.lock().unwrap();
-        if let Some(peer_info) = store.get_peer(peer_id.clone()) {
-            if let Some(wifi_addr) = peer_info.wifi_addr {
-                let addr = wifi_addr.to_string();
-                let connection = self.link.dial(&addr).await.unwrap();
-                connection.send(data).await.unwrap();
-                log::info!("Sent message to {}", peer_id.0);
+        store
+            .get_all_peers()
+            .into_iter()
+            .filter(|peer| peer.endpoints_on(&self.link_type).next().is_some())
+            .map(|peer| peer.id)
+            .collect()
+    }
+
+    fn is_neighbour(&self, id: &PeerID) -> bool {
+        self.address(id).is_some()
+    }
+
+    async fn send_to(&self, neighbour: &PeerID, message: &MeshMessage) -> Result<(), MeshError> {
+        let address = self
+            .address(neighbour)
+            .ok_or_else(|| MeshError::UnknownPeer(neighbour.clone()))?;
+        let connection = self.link.dial(&address).await?;
+        connection.send(&encode_message(message)).await
+    }
+
+    fn incoming(&self, health: &Health) -> mpsc::Receiver<MeshMessage> {
+        let (tx, rx) = mpsc::channel(64);
+        let (link, health) = (self.link.clone(), health.clone());
+        tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = link.accept() => accepted,
+                    _ = tx.closed() => return,
+                };
+                match accepted {
+                    Ok(connection) => {
+                        tokio::spawn(Self::receive_loop(connection, tx.clone()));
+                    }
+                    Err(e) => {
+                        let reason = format!("stopped accepting connections: {}", e);
+                        health.stopped("routing accept", reason);
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
+
+#[derive(Clone)]
+pub struct RoutingLayer<S: NeighbourSender> {
+    pub id: PeerID,
+    pub sender: S,
+    pub peer_store: Arc<Mutex<PeerStore>>,
+    pub strategy: Arc<Mutex<Box<dyn RoutingStrategy>>>,
+    // where a single link reports that it stopped accepting
+    pub health: Health,
+    // raised after every control frame, the strategy may have installed routes
+    routes: Arc<Notify>,
+}
+
+impl<L: Link + Clone + Send + Sync + 'static> RoutingLayer<SingleLink<L>> {
+    // proactive distance vector routing over a single quic link
+    pub fn new(id: PeerID, link: L, peer_store: Arc<Mutex<PeerStore>>) -> Self {
+        let table = RoutingTable::new(id.clone());
+        Self::with_strategy(id, link, peer_store, Box::new(table))
+    }
+
+    // a single link reaches the peers with wi-fi endpoints
+    pub fn with_strategy(
+        id: PeerID,
+        link: L,
+        peer_store: Arc<Mutex<PeerStore>>,
+        strategy: Box<dyn RoutingStrategy>,
+    ) -> Self {
+        let sender = SingleLink {
+            link,
+            link_type: LinkType::WIFI,
+            peer_store: peer_store.clone(),
+        };
+        Self::with_sender(id, sender, peer_store, strategy)
+    }
+}
+
+impl<S: NeighbourSender> RoutingLayer<S> {
+    pub fn with_sender(
+        id: PeerID,
+        sender: S,
+        peer_store: Arc<Mutex<PeerStore>>,
+        strategy: Box<dyn RoutingStrategy>,
+    ) -> Self {
+        Self {
+            id,
+            sender,
+            peer_store,
+            strategy: Arc::new(Mutex::new(strategy)),
+            health: Health::default(),
+            routes: Default::default(),
+        }
+    }
+
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub async fn send(&self, peer_id: PeerID, data: &[u8]) -> Result<(), MeshError> {
+        let message = MeshMessage::data(&self.id, &peer_id, data.to_vec());
+        self.forward(&message).await?;
+        log::info!("Sent message to {}", peer_id.0);
+        Ok(())
+    }
+
+    // the neighbour a message for destination is handed to, direct neighbours win,
+    // everything else is up to the strategy
+    pub fn next_hop(&self, destination: &PeerID) -> Option<PeerID> {
+        if self.sender.is_neighbour(destination) {
+            return Some(destination.clone());
+        }
+        self.strategy.lock().unwrap().next_hop(destination)
+    }
+
+    fn neighbours(&self) -> Vec<PeerID> {
+        let mut neighbours = self.sender.neighbours();
+        neighbours.retain(|id| *id != self.id);
+        neighbours
+    }
+
+    // lets on-demand strategies look for a route, waits until one shows up or we time out
+    async fn discover_route(&self, destination: &PeerID) -> Option<PeerID> {
+        let outgoing = self.strategy.lock().unwrap().on_route_missing(destination);
+        if outgoing.is_empty() {
+            return None;
+        }
+        self.dispatch(outgoing).await;
+
+        let deadline = Instant::now() + ROUTE_DISCOVERY_TIMEOUT;
+        loop {
+            // registered before looking so a route installed in between isn't missed
+            let installed = self.routes.notified();
+            tokio::pin!(installed);
+            installed.as_mut().enable();
+            if let Some(next_hop) = self.strategy.lock().unwrap().next_hop(destination) {
+                return Some(next_hop);
+            }
+            if tokio::time::timeout_at(deadline, installed).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    // straight to a neighbour, through the hop the strategy picks otherwise or when that
+    // failed, the direct failure is what's reported if there is no other way
+    async fn forward(&self, message: &MeshMessage) -> Result<(), MeshError> {
+        let destination = message.destination_id();
+        let mut direct = None;
+        if self.sender.is_neighbour(&destination) {
+            match self.sender.send_to(&destination, message).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::debug!("{} not reachable directly: {}", destination.0, e);
+                    self.link_broken(&destination).await;
+                    direct = Some(e);
+                }
             }
         }
+
+        let known = self.strategy.lock().unwrap().next_hop(&destination);
+        let next_hop = match known {
+            Some(next_hop) => Some(next_hop),
+            None => self.discover_route(&destination).await,
+        };
+        let next_hop = match next_hop {
+            Some(next_hop) if next_hop != destination => next_hop,
+            _ => return Err(direct.unwrap_or(MeshError::NoRoute(destination))),
+        };
+        if let Err(e) = self.sender.send_to(&next_hop, message).await {
+            self.link_broken(&next_hop).await;
+            return Err(e);
+        }
+        log::debug!("Forwarded message for {} via {}", destination.0, next_hop.0);
         Ok(())
     }
+
+    async fn link_broken(&self, neighbour: &PeerID) {
+        let outgoing = self.strategy.lock().unwrap().on_link_broken(neighbour);
+        self.dispatch(outgoing).await;
+    }
+
+    // delivers control frames, neighbours we can't reach are reported back to the strategy
+    async fn dispatch(&self, outgoing: Vec<Outgoing>) {
+        let mut queue = VecDeque::from(outgoing);
+        while let Some(out) = queue.pop_front() {
+            let (targets, frame) = match out {
+                Outgoing::Broadcast(frame) => (self.neighbours(), frame),
+                Outgoing::Unicast(id, frame) => (vec![id], frame),
+            };
+
+            let payload = RoutingFrame { frame: Some(frame) }.encode_to_vec();
+            for id in targets {
+                // control frames only ever travel a single hop
+                let message =
+                    MeshMessage::new(MessageKind::Routing, &self.id, &id, payload.clone())
+                        .with_ttl(1);
+                if let Err(e) = self.sender.send_to(&id, &message).await {
+                    log::warn!("Failed to send control frame to {}: {}", id.0, e);
+                    queue.extend(self.strategy.lock().unwrap().on_link_broken(&id));
+                }
+            }
+        }
+    }
+
+    // handles a single message received from a neighbour, returns it if we are the destination
+    pub async fn handle_message(
+        &self,
+        mut message: MeshMessage,
+    ) -> Result<Option<MeshMessage>, MeshError> {
+        match message.kind() {
+            MessageKind::Data => {
+                if message.destination == self.id.0 {
+                    return Ok(Some(message));
+                }
+                if message.ttl <= 1 {
+                    log::warn!(
+                        "Dropping message from {} to {}: ttl expired",
+                        message.source,
+                        message.destination
+                    );
+                    return Ok(None);
+                }
+                message.ttl -= 1;
+                self.forward(&message).await?;
+                Ok(None)
+            }
+            MessageKind::Routing => {
+                let frame = RoutingFrame::decode(&message.payload[..])?
+                    .frame
+                    .ok_or_else(|| MeshError::decode("empty routing frame"))?;
+                let from = message.source_id();
+                let outgoing = self.strategy.lock().unwrap().on_control(&from, frame);
+                self.routes.notify_waiters();
+                self.dispatch(outgoing).await;
+                Ok(None)
+            }
+            kind => {
+                log::debug!("Ignoring {:?} message from {}", kind, message.source);
+                Ok(None)
+            }
+        }
+    }
+
+    // receives from the neighbours, ticks the strategy every `interval` and hands messages
+    // addressed to us to the returned channel, everything stops once the receiver is dropped
+    pub fn start(&self, interval: Duration) -> mpsc::Receiver<MeshMessage> {
+        let (tx, rx) = mpsc::channel(64);
+
+        let mut incoming = self.sender.incoming(&self.health);
+        let routing = self.clone();
+        let (ticking, watching) = (tx.clone(), tx.clone());
+        tokio::spawn(async move {
+            loop {
+                let message = tokio::select! {
+                    message = incoming.recv() => match message {
+                        Some(message) => message,
+                        None => return,
+                    },
+                    _ = tx.closed() => return,
+                };
+                // each message on its own task, a relay waiting for a route discovery or a
+                // slow dial would otherwise hold up the control frames that finish it
+                let (routing, tx) = (routing.clone(), tx.clone());
+                tokio::spawn(async move {
+                    match routing.handle_message(message).await {
+                        Ok(Some(message)) => {
+                            let _ = tx.send(message).await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => log::warn!("Failed to handle mesh message: {}", e),
+                    }
+                });
+            }
+        });
+
+        let routing = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let outgoing = routing.strategy.lock().unwrap().on_tick(interval);
+                routing.dispatch(outgoing).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = ticking.closed() => return,
+                }
+            }
+        });
+
+        // a neighbour the store gave up on is a broken link to the strategy, the task holds
+        // the store too so it has to stop with the rest or neither is ever freed
+        let mut events = self.peer_store.lock().unwrap().subscribe();
+        let routing = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = watching.closed() => return,
+                };
+                match event {
+                    Ok(PeerEvent::Left(peer)) => routing.link_broken(&peer.id).await,
+                    Ok(PeerEvent::Joined(_)) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("Routing layer missed {} peer events", missed);
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+
+        rx
+    }
 }
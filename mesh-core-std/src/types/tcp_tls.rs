@@ -0,0 +1,42 @@
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::WriteHalf,
+    net::TcpStream,
+    sync::{oneshot, watch, Mutex, Semaphore},
+};
+use tokio_rustls::TlsStream;
+
+use super::wifi_quic::IncomingFrames;
+
+// a connection that heard nothing for this many keepalive intervals is considered dead
+pub const KEEPALIVE_MISSES: u32 = 3;
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// data frames a sender keeps in flight before waiting for acks, and what the reader buffers
+pub const MAX_UNACKED_FRAMES: usize = 64;
+
+pub type TlsWriter = WriteHalf<TlsStream<TcpStream>>;
+
+// one per tls connection, clones share the stream and the receive queue
+#[derive(Debug, Clone)]
+pub struct TcpTlsLinkConnection {
+    pub remote_address: SocketAddr,
+    // frames bigger than this are refused in both directions
+    pub max_frame_size: usize,
+    // held for a whole frame so frames from concurrent senders never interleave
+    pub writer: Arc<Mutex<TlsWriter>>,
+    // filled by a background reader
+    pub incoming: Arc<Mutex<IncomingFrames>>,
+    // one per data frame sent and not yet acknowledged, in the order they went out
+    pub pending_acks: Arc<std::sync::Mutex<VecDeque<oneshot::Sender<()>>>>,
+    // one permit per data frame in flight, bounded by MAX_UNACKED_FRAMES
+    pub in_flight: Arc<Semaphore>,
+    pub closed: Arc<watch::Sender<bool>>,
+}
+
+// a connection we dialed, pooled for later dials of the same address
+#[derive(Debug)]
+pub struct DialedConnection {
+    pub connection: TcpTlsLinkConnection,
+    // whether accept handed it out yet, it waits here until it does so replies get read
+    pub accepted: bool,
+}
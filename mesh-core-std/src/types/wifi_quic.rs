@@ -1,8 +1,17 @@
+use crate::MeshError;
 use quinn::Connection;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc::Receiver, Mutex};
 
+// frames read from incoming streams, or the error that ended the connection
+pub type IncomingFrames = Receiver<Result<Vec<u8>, MeshError>>;
+
+// one per QUIC connection, clones share the same receive queue
 #[derive(Debug, Clone)]
 pub struct WifiQuicLinkConnection {
-    pub connection: Arc<Mutex<Connection>>,
+    pub connection: Connection,
+    // frames bigger than this are refused in both directions
+    pub max_frame_size: usize,
+    // filled by a background dispatcher
+    pub incoming: Arc<Mutex<IncomingFrames>>,
 }
@@ -1,4 +1,6 @@
-use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, KeyPair};
+use crate::MeshError;
+use quinn::rustls::pki_types::{pem::PemObject, CertificateDer};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, Issuer, KeyPair};
 use std::collections::HashSet;
 use time::{Duration, OffsetDateTime};
 
@@ -43,6 +45,20 @@ pub fn generate_certificate_authority() -> (Certificate, rcgen::Issuer<'static,
     (ca_cert, issuer)
 }
 
+// a ca made once and shared, so nodes started apart from each other trust each other
+pub fn load_certificate_authority(
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<(CertificateDer<'static>, Issuer<'static, KeyPair>), MeshError> {
+    let cert = CertificateDer::from_pem_slice(cert_pem.as_bytes())
+        .map_err(|e| MeshError::Config(format!("ca certificate: {}", e)))?;
+    let key =
+        KeyPair::from_pem(key_pem).map_err(|e| MeshError::Config(format!("ca key: {}", e)))?;
+    let issuer = Issuer::from_ca_cert_der(&cert, key)
+        .map_err(|e| MeshError::Config(format!("ca certificate: {}", e)))?;
+    Ok((cert, issuer))
+}
+
 pub fn generate_node_certs(
     issuer: &rcgen::Issuer<'static, KeyPair>,
     node_name: &str,
@@ -6,7 +6,7 @@ use quinn::{
 use rcgen::{Issuer, KeyPair};
 use std::{net::SocketAddr, sync::Arc};
 
-use crate::{utils::generate_node_certs, MeshError, StaticMeshError};
+use crate::{utils::generate_node_certs, MeshError};
 
 pub fn make_endpoint(
     addr: SocketAddr,
@@ -40,14 +40,22 @@ pub fn make_server_endpoint(
     addr: SocketAddr,
     node_name: &str,
     issuer: Issuer<'static, KeyPair>,
-) -> Result<(Endpoint, CertificateDer<'static>), StaticMeshError> {
+) -> Result<(Endpoint, CertificateDer<'static>), MeshError> {
     let (server_config, certificates) = configure_server(node_name, &issuer)?;
     let endpoint = Endpoint::server(server_config, addr)?;
 
     Ok((endpoint, certificates))
 }
 
-fn configure_client(server_certificates: &[&[u8]]) -> Result<ClientConfig, StaticMeshError> {
+// client side only, what WifiQuicLink::set_trusted swaps in
+pub fn make_client_config(
+    trusted_peers: &[CertificateDer<'static>],
+) -> Result<ClientConfig, MeshError> {
+    let trusted_peers: Vec<&[u8]> = trusted_peers.iter().map(|c| c.as_ref()).collect();
+    configure_client(&trusted_peers)
+}
+
+fn configure_client(server_certificates: &[&[u8]]) -> Result<ClientConfig, MeshError> {
     // rustls stores trusted certificates in RootCert
     let mut certificates = rustls::RootCertStore::empty();
     for cert in server_certificates {
@@ -62,7 +70,7 @@ fn configure_client(server_certificates: &[&[u8]]) -> Result<ClientConfig, Stati
 fn configure_server(
     node_name: &str,
     issuer: &Issuer<'static, KeyPair>,
-) -> Result<(ServerConfig, CertificateDer<'static>), StaticMeshError> {
+) -> Result<(ServerConfig, CertificateDer<'static>), MeshError> {
     // for now we are using self signed certicates without any Cetificate Authority
     let (node_cert, key_pair) = generate_node_certs(&issuer, node_name);
     let certificate_der = node_cert.clone().der().clone().into_owned();
@@ -1,2 +1,3 @@
 pub mod configure;
+pub mod pool;
 pub mod wifi_impl;
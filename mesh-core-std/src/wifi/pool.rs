@@ -0,0 +1,207 @@
+use crate::{types::wifi_quic::WifiQuicLinkConnection, MeshError};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+// connections unused for this long are closed by the reaper
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// dual stack sockets report ipv4 peers as ::ffff:a.b.c.d, keyed as plain ipv4 so dials and
+// accepts of the same peer meet in one entry
+pub fn canonical(addr: &SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+#[derive(Debug)]
+struct PooledConnection {
+    connection: WifiQuicLinkConnection,
+    // true if we dialed it, false if the peer did
+    outbound: bool,
+    // whether accept handed it out, dialed connections wait in the pool until it does
+    accepted: bool,
+    last_used: Instant,
+}
+
+// live QUIC connections keyed by the peer's address, shared by every dial on a link
+#[derive(Debug)]
+pub struct ConnectionPool {
+    pub local_addr: SocketAddr,
+    pub idle_timeout: Duration,
+    connections: Mutex<HashMap<SocketAddr, PooledConnection>>,
+    // one dial at a time per peer so concurrent senders share a single handshake
+    dial_locks: Mutex<HashMap<SocketAddr, Arc<tokio::sync::Mutex<()>>>>,
+    // raised whenever a dialed connection was added
+    dialed: Notify,
+}
+
+impl ConnectionPool {
+    pub fn new(local_addr: SocketAddr, idle_timeout: Duration) -> Self {
+        Self {
+            local_addr,
+            idle_timeout,
+            connections: Mutex::new(HashMap::new()),
+            dial_locks: Mutex::new(HashMap::new()),
+            dialed: Notify::new(),
+        }
+    }
+
+    // live connection to addr, if any
+    pub fn get(&self, addr: &SocketAddr) -> Option<WifiQuicLinkConnection> {
+        let addr = &canonical(addr);
+        let mut connections = self.connections.lock().unwrap();
+        match connections.get_mut(addr) {
+            Some(pooled) if !pooled.connection.is_closed() => {
+                pooled.last_used = Instant::now();
+                Some(pooled.connection.clone())
+            }
+            Some(_) => {
+                // closed underneath us, next dial reconnects
+                connections.remove(addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // reuses a live connection or dials a new one
+    pub async fn get_or_dial<F, Fut>(
+        &self,
+        addr: SocketAddr,
+        dial: F,
+    ) -> Result<WifiQuicLinkConnection, MeshError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<WifiQuicLinkConnection, MeshError>>,
+    {
+        let addr = canonical(&addr);
+        if let Some(connection) = self.get(&addr) {
+            return Ok(connection);
+        }
+
+        let lock = self
+            .dial_locks
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+
+        // someone else may have finished dialing while we waited
+        if let Some(connection) = self.get(&addr) {
+            return Ok(connection);
+        }
+
+        let connection = dial().await?;
+        self.insert(connection.clone(), true);
+        Ok(connection)
+    }
+
+    // a connection we dialed that wasn't handed out yet, waits for the next dial if there is none
+    pub async fn next_dialed(&self) -> WifiQuicLinkConnection {
+        loop {
+            let waiting = self
+                .connections
+                .lock()
+                .unwrap()
+                .values_mut()
+                .find(|pooled| !pooled.accepted && !pooled.connection.is_closed())
+                .map(|pooled| {
+                    pooled.accepted = true;
+                    pooled.connection.clone()
+                });
+            if let Some(connection) = waiting {
+                return connection;
+            }
+            self.dialed.notified().await;
+        }
+    }
+
+    // both sides dialing each other at once leaves two connections, both nodes keep the one
+    // dialed by the lower address so they converge on the same connection
+    fn preferred(local: &SocketAddr, remote: &SocketAddr, outbound: bool) -> bool {
+        outbound == (local < remote)
+    }
+
+    // our address as the peer sees it, a wildcard bind says nothing about that so the address
+    // the peer's connection arrived on stands in for it
+    fn observed_local(&self, inbound: &WifiQuicLinkConnection) -> SocketAddr {
+        let ip = match self.local_addr.ip() {
+            ip if !ip.is_unspecified() => ip,
+            ip => inbound.connection.local_ip().unwrap_or(ip),
+        };
+        canonical(&SocketAddr::new(ip, self.local_addr.port()))
+    }
+
+    // adds a connection, returns false if an existing preferred connection was kept instead
+    pub fn insert(&self, connection: WifiQuicLinkConnection, outbound: bool) -> bool {
+        let remote = canonical(&connection.remote_address());
+        let mut connections = self.connections.lock().unwrap();
+
+        if let Some(existing) = connections.get(&remote) {
+            if !existing.connection.is_closed() && existing.outbound != outbound {
+                let inbound = match outbound {
+                    true => &existing.connection,
+                    false => &connection,
+                };
+                let local = self.observed_local(inbound);
+                if Self::preferred(&local, &remote, existing.outbound) {
+                    return false;
+                }
+            }
+        }
+
+        connections.insert(
+            remote,
+            PooledConnection {
+                connection,
+                outbound,
+                // the peer's connections come out of accept to begin with
+                accepted: !outbound,
+                last_used: Instant::now(),
+            },
+        );
+        if outbound {
+            self.dialed.notify_one();
+        }
+        true
+    }
+
+    pub fn remove(&self, addr: &SocketAddr) -> Option<WifiQuicLinkConnection> {
+        self.connections
+            .lock()
+            .unwrap()
+            .remove(&canonical(addr))
+            .map(|pooled| pooled.connection)
+    }
+
+    // closes connections idle for longer than idle_timeout, returns how many were evicted
+    pub fn evict_idle(&self) -> usize {
+        let now = Instant::now();
+        let mut connections = self.connections.lock().unwrap();
+        let before = connections.len();
+        connections.retain(|addr, pooled| {
+            let closed = pooled.connection.is_closed();
+            let idle = now.duration_since(pooled.last_used) > self.idle_timeout;
+            if idle && !closed {
+                log::info!("Closing idle connection to {}", addr);
+                pooled.connection.close("idle");
+            }
+            !(idle || closed)
+        });
+        before - connections.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
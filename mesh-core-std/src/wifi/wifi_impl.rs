@@ -1,18 +1,52 @@
 use crate::{
-    link::link_trait::{Link, LinkConnection},
+    link::{
+        framing::{read_frame, write_frame, DEFAULT_MAX_FRAME_SIZE},
+        link_trait::{Link, LinkConnection},
+    },
     types::wifi_quic::WifiQuicLinkConnection,
     MeshError,
 };
-use quinn::{rustls::pki_types::CertificateDer, Endpoint};
+use quinn::{
+    rustls::pki_types::CertificateDer, ClientConfig, ConnectError, Connection, Endpoint,
+    SendDatagramError,
+};
 use rcgen::{Issuer, KeyPair};
-use std::{net::SocketAddr, sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock, Weak},
+    time::Duration,
+};
+use tokio::sync::{mpsc, Mutex};
 
-use super::configure::make_endpoint;
+use super::{
+    configure::{make_client_config, make_endpoint},
+    pool::{ConnectionPool, DEFAULT_IDLE_TIMEOUT},
+};
+
+// datagram payload that fits every path, quinn starts at a 1200 byte UDP payload
+pub const QUIC_MIN_DATAGRAM_SIZE: usize = 1162;
+
+// parses a dialable address, link-local ipv6 needs its interface as in [fe80::1%2]:4433
+pub fn parse_address(address: &str) -> Result<SocketAddr, MeshError> {
+    let addr = address.parse::<SocketAddr>()?;
+    if let SocketAddr::V6(v6) = addr {
+        if v6.ip().is_unicast_link_local() && v6.scope_id() == 0 {
+            return Err(MeshError::InvalidAddress(format!(
+                "link-local address {} has no interface scope",
+                address
+            )));
+        }
+    }
+    Ok(addr)
+}
 
 #[derive(Debug, Clone)]
 pub struct WifiQuicLink {
     pub endpoint: Endpoint,
+    pub pool: Arc<ConnectionPool>,
+    pub max_frame_size: usize,
+    // dials use it instead of the endpoint's default once set_trusted was called
+    client_config: Arc<RwLock<Option<ClientConfig>>>,
 }
 
 impl WifiQuicLink {
@@ -21,6 +55,16 @@ impl WifiQuicLink {
         trusted_peers: &[CertificateDer<'static>],
         node_name: &str,
         issuer: &Issuer<'static, KeyPair>,
+    ) -> Result<Self, MeshError> {
+        Self::with_idle_timeout(addr, trusted_peers, node_name, issuer, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    pub fn with_idle_timeout(
+        addr: &str,
+        trusted_peers: &[CertificateDer<'static>],
+        node_name: &str,
+        issuer: &Issuer<'static, KeyPair>,
+        idle_timeout: Duration,
     ) -> Result<Self, MeshError> {
         let endpoint = make_endpoint(
             addr.parse::<SocketAddr>()?,
@@ -28,91 +72,257 @@ impl WifiQuicLink {
             node_name,
             issuer,
         )?;
-        Ok(Self { endpoint })
+        let pool = Arc::new(ConnectionPool::new(endpoint.local_addr()?, idle_timeout));
+        tokio::spawn(Self::reaper(Arc::downgrade(&pool)));
+
+        Ok(Self {
+            endpoint,
+            pool,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            client_config: Default::default(),
+        })
+    }
+
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    // evicts idle connections until the link is dropped
+    async fn reaper(pool: Weak<ConnectionPool>) {
+        loop {
+            let interval = match pool.upgrade() {
+                Some(pool) => {
+                    pool.evict_idle();
+                    pool.idle_timeout / 2
+                }
+                None => return,
+            };
+            tokio::time::sleep(interval.max(Duration::from_millis(100))).await;
+        }
+    }
+
+    async fn connect(&self, addr: SocketAddr) -> Result<WifiQuicLinkConnection, MeshError> {
+        let client_config = self.client_config.read().unwrap().clone();
+        let dial_failed = |reason: String| MeshError::DialFailed {
+            address: addr.to_string(),
+            reason,
+        };
+        let connecting = match client_config {
+            Some(config) => self.endpoint.connect_with(config, addr, "localhost"),
+            None => self.endpoint.connect(addr, "localhost"),
+        };
+        // only a bad address or server name is the caller's fault, the rest is our endpoint
+        // failing to set up the connection
+        let connecting = connecting.map_err(|e| match e {
+            ConnectError::InvalidRemoteAddress(_) | ConnectError::InvalidServerName(_) => {
+                MeshError::InvalidAddress(format!("{}: {}", addr, e))
+            }
+            e => dial_failed(e.to_string()),
+        })?;
+        let connection = connecting.await.map_err(|e| match MeshError::from(e) {
+            MeshError::LinkClosed(reason) => dial_failed(reason),
+            e => e,
+        })?;
+        log::info!("Connection established to remote peer {}", addr);
+        Ok(WifiQuicLinkConnection::new(connection, self.max_frame_size))
     }
 }
 
 #[async_trait::async_trait]
 impl Link for WifiQuicLink {
+    // reuses a pooled connection to the address, dialing only if there is none
     async fn dial(
         &self,
         address: &str,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>
-    {
-        let connection = self
-            .endpoint
-            .connect(address.parse::<SocketAddr>()?, "localhost")?
-            .await?;
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        let addr = parse_address(address)?;
+        // an ipv4 socket can't reach ipv6 peers, binding [::] serves both
+        if addr.is_ipv6() && self.endpoint.local_addr()?.is_ipv4() {
+            return Err(MeshError::DialFailed {
+                address: addr.to_string(),
+                reason: "link bound to ipv4 can't dial ipv6".to_string(),
+            });
+        }
+        let connection = self.pool.get_or_dial(addr, || self.connect(addr)).await?;
+        Ok(Box::new(connection))
+    }
+
+    // yields every new connection, whether the peer dialed us or we dialed the peer,
+    // dialed ones the pool closed in the meantime are skipped
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        // Wait for an incoming handshake or one of our own dials
+        let incoming = tokio::select! {
+            connection = self.pool.next_dialed() => return Ok(Box::new(connection)),
+            incoming = self.endpoint.accept() => incoming,
+        };
+        let Some(incoming) = incoming else {
+            log::error!("Endpoint closed; no more incoming connections");
+            return Err(MeshError::closed(
+                "endpoint closed; no more incoming connections",
+            ));
+        };
 
-        Ok(Box::new(WifiQuicLinkConnection {
-            connection: Arc::new(Mutex::new(connection)),
-        }))
+        // Finish QUIC handshake
+        match incoming.await {
+            Ok(connection) => {
+                log::info!(
+                    "Connection established to remote peer {}",
+                    connection.remote_address()
+                );
+                let connection = WifiQuicLinkConnection::new(connection, self.max_frame_size);
+                self.pool.insert(connection.clone(), false);
+                Ok(Box::new(connection))
+            }
+            Err(e) => {
+                log::error!("Error establishing connection: {}", e);
+                Err(e.into())
+            }
+        }
     }
 
-    async fn accept(
-        &self,
-    ) -> Result<Box<dyn LinkConnection + Send + Sync>, Box<dyn std::error::Error + Send + Sync>>
-    {
-        // Wait for an incoming handshake
-
-        while let Some(connecting) = self.endpoint.accept().await {
-            // Finish QUIC handshake
-            match connecting.await {
-                Ok(connection) => {
-                    log::info!(
-                        "Connection established to remote peer {}",
-                        connection.remote_address()
-                    );
-                    return Ok(Box::new(WifiQuicLinkConnection {
-                        connection: Arc::new(Mutex::new(connection)),
-                    }));
+    fn mtu(&self) -> usize {
+        QUIC_MIN_DATAGRAM_SIZE
+    }
+
+    fn latency(&self) -> std::time::Duration {
+        Duration::from_millis(10)
+    }
+
+    // closes the pooled connection so a dead peer doesn't hold on to it until the idle timeout
+    fn forget(&self, address: &str) {
+        let Ok(addr) = address.parse::<SocketAddr>() else {
+            return;
+        };
+        if let Some(connection) = self.pool.remove(&addr) {
+            connection.close("peer left");
+        }
+    }
+
+    fn set_trusted(&self, trusted: &[CertificateDer<'static>]) -> Result<(), MeshError> {
+        *self.client_config.write().unwrap() = Some(make_client_config(trusted)?);
+        Ok(())
+    }
+}
+
+impl WifiQuicLinkConnection {
+    // starts reading incoming streams of the connection in the background
+    pub fn new(connection: Connection, max_frame_size: usize) -> Self {
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(Self::dispatch_streams(
+            connection.clone(),
+            max_frame_size,
+            tx,
+        ));
+        Self {
+            connection,
+            max_frame_size,
+            incoming: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    // every incoming stream gets its own reader so a slow or large message doesn't hold up
+    // the ones behind it, stops once the connection is gone or nobody can receive anymore
+    async fn dispatch_streams(
+        connection: Connection,
+        max_frame_size: usize,
+        tx: mpsc::Sender<Result<Vec<u8>, MeshError>>,
+    ) {
+        loop {
+            let stream = tokio::select! {
+                stream = connection.accept_bi() => stream,
+                _ = tx.closed() => return,
+            };
+
+            match stream {
+                Ok((_send, mut receive)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        match read_frame(&mut receive, max_frame_size).await {
+                            Ok(data) => {
+                                let _ = tx.send(Ok(data)).await;
+                            }
+                            Err(e) => log::warn!("Dropping incoming stream: {}", e),
+                        }
+                    });
                 }
                 Err(e) => {
-                    log::error!("Error establishing connection: {}", e);
-                    return Err(Box::new(e));
+                    let _ = tx.send(Err(e.into())).await;
+                    return;
                 }
-            };
+            }
         }
+    }
 
-        log::error!("Endpoint closed; no more incoming connections");
-        Err("endpoint closed; no more incoming connections".into())
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
     }
 
-    fn mtu(&self) -> usize {
-        100
+    pub fn is_closed(&self) -> bool {
+        self.connection.close_reason().is_some()
     }
 
-    fn latency(&self) -> std::time::Duration {
-        Duration::from_millis(10)
+    pub fn close(&self, reason: &str) {
+        self.connection.close(0u32.into(), reason.as_bytes());
     }
 }
 
 #[async_trait::async_trait]
 impl LinkConnection for WifiQuicLinkConnection {
-    async fn send(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let connection = self.connection.lock().await;
-        let (mut send, _receive) = connection.clone().open_bi().await?;
-        send.write_all(data).await?;
+    // opens a fresh stream per message, concurrent sends don't wait on each other
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError> {
+        let (mut send, _receive) = self.connection.open_bi().await?;
+        write_frame(&mut send, data, self.max_frame_size).await?;
         send.finish()?;
+        // wait for the peer to acknowledge, dropping the connection early discards the data
+        send.stopped().await?;
         log::info!("Data is successfully sent!");
         Ok(())
     }
 
-    async fn receive(&self) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-        let connection = self.connection.lock().await;
-        let bi_stream = connection.accept_bi().await?;
-        let (_send, mut receive) = bi_stream;
-
-        let mut buf = vec![0u8; 1024];
-        match receive.read(&mut buf).await {
-            Ok(_) => {
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        let mut incoming = self.incoming.lock().await;
+        match incoming.recv().await {
+            Some(Ok(data)) => {
                 log::info!("Data read successfully!");
-                Ok(buf)
+                Ok(data)
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 log::error!("Error occurred while reading data!");
-                Err(e.into())
+                Err(e)
             }
+            None => Err(MeshError::closed("connection closed")),
         }
     }
+
+    async fn send_datagram(&self, data: &[u8]) -> Result<(), MeshError> {
+        // waits for buffer space instead of dropping our own datagrams locally
+        let sent = self
+            .connection
+            .send_datagram_wait(data.to_vec().into())
+            .await;
+        sent.map_err(|e| match e {
+            SendDatagramError::TooLarge => MeshError::MtuExceeded {
+                size: data.len(),
+                limit: self.max_datagram_size().unwrap_or(0),
+            },
+            e => MeshError::LinkClosed(e.to_string()),
+        })
+    }
+
+    async fn receive_datagram(&self) -> Result<Vec<u8>, MeshError> {
+        Ok(self.connection.read_datagram().await?.to_vec())
+    }
+
+    // the path mtu quinn discovered, streams themselves aren't bound by it
+    fn mtu(&self) -> usize {
+        self.connection
+            .max_datagram_size()
+            .unwrap_or(QUIC_MIN_DATAGRAM_SIZE)
+    }
+
+    // grows past QUIC_MIN_DATAGRAM_SIZE once path MTU discovery finds a bigger path
+    fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
 }
@@ -0,0 +1,38 @@
+use mesh_core::types::ble_types::PeripheralCache;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+// stands in for a dialed connection, only its connected state matters here
+async fn connected(connection: Arc<AtomicBool>) -> bool {
+    connection.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn connected_peripherals_are_reused() {
+    let cache = PeripheralCache::default();
+    let connection = Arc::new(AtomicBool::new(true));
+    cache.insert("AA:BB:CC:DD:EE:FF", connection.clone());
+
+    let reused = cache.get("AA:BB:CC:DD:EE:FF", connected).await.unwrap();
+    assert!(Arc::ptr_eq(&reused, &connection));
+    assert!(cache.get("11:22:33:44:55:66", connected).await.is_none());
+    assert_eq!(cache.len(), 1);
+}
+
+#[tokio::test]
+async fn disconnected_peripherals_are_dialed_again() {
+    let cache = PeripheralCache::default();
+    let connection = Arc::new(AtomicBool::new(true));
+    cache.insert("AA:BB:CC:DD:EE:FF", connection.clone());
+
+    // the peripheral went away, the next dial has to scan for it
+    connection.store(false, Ordering::SeqCst);
+    assert!(cache.get("AA:BB:CC:DD:EE:FF", connected).await.is_none());
+    assert!(cache.is_empty());
+
+    cache.insert("AA:BB:CC:DD:EE:FF", connection);
+    assert!(cache.remove("AA:BB:CC:DD:EE:FF").is_some());
+    assert!(cache.is_empty());
+}
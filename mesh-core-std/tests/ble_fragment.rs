@@ -0,0 +1,163 @@
+use mesh_core::{
+    bluetooth::fragment::{
+        fragment, Fragmenter, Reassembler, DEFAULT_MAX_MESSAGE_SIZE, FIRST_FRAGMENT_HEADER_SIZE,
+        MAX_PENDING_MESSAGES,
+    },
+    types::ble_types::DEFAULT_MTU,
+};
+use proptest::prelude::*;
+use std::time::{Duration, Instant};
+
+fn reassemble_all(reassembler: &mut Reassembler, packets: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    packets
+        .iter()
+        .filter_map(|packet| reassembler.push(packet).unwrap())
+        .collect()
+}
+
+#[test]
+fn messages_complete_on_their_last_fragment() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let packets = fragmenter.fragment(&[5u8; 100]).unwrap();
+    assert!(packets.len() > 1);
+
+    let mut reassembler = Reassembler::default();
+    for packet in &packets[..packets.len() - 1] {
+        assert_eq!(reassembler.push(packet).unwrap(), None);
+    }
+    assert_eq!(reassembler.pending(), 1);
+    assert_eq!(
+        reassembler.push(packets.last().unwrap()).unwrap(),
+        Some(vec![5u8; 100])
+    );
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[test]
+fn empty_message_is_one_fragment() {
+    let packets = Fragmenter::new(DEFAULT_MTU).fragment(&[]).unwrap();
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].len(), FIRST_FRAGMENT_HEADER_SIZE);
+    assert_eq!(
+        Reassembler::default().push(&packets[0]).unwrap(),
+        Some(vec![])
+    );
+}
+
+#[test]
+fn interleaved_messages_keep_their_ids_apart() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let a = fragmenter.fragment(&[1u8; 50]).unwrap();
+    let b = fragmenter.fragment(&[2u8; 50]).unwrap();
+
+    let mut reassembler = Reassembler::default();
+    let mut delivered = Vec::new();
+    for (x, y) in a.iter().zip(b.iter()) {
+        delivered.extend(reassembler.push(x).unwrap());
+        delivered.extend(reassembler.push(y).unwrap());
+    }
+    assert_eq!(delivered, vec![vec![1u8; 50], vec![2u8; 50]]);
+}
+
+#[test]
+fn missing_or_reordered_fragments_drop_the_message() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let packets = fragmenter.fragment(&[3u8; 60]).unwrap();
+
+    let mut reassembler = Reassembler::default();
+    reassembler.push(&packets[0]).unwrap();
+    assert!(reassembler.push(&packets[2]).is_err());
+    assert_eq!(reassembler.pending(), 0);
+    // the rest of the broken message is ignored
+    assert!(reassembler.push(&packets[1]).is_err());
+
+    // the next message is unaffected
+    let packets = fragmenter.fragment(&[4u8; 60]).unwrap();
+    assert_eq!(
+        reassemble_all(&mut reassembler, &packets),
+        vec![vec![4u8; 60]]
+    );
+}
+
+#[test]
+fn stalled_messages_time_out() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let packets = fragmenter.fragment(&[6u8; 60]).unwrap();
+    let start = Instant::now();
+
+    let mut reassembler = Reassembler::new(DEFAULT_MAX_MESSAGE_SIZE, Duration::from_secs(1));
+    reassembler.push_at(&packets[0], start).unwrap();
+    assert_eq!(reassembler.expire(start + Duration::from_millis(500)), 0);
+    assert!(reassembler
+        .push_at(&packets[1], start + Duration::from_secs(3))
+        .is_err());
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU).with_max_message_size(32);
+    assert!(fragmenter.fragment(&[0u8; 33]).is_err());
+
+    let packets = fragment(&[0u8; 64], 0, DEFAULT_MTU, 64).unwrap();
+    let mut reassembler = Reassembler::new(32, Duration::from_secs(1));
+    assert!(reassembler.push(&packets[0]).is_err());
+    assert_eq!(reassembler.pending(), 0);
+
+    assert!(fragment(&[0u8; 4], 0, FIRST_FRAGMENT_HEADER_SIZE, 64).is_err());
+}
+
+#[test]
+fn pending_messages_are_bounded() {
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let mut reassembler = Reassembler::default();
+    for _ in 0..MAX_PENDING_MESSAGES * 2 {
+        let packets = fragmenter.fragment(&[0u8; 40]).unwrap();
+        reassembler.push(&packets[0]).unwrap();
+    }
+    assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES);
+}
+
+proptest! {
+    #[test]
+    fn fragments_round_trip(
+        data in proptest::collection::vec(any::<u8>(), 0..4096),
+        mtu in (FIRST_FRAGMENT_HEADER_SIZE + 1)..512usize,
+        id in any::<u16>(),
+    ) {
+        let packets = fragment(&data, id, mtu, DEFAULT_MAX_MESSAGE_SIZE).unwrap();
+        prop_assert!(packets.iter().all(|packet| packet.len() <= mtu));
+
+        let mut reassembler = Reassembler::default();
+        let delivered = reassemble_all(&mut reassembler, &packets);
+        prop_assert_eq!(delivered, vec![data]);
+        prop_assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn dropping_a_fragment_never_delivers_a_wrong_message(
+        data in proptest::collection::vec(any::<u8>(), 1..1024),
+        drop in any::<prop::sample::Index>(),
+    ) {
+        let mut packets = Fragmenter::new(DEFAULT_MTU).fragment(&data).unwrap();
+        packets.remove(drop.index(packets.len()));
+
+        let mut reassembler = Reassembler::default();
+        for packet in &packets {
+            if let Ok(Some(message)) = reassembler.push(packet) {
+                prop_assert_eq!(&message, &data);
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_packets_never_panic(
+        packets in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 0..64), 0..64),
+    ) {
+        let mut reassembler = Reassembler::new(1024, Duration::from_secs(1));
+        for packet in &packets {
+            let _ = reassembler.push(packet);
+        }
+        prop_assert!(reassembler.pending() <= MAX_PENDING_MESSAGES);
+    }
+}
@@ -0,0 +1,136 @@
+use mesh_core::{
+    bluetooth::{
+        fake::{FakeCentral, FakePeripheralBackend},
+        fragment::{Fragmenter, Reassembler, PACKET_DATAGRAM},
+    },
+    link::link_trait::{Link, LinkConnection},
+    types::ble_types::{mtu_packet, reassemble, BleLink, DEFAULT_MTU, MAX_MTU},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+const SERVICE: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+const CHARACTERISTIC: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+
+type Connection = Box<dyn LinkConnection + Send + Sync>;
+
+// accepts one fake central that negotiated mtu, consuming the mtu announcement
+async fn accept_central(mtu: usize) -> (FakeCentral, Connection) {
+    let backend = FakePeripheralBackend::new();
+    let link = BleLink::with_backend(SERVICE, CHARACTERISTIC, Arc::new(backend.clone()));
+
+    // nothing to connect to until accept starts advertising
+    assert!(backend.connect("AA:BB:CC:DD:EE:FF", SERVICE).is_err());
+
+    let server = tokio::spawn(async move { link.accept().await.unwrap() });
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while backend.advertising().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(backend.advertising(), Some((SERVICE, CHARACTERISTIC)));
+    assert!(backend.connect("AA:BB:CC:DD:EE:FF", Uuid::nil()).is_err());
+
+    let central = backend
+        .connect_with_mtu("AA:BB:CC:DD:EE:FF", SERVICE, mtu)
+        .unwrap();
+    let connection = server.await.unwrap();
+    assert_eq!(
+        central.notification().await.unwrap(),
+        mtu_packet(connection.mtu())
+    );
+    (central, connection)
+}
+
+async fn receive_notified(central: &FakeCentral) -> Vec<u8> {
+    let mut reassembler = Reassembler::default();
+    loop {
+        let packet = central.notification().await.unwrap();
+        assert!(packet.len() <= central.mtu);
+        if let Some(message) = reassembler.push(&packet).unwrap() {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn accept_serves_a_connecting_central() {
+    let (central, connection) = accept_central(DEFAULT_MTU).await;
+    assert_eq!(connection.mtu(), DEFAULT_MTU);
+
+    // central writes reach the accepted connection
+    central.write(&[PACKET_DATAGRAM, 1, 2, 3]).unwrap();
+    let datagram = tokio::time::timeout(Duration::from_secs(5), connection.receive_datagram())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(datagram, vec![1, 2, 3]);
+
+    // and our sends come back as notifications
+    connection.send_datagram(b"ping").await.unwrap();
+    assert_eq!(
+        central.notification().await.unwrap(),
+        [&[PACKET_DATAGRAM][..], b"ping"].concat()
+    );
+    assert!(connection.send_datagram(&[0u8; DEFAULT_MTU]).await.is_err());
+
+    // whole messages both ways, fragmented to the mtu
+    let fragmenter = Fragmenter::new(DEFAULT_MTU);
+    let message: Vec<u8> = (0..200u8).collect();
+    for packet in fragmenter.fragment(&message).unwrap() {
+        central.write(&packet).unwrap();
+    }
+    let received = tokio::time::timeout(Duration::from_secs(5), connection.receive())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, message);
+
+    connection.send(&message).await.unwrap();
+    assert_eq!(receive_notified(&central).await, message);
+}
+
+#[tokio::test]
+async fn fragments_follow_the_negotiated_mtu() {
+    let (central, connection) = accept_central(244).await;
+    assert_eq!(connection.mtu(), 244);
+    assert_eq!(connection.max_datagram_size(), Some(243));
+    connection.send_datagram(&[1u8; 243]).await.unwrap();
+    assert_eq!(central.notification().await.unwrap().len(), 244);
+
+    let message = vec![7u8; 2000];
+    connection.send(&message).await.unwrap();
+    assert_eq!(receive_notified(&central).await, message);
+
+    // stacks reporting more than an attribute can hold are capped
+    let (_, connection) = accept_central(4096).await;
+    assert_eq!(connection.mtu(), MAX_MTU);
+}
+
+#[tokio::test]
+async fn centrals_adopt_the_announced_mtu() {
+    let (tx, mut rx) = mpsc::channel(4);
+    let (datagram_tx, _datagram_rx) = mpsc::channel(4);
+    let fragmenter = Arc::new(Fragmenter::new(DEFAULT_MTU));
+
+    let big = Fragmenter::new(185);
+    let mut packets = vec![mtu_packet(185)];
+    packets.extend(big.fragment(&[3u8; 500]).unwrap());
+    let packets = Box::pin(tokio_stream::iter(packets));
+
+    reassemble(packets, tx, datagram_tx, fragmenter.clone()).await;
+    assert_eq!(fragmenter.mtu(), 185);
+    assert_eq!(rx.recv().await.unwrap(), vec![3u8; 500]);
+}
+
+#[tokio::test]
+async fn accept_without_backend_fails() {
+    let backend = FakePeripheralBackend::new();
+    let mut link = BleLink::with_backend(SERVICE, CHARACTERISTIC, Arc::new(backend));
+    link.peripheral = None;
+    assert!(link.accept().await.is_err());
+    assert!(link.dial("AA:BB:CC:DD:EE:FF").await.is_err());
+}
@@ -0,0 +1,200 @@
+use clap::Parser;
+use mesh_core::{
+    types::{
+        args::Args,
+        config::{Config, RoutingKind},
+        identity::{verify_signed, NodeIdentity},
+        peer::{LinkType, PeerID},
+    },
+    utils::generate_certificate_authority,
+    MeshError,
+};
+use std::{net::Ipv4Addr, time::Duration};
+
+fn problems(config: &Config) -> String {
+    match config.validate() {
+        Err(MeshError::Config(problems)) => problems,
+        other => panic!("expected config errors, got {:?}", other),
+    }
+}
+
+#[test]
+fn the_shipped_configs_are_valid() {
+    let config = Config::parse(include_str!("../config/config.toml")).unwrap();
+    config.validate().unwrap();
+    assert_eq!(config, Config::default());
+    Config::parse(include_str!("../config/docker.toml")).unwrap();
+}
+
+#[test]
+fn sections_are_read_into_their_settings() {
+    let config = Config::parse(
+        r#"
+        [node]
+        name = "node7"
+
+        [links]
+        priority = ["tcp", "wifi"]
+
+        [links.tcp]
+        enabled = true
+        listen = "127.0.0.1:7000"
+
+        [discovery]
+        group = "239.255.42.7"
+        port = 4100
+        interval_ms = 250
+
+        [routing]
+        strategy = "on_demand"
+        interval_ms = 100
+
+        [[peers]]
+        name = "node8"
+        link = "wifi"
+        address = "10.0.0.8:5000"
+
+        [[peers]]
+        name = "node8"
+        link = "tcp"
+        address = "10.0.0.8:7000"
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.node.name, "node7");
+    assert_eq!(config.priority(), vec![LinkType::TCP, LinkType::WIFI]);
+    let discovery = config.discovery.discovery_config();
+    assert_eq!(discovery.group, Ipv4Addr::new(239, 255, 42, 7));
+    assert_eq!(discovery.port, 4100);
+    assert_eq!(discovery.interval, Duration::from_millis(250));
+    assert_eq!(config.routing.strategy, RoutingKind::OnDemand);
+    assert!(config.routing.strategy(PeerID("node7".into())).is_some());
+
+    // one peer with both of its endpoints
+    let peers = config.bootstrap_peers().unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].id, PeerID("node8".into()));
+    assert_eq!(peers[0].endpoints.len(), 2);
+}
+
+#[test]
+fn flags_override_the_file() {
+    // the command docker-compose.yml runs
+    let args = Args::parse_from(["mesh-core", "--node-name", "node2", "--port", "5001"]);
+    let mut config = Config::parse("[links.wifi]\nlisten = \"10.0.0.2:5000\"").unwrap();
+    config.apply_args(&args);
+
+    assert_eq!(config.node.name, "node2");
+    assert_eq!(config.links.wifi.listen, "10.0.0.2:5001");
+    config.validate().unwrap();
+}
+
+#[test]
+fn a_config_asked_for_must_exist() {
+    let args = Args::parse_from(["mesh-core", "--config", "/nonexistent/mesh.toml"]);
+    assert!(matches!(
+        Config::from_args(&args),
+        Err(MeshError::Config(_))
+    ));
+}
+
+#[test]
+fn unknown_fields_and_bad_values_dont_parse() {
+    assert!(Config::parse("[node]\nnmae = \"typo\"").is_err());
+    assert!(Config::parse("[discovery]\ngroup = \"not an ip\"").is_err());
+    assert!(Config::parse("[routing]\nstrategy = \"flooding\"").is_err());
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    let config = Config::parse(
+        r#"
+        [node]
+        name = "a"
+
+        [certificates]
+        ca_cert = "ca.pem"
+
+        [links]
+        priority = ["wifi", "lora", "wifi"]
+
+        [links.wifi]
+        listen = "localhost"
+
+        [discovery]
+        group = "10.0.0.1"
+
+        [liveness]
+        suspect_after_ms = 5000
+        dead_after_ms = 1000
+
+        [[peers]]
+        name = "a"
+        link = "wifi"
+        address = "nowhere"
+        "#,
+    )
+    .unwrap();
+
+    let problems = problems(&config);
+    for expected in [
+        "ca_key",
+        "lora",
+        "wifi twice",
+        "links.wifi.listen",
+        "multicast",
+        "suspect_after_ms",
+        "peer a is this node",
+        "peer a: invalid address",
+    ] {
+        assert!(
+            problems.contains(expected),
+            "{:?} in {}",
+            expected,
+            problems
+        );
+    }
+}
+
+#[test]
+fn a_node_needs_a_link() {
+    let mut config = Config::default();
+    config.links.wifi.enabled = false;
+    assert!(problems(&config).contains("no link is enabled"));
+    assert!(config.priority().is_empty());
+}
+
+#[test]
+fn a_ca_is_loaded_from_pem_files() {
+    let (ca_cert, issuer) = generate_certificate_authority();
+    let dir = std::env::temp_dir().join(format!("mesh-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca_cert.pem()).unwrap();
+    std::fs::write(dir.join("ca.key"), issuer.key().serialize_pem()).unwrap();
+
+    let config = Config::parse(&format!(
+        "[certificates]\nca_cert = {:?}\nca_key = {:?}",
+        dir.join("ca.pem"),
+        dir.join("ca.key")
+    ))
+    .unwrap();
+    let (ca, loaded) = config.certificates.load().unwrap().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(ca, *ca_cert.der());
+
+    // nodes signed with the loaded ca are trusted by holders of the original
+    let identity = NodeIdentity::new(&loaded, "a");
+    let signature = identity.sign(b"hello").unwrap();
+    assert!(verify_signed(
+        &ca,
+        &identity.certificate,
+        &identity.id,
+        b"hello",
+        &signature
+    )
+    .is_ok());
+
+    assert!(Config::default().certificates.load().unwrap().is_none());
+}
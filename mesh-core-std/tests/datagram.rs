@@ -0,0 +1,46 @@
+use mesh_core::{
+    link::link_trait::Link,
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::{WifiQuicLink, QUIC_MIN_DATAGRAM_SIZE},
+    MeshError,
+};
+use std::time::Duration;
+
+#[tokio::test]
+async fn quic_datagrams_round_trip() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap().to_string();
+    assert_eq!(a.mtu(), QUIC_MIN_DATAGRAM_SIZE);
+
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        connection.receive_datagram().await.unwrap()
+    });
+
+    let connection = a.dial(&b_addr).await.unwrap();
+    let max = connection.max_datagram_size().unwrap();
+    assert!(max >= QUIC_MIN_DATAGRAM_SIZE);
+    assert_eq!(connection.mtu(), max);
+    assert!(matches!(
+        connection.send_datagram(&vec![1u8; max + 1]).await,
+        Err(MeshError::MtuExceeded { size, limit }) if size == max + 1 && limit == max
+    ));
+
+    // the first datagrams may race the peer's accept, keep beaconing until one lands
+    let beacon = vec![7u8; QUIC_MIN_DATAGRAM_SIZE];
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            connection.send_datagram(&beacon).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if server.is_finished() {
+                return server.await.unwrap();
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received, beacon);
+}
@@ -1,22 +1,355 @@
-use std::sync::{Arc, Mutex};
-
 use mesh_core::{
-    discovery::{broadcast, listener},
-    types::peer::{PeerID, PeerStore},
+    link::discovery::{
+        broadcast, encode_announcement, handle_announcement, listener, resolve_announced,
+        AnnouncementVerifier, LINK_BLE, LINK_WIFI,
+    },
+    link::link_trait::Link,
+    mesh::{Announcement, LinkAddress, SignedAnnouncement},
+    types::{
+        discovery::{DiscoveryConfig, DEFAULT_GROUP, DEFAULT_GROUP_V6},
+        identity::NodeIdentity,
+        peer::{LinkType, PeerID, PeerStore},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+};
+use prost::Message;
+use quinn::rustls::pki_types::CertificateDer;
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
+fn mesh() -> (CertificateDer<'static>, NodeIdentity) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let identity = NodeIdentity::new(&ca_issuer, "node1");
+    (ca_cert.der().clone().into_owned(), identity)
+}
+
+fn addresses() -> Vec<LinkAddress> {
+    vec![
+        LinkAddress::new(LINK_WIFI, "127.0.0.1:12345"),
+        LinkAddress::new(LINK_BLE, "AA:BB:CC:DD:EE:FF"),
+    ]
+}
+
+#[test]
+fn signed_announcements_populate_the_store() {
+    let (ca, identity) = mesh();
+    let store = Mutex::new(PeerStore::default());
+    let mut verifier = AnnouncementVerifier::new(ca);
+
+    let capabilities = vec!["quic-datagram".to_string()];
+    let beacon = encode_announcement(&identity, &addresses(), &capabilities, 1).unwrap();
+    let src = "127.0.0.1:4000".parse().unwrap();
+    let id = handle_announcement(&store, &mut verifier, &beacon, src).unwrap();
+    assert_eq!(id, identity.id);
+
+    let store = store.lock().unwrap();
+    let peer = store.get_peer(identity.id.clone()).unwrap();
+    assert!(peer.endpoint(&LinkType::WIFI, "127.0.0.1:12345").is_some());
+    assert!(peer.endpoint(&LinkType::BLE, "AA:BB:CC:DD:EE:FF").is_some());
+    assert_eq!(peer.capabilities, capabilities);
+}
+
+#[test]
+fn bad_endpoints_are_skipped_not_the_whole_beacon() {
+    let (ca, identity) = mesh();
+    let store = Mutex::new(PeerStore::default());
+    let mut verifier = AnnouncementVerifier::new(ca);
+
+    let mut addresses = addresses();
+    addresses.insert(0, LinkAddress::new(LINK_WIFI, "not an address"));
+    let beacon = encode_announcement(&identity, &addresses, &[], 1).unwrap();
+    let src = "127.0.0.1:4000".parse().unwrap();
+    handle_announcement(&store, &mut verifier, &beacon, src).unwrap();
+
+    let store = store.lock().unwrap();
+    let peer = store.get_peer(identity.id.clone()).unwrap();
+    assert_eq!(peer.endpoints.len(), 2);
+    assert!(peer.endpoint(&LinkType::WIFI, "127.0.0.1:12345").is_some());
+}
+
+#[test]
+fn replayed_announcements_are_rejected() {
+    let (ca, identity) = mesh();
+    let mut verifier = AnnouncementVerifier::new(ca);
+
+    let first = encode_announcement(&identity, &addresses(), &[], 5).unwrap();
+    verifier.verify(&first).unwrap();
+    assert!(verifier.verify(&first).is_err());
+
+    let older = encode_announcement(&identity, &addresses(), &[], 4).unwrap();
+    assert!(verifier.verify(&older).is_err());
+    let newer = encode_announcement(&identity, &addresses(), &[], 6).unwrap();
+    assert!(verifier.verify(&newer).is_ok());
+}
+
+#[test]
+fn unsigned_or_tampered_announcements_are_rejected() {
+    let (ca, identity) = mesh();
+    let mut verifier = AnnouncementVerifier::new(ca);
+    let beacon = encode_announcement(&identity, &addresses(), &[], 1).unwrap();
+    let signed = SignedAnnouncement::decode(beacon.as_slice()).unwrap();
+
+    let unsigned = SignedAnnouncement {
+        announcement: signed.announcement.clone(),
+        signature: Vec::new(),
+    };
+    assert!(verifier.verify(&unsigned.encode_to_vec()).is_err());
+
+    // someone else's wifi address under the same signature
+    let mut announcement = Announcement::decode(signed.announcement.as_slice()).unwrap();
+    announcement.addresses = vec![LinkAddress::new(LINK_WIFI, "10.0.0.66:4000")];
+    let tampered = SignedAnnouncement {
+        announcement: announcement.encode_to_vec(),
+        signature: signed.signature.clone(),
+    };
+    assert!(verifier.verify(&tampered.encode_to_vec()).is_err());
+
+    // claiming a different node id than the certificate was issued to
+    let mut announcement = Announcement::decode(signed.announcement.as_slice()).unwrap();
+    announcement.node_id = "node2".to_string();
+    let renamed = SignedAnnouncement {
+        announcement: announcement.encode_to_vec(),
+        signature: identity.sign(&announcement.encode_to_vec()).unwrap(),
+    };
+    assert!(verifier.verify(&renamed.encode_to_vec()).is_err());
+
+    assert!(verifier.verify(b"node1|127.0.0.1:12345").is_err());
+
+    // rejected beacons didn't burn the sequence
+    assert!(verifier.verify(&beacon).is_ok());
+}
+
+#[test]
+fn other_meshes_are_rejected() {
+    let (ca, _) = mesh();
+    let (_, stranger) = mesh();
+    let store = Mutex::new(PeerStore::default());
+    let mut verifier = AnnouncementVerifier::new(ca);
+
+    let beacon = encode_announcement(&stranger, &addresses(), &[], 1).unwrap();
+    let src = "127.0.0.1:4000".parse().unwrap();
+    assert!(handle_announcement(&store, &mut verifier, &beacon, src).is_err());
+    assert!(store.lock().unwrap().get_all_peers().is_empty());
+}
+
+#[test]
+fn nodes_of_trusted_cas_are_accepted_too() {
+    let (ca, _) = mesh();
+    let (their_ca, stranger) = mesh();
+    let mut verifier = AnnouncementVerifier::new(ca);
+
+    let beacon = encode_announcement(&stranger, &addresses(), &[], 1).unwrap();
+    assert!(verifier.verify(&beacon).is_err());
+    verifier.set_trusted(vec![their_ca]);
+    assert!(verifier.verify(&beacon).is_ok());
+}
+
+fn config(port: u16) -> DiscoveryConfig {
+    DiscoveryConfig::default()
+        .with_group(Ipv4Addr::new(239, 255, 42, 1), port)
+        .with_interface(Ipv4Addr::LOCALHOST)
+        .with_interval(Duration::from_millis(50), Duration::from_millis(20))
+}
+
+async fn wait_for(store: &Mutex<PeerStore>, id: &PeerID) -> bool {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while store.lock().unwrap().get_peer(id.clone()).is_none() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+#[tokio::test]
+async fn listener_accepts_beacons_over_udp() {
+    let (ca, identity) = mesh();
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    let handle = listener(&config(45123), store.clone(), ca).await.unwrap();
+
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let beacon = encode_announcement(&identity, &addresses(), &[], 1).unwrap();
+    socket.send_to(&beacon, "127.0.0.1:45123").await.unwrap();
+    assert!(wait_for(&store, &identity.id).await);
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn meshes_on_different_ports_stay_apart() {
+    let (ca, identity) = mesh();
+    let identity = Arc::new(identity);
+    let ours = Arc::new(Mutex::new(PeerStore::default()));
+    let theirs = Arc::new(Mutex::new(PeerStore::default()));
+
+    let our_listener = listener(&config(45124), ours.clone(), ca.clone())
+        .await
+        .unwrap();
+    let their_listener = listener(&config(45125), theirs.clone(), ca).await.unwrap();
+    let announcer = broadcast(&config(45124), identity.clone(), addresses(), Vec::new())
+        .await
+        .unwrap();
+
+    assert!(wait_for(&ours, &identity.id).await);
+    assert!(theirs.lock().unwrap().get_all_peers().is_empty());
+
+    announcer.stop().await;
+    our_listener.stop().await;
+    their_listener.stop().await;
+}
+
+#[tokio::test]
+async fn the_beacon_interval_changes_while_running() {
+    let (ca, identity) = mesh();
+    let identity = Arc::new(identity);
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    let slow = config(45126).with_interval(Duration::from_secs(3600), Duration::ZERO);
+    let listener = listener(&slow, store.clone(), ca).await.unwrap();
+    let announcer = broadcast(&slow, identity.clone(), addresses(), Vec::new())
+        .await
+        .unwrap();
+    assert!(wait_for(&store, &identity.id).await);
+
+    // a few more beacons long before the hour is up
+    announcer.set_interval(Duration::from_millis(50), Duration::ZERO);
+    let last_seen = || {
+        store
+            .lock()
+            .unwrap()
+            .get_peer(identity.id.clone())
+            .unwrap()
+            .last_seen
+    };
+    let beacons = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut seen = last_seen();
+        for _ in 0..3 {
+            while last_seen() == seen {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            seen = last_seen();
+        }
+    })
+    .await;
+    assert!(beacons.is_ok());
+
+    announcer.stop().await;
+    listener.stop().await;
+}
+
+#[test]
+fn delays_stay_within_the_jitter_and_backoff_is_capped() {
+    let config = DiscoveryConfig::default()
+        .with_interval(Duration::from_secs(1), Duration::from_millis(200))
+        .with_max_backoff(Duration::from_secs(5));
+    for _ in 0..100 {
+        let delay = config.next_delay();
+        assert!(delay >= Duration::from_secs(1));
+        assert!(delay <= Duration::from_millis(1200));
+    }
+
+    let mut backoff = config.interval;
+    for _ in 0..10 {
+        backoff = config.backoff(backoff);
+    }
+    assert_eq!(backoff, Duration::from_secs(5));
+}
+
+#[test]
+fn announced_addresses_are_resolved_against_the_sender() {
+    let src: SocketAddr = "[fe80::2%7]:4000".parse().unwrap();
+    let link_local: SocketAddr = "[fe80::1]:4433".parse().unwrap();
+    assert_eq!(
+        resolve_announced(link_local, src),
+        "[fe80::1%7]:4433".parse().unwrap()
+    );
+
+    let wildcard: SocketAddr = "0.0.0.0:4433".parse().unwrap();
+    assert_eq!(
+        resolve_announced(wildcard, "192.168.1.9:4000".parse().unwrap()),
+        "192.168.1.9:4433".parse().unwrap()
+    );
+
+    let global: SocketAddr = "[2001:db8::1]:4433".parse().unwrap();
+    assert_eq!(resolve_announced(global, src), global);
+}
+
+// an interface with a link-local address, from /proc/net/if_inet6 on linux
+fn link_local_interface() -> Option<(Ipv6Addr, u32)> {
+    let table = std::fs::read_to_string("/proc/net/if_inet6").ok()?;
+    table.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let addr = Ipv6Addr::from(u128::from_str_radix(fields.first()?, 16).ok()?);
+        let index = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+        addr.is_unicast_link_local().then_some((addr, index))
+    })
+}
+
 #[tokio::test]
-async fn discovery() {
-    let peer_store = Arc::new(Mutex::new(PeerStore::default()));
-    println!("PeerStore: {:?}", peer_store);
-    let peer_id = PeerID("peer1".to_string());
-    let peer_addr = "127.0.0.1:12345".parse().unwrap();
-    let address = "0.0.0.0:5000".to_string();
-
-    tokio::spawn(async {
-        listener(peer_store, address).await;
+async fn ipv6_link_local_peers_are_discovered_and_dialable() {
+    let Some((link_local, index)) = link_local_interface() else {
+        eprintln!("no ipv6 link-local interface, skipping");
+        return;
+    };
+
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let ca = ca_cert.der().clone().into_owned();
+    let trusted = [ca.clone()];
+    let a = WifiQuicLink::new("[::]:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("[::]:0", &trusted, "node1", &ca_issuer).unwrap();
+    let b_port = b.endpoint.local_addr().unwrap().port();
+
+    // b announces its link-local address without a scope, only the receiver knows its interface
+    let config = DiscoveryConfig::default()
+        .with_ipv4(false)
+        .with_ipv6(DEFAULT_GROUP_V6, vec![index])
+        .with_group(DEFAULT_GROUP, 45126)
+        .with_interval(Duration::from_millis(50), Duration::ZERO);
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    let a_listener = listener(&config, store.clone(), ca).await.unwrap();
+    let identity = Arc::new(NodeIdentity::new(&ca_issuer, "node1"));
+    let address = SocketAddr::new(link_local.into(), b_port).to_string();
+    let announcer = broadcast(
+        &config,
+        identity.clone(),
+        vec![LinkAddress::new(LINK_WIFI, &address)],
+        Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert!(wait_for(&store, &identity.id).await);
+    let wifi_addr: SocketAddr = store
+        .lock()
+        .unwrap()
+        .get_peer(identity.id.clone())
+        .unwrap()
+        .endpoints_on(&LinkType::WIFI)
+        .next()
+        .unwrap()
+        .address
+        .parse()
+        .unwrap();
+    let SocketAddr::V6(scoped) = wifi_addr else {
+        panic!("expected an ipv6 address, got {}", wifi_addr);
+    };
+    assert_eq!(*scoped.ip(), link_local);
+    assert_eq!(scoped.scope_id(), index);
+
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        connection.receive().await.unwrap()
     });
+    let connection = a.dial(&wifi_addr.to_string()).await.unwrap();
+    connection.send(b"hello over ipv6").await.unwrap();
+    assert_eq!(server.await.unwrap(), b"hello over ipv6");
+
+    // without the scope there is no telling which interface to use
+    assert!(a.dial(&address).await.is_err());
 
-    broadcast(peer_id, peer_addr).await;
-    tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+    announcer.stop().await;
+    a_listener.stop().await;
 }
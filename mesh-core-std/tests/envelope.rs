@@ -0,0 +1,43 @@
+use mesh_core::{
+    envelope::{decode_message, encode_message, DEFAULT_TTL, PROTOCOL_VERSION},
+    mesh::{MeshMessage, MessageKind},
+    types::peer::PeerID,
+};
+
+#[test]
+fn envelope_round_trip() {
+    let source = PeerID("node1".to_string());
+    let destination = PeerID("node2".to_string());
+    let message = MeshMessage::data(&source, &destination, vec![0, 1, 2, 0])
+        .with_extension("trace", b"abc".to_vec());
+
+    assert_eq!(message.version, PROTOCOL_VERSION);
+    assert_eq!(message.ttl, DEFAULT_TTL);
+    assert_eq!(message.kind(), MessageKind::Data);
+
+    let decoded = decode_message(&encode_message(&message)).unwrap();
+    assert_eq!(decoded, message);
+    assert_eq!(decoded.source_id(), source);
+    assert_eq!(decoded.destination_id(), destination);
+}
+
+#[test]
+fn message_ids_are_unique() {
+    let id = PeerID("node1".to_string());
+    let first = MeshMessage::data(&id, &id, vec![]);
+    let second = MeshMessage::data(&id, &id, vec![]);
+    assert_ne!(first.id, second.id);
+}
+
+#[test]
+fn unsupported_versions_are_rejected() {
+    let id = PeerID("node1".to_string());
+    let mut message = MeshMessage::data(&id, &id, vec![]);
+    message.version = PROTOCOL_VERSION + 1;
+    assert!(decode_message(&encode_message(&message)).is_err());
+
+    message.version = 0;
+    assert!(decode_message(&encode_message(&message)).is_err());
+
+    assert!(decode_message(b"not a mesh message").is_err());
+}
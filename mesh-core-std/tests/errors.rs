@@ -0,0 +1,117 @@
+use mesh_core::{
+    envelope::decode_message,
+    link::{
+        framing::{read_frame, write_frame},
+        link_trait::Link,
+        multilink::MultiLinkManager,
+    },
+    tcp::tcp_impl::TcpTlsLink,
+    types::peer::{LinkType, PeerID, PeerInfo},
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+    MeshError,
+};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn unknown_and_unreachable_peers_are_told_apart() {
+    let manager = MultiLinkManager::new(HashMap::new(), vec![], vec![]);
+    let id = PeerID("b".to_string());
+    assert!(matches!(
+        manager.pick_best_link(&id).await,
+        Err(MeshError::UnknownPeer(peer)) if peer == id
+    ));
+
+    // known, but only over a link this node doesn't have
+    manager
+        .peer_store
+        .lock()
+        .unwrap()
+        .update_store(PeerInfo::new(id.clone()).with_endpoint(LinkType::TCP, "127.0.0.1:9"));
+    assert!(matches!(
+        manager.pick_best_link(&id).await,
+        Err(MeshError::NoRoute(peer)) if peer == id
+    ));
+}
+
+#[tokio::test]
+async fn oversized_frames_report_the_limit() {
+    let mut buffer = Vec::new();
+    let result = write_frame(&mut buffer, &[0u8; 32], 16).await;
+    assert!(matches!(
+        result,
+        Err(MeshError::MtuExceeded {
+            size: 32,
+            limit: 16
+        })
+    ));
+
+    write_frame(&mut buffer, &[0u8; 32], 64).await.unwrap();
+    let result = read_frame(&mut buffer.as_slice(), 16).await;
+    assert!(matches!(result, Err(MeshError::MtuExceeded { .. })));
+}
+
+#[test]
+fn garbage_fails_to_decode() {
+    let result = decode_message(&[0xff, 0xff, 0xff]);
+    assert!(matches!(result, Err(MeshError::Decode(_))));
+    assert!(!result.unwrap_err().is_transient());
+}
+
+#[tokio::test]
+async fn dialing_outside_the_mesh_is_a_rejected_handshake() {
+    let (ca_cert, issuer) = generate_certificate_authority();
+    let (_, other_issuer) = generate_certificate_authority();
+    let trusted = vec![ca_cert.der().clone().into_owned()];
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let stranger = TcpTlsLink::new("127.0.0.1:0", &trusted, "x", &other_issuer).unwrap();
+    let stranger_addr = stranger.local_addr.to_string();
+    tokio::spawn(async move { while stranger.accept().await.is_ok() {} });
+
+    let result = a.dial(&stranger_addr).await;
+    assert!(matches!(result, Err(MeshError::Handshake(_))));
+}
+
+#[tokio::test]
+async fn nothing_listening_is_a_transient_dial_failure() {
+    let (ca_cert, issuer) = generate_certificate_authority();
+    let trusted = vec![ca_cert.der().clone().into_owned()];
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    // bound and dropped again, so nothing listens there
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let error = match a.dial(&port.to_string()).await {
+        Ok(_) => panic!("dial to a closed port succeeded"),
+        Err(e) => e,
+    };
+    assert!(matches!(error, MeshError::DialFailed { .. }));
+    assert!(error.is_transient());
+}
+
+#[tokio::test]
+async fn quic_dials_fail_on_the_address_only_when_it_is_bad() {
+    let (ca_cert, issuer) = generate_certificate_authority();
+    let trusted = vec![ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+
+    let error = match a.dial("127.0.0.1:0").await {
+        Ok(_) => panic!("dial to port 0 succeeded"),
+        Err(e) => e,
+    };
+    assert!(matches!(error, MeshError::InvalidAddress(_)));
+    assert!(!error.is_transient());
+
+    // a closed endpoint can't dial anywhere, the address is fine
+    a.endpoint.close(0u32.into(), b"done");
+    let error = match a.dial("127.0.0.1:9").await {
+        Ok(_) => panic!("dial from a closed endpoint succeeded"),
+        Err(e) => e,
+    };
+    assert!(matches!(
+        &error,
+        MeshError::DialFailed { address, .. } if address == "127.0.0.1:9"
+    ));
+}
@@ -0,0 +1,154 @@
+use mesh_core::{
+    link::{
+        framing::{read_frame, write_frame, DEFAULT_MAX_FRAME_SIZE},
+        link_trait::{Link, LinkConnection},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test]
+async fn frames_round_trip_byte_exact() {
+    let (mut writer, mut reader) = tokio::io::duplex(64);
+    let messages = vec![
+        vec![],
+        vec![0u8],
+        vec![0u8; 100],
+        (0..=255u8).cycle().take(70_000).collect(),
+    ];
+
+    let expected = messages.clone();
+    let write = tokio::spawn(async move {
+        for message in &messages {
+            write_frame(&mut writer, message, DEFAULT_MAX_FRAME_SIZE)
+                .await
+                .unwrap();
+        }
+    });
+
+    for message in expected {
+        assert_eq!(
+            read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+                .await
+                .unwrap(),
+            message
+        );
+    }
+    write.await.unwrap();
+}
+
+#[tokio::test]
+async fn oversized_and_truncated_frames_are_rejected() {
+    let (mut writer, mut reader) = tokio::io::duplex(1024);
+    assert!(write_frame(&mut writer, &[1; 11], 10).await.is_err());
+
+    write_frame(&mut writer, &[1; 11], 100).await.unwrap();
+    assert!(read_frame(&mut reader, 10).await.is_err());
+
+    // header promises more than the stream delivers
+    let (mut writer, mut reader) = tokio::io::duplex(1024);
+    writer.write_all(&8u32.to_be_bytes()).await.unwrap();
+    writer.write_all(&[1, 2, 3]).await.unwrap();
+    drop(writer);
+    assert!(read_frame(&mut reader, 100).await.is_err());
+}
+
+#[tokio::test]
+async fn quic_messages_round_trip_byte_exact() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer)
+        .unwrap()
+        .with_max_frame_size(1024 * 1024);
+    let b_addr = b.endpoint.local_addr().unwrap().to_string();
+
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        let mut received = vec![];
+        for _ in 0..3 {
+            received.push(connection.receive().await.unwrap());
+        }
+        // the oversized stream is dropped, the connection keeps working
+        assert_eq!(connection.receive().await.unwrap(), b"after");
+        received
+    });
+
+    // trailing zeros used to be stripped by the receiver
+    let messages = vec![
+        b"hello".to_vec(),
+        vec![7, 0, 0, 0],
+        (0..=255u8).cycle().take(300_000).collect::<Vec<u8>>(),
+    ];
+    let connection = a.dial(&b_addr).await.unwrap();
+    for message in &messages {
+        connection.send(message).await.unwrap();
+    }
+    let _ = connection.send(&vec![0u8; 2 * 1024 * 1024]).await;
+    connection.send(b"after").await.unwrap();
+
+    let received = tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, messages);
+}
+
+#[tokio::test]
+async fn concurrent_sends_and_receives_share_a_connection() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap().to_string();
+
+    // b echoes everything back over the same connection
+    tokio::spawn(async move {
+        let connection: Arc<dyn LinkConnection + Send + Sync> = b.accept().await.unwrap().into();
+        loop {
+            let data = connection.receive().await.unwrap();
+            let connection = connection.clone();
+            tokio::spawn(async move { connection.send(&data).await.unwrap() });
+        }
+    });
+
+    let connection: Arc<dyn LinkConnection + Send + Sync> = a.dial(&b_addr).await.unwrap().into();
+    // a receive parked on the connection must not block sends
+    let receiver = {
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            let mut echoed = vec![];
+            for _ in 0..20 {
+                echoed.push(connection.receive().await.unwrap());
+            }
+            echoed
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut senders = vec![];
+    for i in 0..20u8 {
+        let connection = connection.clone();
+        senders.push(tokio::spawn(async move {
+            connection.send(&vec![i; 10_000]).await.unwrap()
+        }));
+    }
+    for sender in senders {
+        tokio::time::timeout(Duration::from_secs(10), sender)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut echoed = tokio::time::timeout(Duration::from_secs(10), receiver)
+        .await
+        .unwrap()
+        .unwrap();
+    echoed.sort();
+    assert_eq!(
+        echoed,
+        (0..20u8).map(|i| vec![i; 10_000]).collect::<Vec<_>>()
+    );
+}
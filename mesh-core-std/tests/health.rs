@@ -0,0 +1,114 @@
+use mesh_core::{
+    link::{
+        link_trait::{Link, LinkConnection},
+        multilink::MultiLinkManager,
+    },
+    types::{
+        health::{Health, HealthEvent, HealthStatus},
+        peer::{LinkType, PeerID, PeerInfo, PeerStore},
+        routing::RoutingLayer,
+    },
+    MeshError,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+
+// a radio that went away, nothing can be accepted or dialed
+#[derive(Clone)]
+struct DeadLink;
+
+#[async_trait::async_trait]
+impl Link for DeadLink {
+    async fn dial(
+        &self,
+        address: &str,
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        Err(MeshError::DialFailed {
+            address: address.to_string(),
+            reason: "radio off".to_string(),
+        })
+    }
+
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        Err(MeshError::closed("radio off"))
+    }
+
+    fn mtu(&self) -> usize {
+        1200
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_millis(10)
+    }
+}
+
+async fn next(events: &mut broadcast::Receiver<HealthEvent>) -> HealthEvent {
+    tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_link_that_stops_accepting_is_reported() {
+    let health = Health::default();
+    let mut events = health.subscribe();
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(DeadLink));
+    let manager = Arc::new(MultiLinkManager::new(links, vec![], vec![]).with_health(health));
+
+    let _inbox = manager.start_receiving();
+    let event = next(&mut events).await;
+    assert_eq!(event.component, "wifi accept");
+    assert!(matches!(event.status, HealthStatus::Stopped(_)));
+}
+
+#[tokio::test]
+async fn unreachable_peers_are_errors_not_crashes() {
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    let health = Health::default();
+    let mut events = health.subscribe();
+    let routing =
+        RoutingLayer::new(PeerID("a".to_string()), DeadLink, store.clone()).with_health(health);
+    let _inbox = routing.start(Duration::from_secs(3600));
+    assert_eq!(next(&mut events).await.component, "routing accept");
+
+    let id = PeerID("b".to_string());
+    assert!(matches!(
+        routing.send(id.clone(), b"hi").await,
+        Err(MeshError::NoRoute(_))
+    ));
+
+    // a neighbour that can't be dialed fails the send and the layer keeps going
+    store
+        .lock()
+        .unwrap()
+        .update_store(PeerInfo::new(id.clone()).with_endpoint(LinkType::WIFI, "127.0.0.1:9"));
+    assert!(matches!(
+        routing.send(id.clone(), b"hi").await,
+        Err(MeshError::DialFailed { .. })
+    ));
+    assert!(routing.send(id, b"again").await.is_err());
+}
+
+#[test]
+fn reports_reach_every_subscriber() {
+    let health = Health::default();
+    let mut first = health.subscribe();
+    let mut second = health.clone().subscribe();
+    health.degraded("discovery listener", "no route to host");
+    health.recovered("discovery listener");
+
+    for events in [&mut first, &mut second] {
+        let event = events.try_recv().unwrap();
+        assert_eq!(
+            event.status,
+            HealthStatus::Degraded("no route to host".to_string())
+        );
+        assert_eq!(events.try_recv().unwrap().status, HealthStatus::Recovered);
+    }
+}
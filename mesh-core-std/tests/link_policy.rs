@@ -0,0 +1,196 @@
+use mesh_core::{
+    bluetooth::fake::FakePeripheralBackend,
+    link::{
+        link_trait::Link,
+        multilink::MultiLinkManager,
+        policy::{LinkCandidate, LinkPolicy, PriorityPolicy, WeightedPolicy},
+        registry::TransportCapabilities,
+    },
+    types::{
+        ble_types::BleLink,
+        peer::{LinkType, PeerID, PeerInfo},
+        probe::{LinkMetrics, ProbeConfig},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use uuid::Uuid;
+
+fn candidate(link_type: LinkType, rtt_ms: u64) -> LinkCandidate {
+    LinkCandidate {
+        link_type,
+        address: String::new(),
+        rtt: Duration::from_millis(rtt_ms),
+        jitter: Duration::ZERO,
+        loss: 0.0,
+        mtu: 1200,
+        energy_cost: 1.0,
+        failures: 0,
+        priority: 0,
+        capabilities: TransportCapabilities::default(),
+    }
+}
+
+// costs set from the test, to drive the manager's hysteresis
+struct TablePolicy(Arc<Mutex<HashMap<LinkType, f64>>>);
+
+impl LinkPolicy for TablePolicy {
+    fn cost(&self, candidate: &LinkCandidate) -> f64 {
+        self.0.lock().unwrap()[&candidate.link_type]
+    }
+
+    fn hysteresis(&self) -> f64 {
+        0.2
+    }
+}
+
+// a peer reachable over wi-fi and ble, neither link is ever dialed
+fn two_link_manager(priority: Vec<LinkType>) -> (MultiLinkManager, PeerID) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let wifi = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let ble = BleLink::with_backend(
+        Uuid::from_u128(1),
+        Uuid::from_u128(2),
+        Arc::new(FakePeripheralBackend::new()),
+    );
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(wifi));
+    links.insert(LinkType::BLE, Box::new(ble));
+    let manager = MultiLinkManager::new(links, vec![], priority);
+
+    let id = PeerID("b".to_string());
+    manager.peer_store.lock().unwrap().update_store(
+        PeerInfo::new(id.clone())
+            .with_endpoint(LinkType::WIFI, "127.0.0.1:9")
+            .with_endpoint(LinkType::BLE, "AA:BB:CC:DD:EE:FF"),
+    );
+    (manager, id)
+}
+
+#[test]
+fn weighted_policy_prices_rtt_loss_energy_mtu_and_failures() {
+    let policy = WeightedPolicy::default();
+    let base = candidate(LinkType::WIFI, 20);
+    let cost = policy.cost(&base);
+
+    let slower = candidate(LinkType::WIFI, 40);
+    assert!(policy.cost(&slower) > cost);
+
+    let lossy = LinkCandidate {
+        loss: 0.5,
+        ..base.clone()
+    };
+    assert!(policy.cost(&lossy) > cost);
+
+    let frugal = LinkCandidate {
+        energy_cost: 0.2,
+        ..base.clone()
+    };
+    assert!(policy.cost(&frugal) < cost);
+
+    let small_packets = LinkCandidate {
+        mtu: 20,
+        ..base.clone()
+    };
+    assert!(policy.cost(&small_packets) > cost);
+
+    let failing = LinkCandidate {
+        failures: 2,
+        ..base.clone()
+    };
+    assert_eq!(policy.cost(&failing), cost + 2.0 * policy.failure_penalty);
+}
+
+#[tokio::test]
+async fn unknown_or_unreachable_peers_are_errors() {
+    let (manager, _) = two_link_manager(vec![LinkType::WIFI]);
+    assert!(manager
+        .pick_best_link(&PeerID("nobody".to_string()))
+        .await
+        .is_err());
+
+    // no link in the priority list
+    let (manager, id) = two_link_manager(vec![]);
+    assert!(manager.pick_best_link(&id).await.is_err());
+}
+
+#[tokio::test]
+async fn measurements_decide_between_links() {
+    let (manager, id) = two_link_manager(vec![LinkType::WIFI, LinkType::BLE]);
+    // nothing measured yet, wi-fi's latency estimate and mtu win
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::WIFI
+    );
+
+    // wi-fi turns out slow and lossy
+    let config = ProbeConfig::default();
+    let mut wifi = LinkMetrics::default();
+    wifi.record_rtt(Duration::from_millis(400), &config);
+    for _ in 0..5 {
+        wifi.record_loss(&config);
+    }
+    let mut ble = LinkMetrics::default();
+    ble.record_rtt(Duration::from_millis(30), &config);
+    {
+        let mut store = manager.peer_store.lock().unwrap();
+        store.update_metrics(&id, &LinkType::WIFI, "127.0.0.1:9", wifi);
+        store.update_metrics(&id, &LinkType::BLE, "AA:BB:CC:DD:EE:FF", ble);
+    }
+
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::BLE
+    );
+}
+
+#[tokio::test]
+async fn hysteresis_keeps_the_current_link_until_clearly_beaten() {
+    let costs = Arc::new(Mutex::new(HashMap::from([
+        (LinkType::WIFI, 100.0),
+        (LinkType::BLE, 120.0),
+    ])));
+    let (manager, id) = two_link_manager(vec![LinkType::WIFI, LinkType::BLE]);
+    let manager = manager.with_policy(Box::new(TablePolicy(costs.clone())));
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::WIFI
+    );
+
+    // ble is cheaper now but not by the 20% hysteresis
+    costs.lock().unwrap().insert(LinkType::BLE, 85.0);
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::WIFI
+    );
+
+    costs.lock().unwrap().insert(LinkType::BLE, 70.0);
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::BLE
+    );
+
+    // and the same margin applies on the way back
+    costs.lock().unwrap().insert(LinkType::WIFI, 65.0);
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::BLE
+    );
+}
+
+#[tokio::test]
+async fn priority_policy_follows_the_list() {
+    let (manager, id) = two_link_manager(vec![LinkType::BLE, LinkType::WIFI]);
+    let manager = manager.with_policy(Box::new(PriorityPolicy));
+    assert_eq!(
+        manager.pick_best_link(&id).await.unwrap().link,
+        LinkType::BLE
+    );
+}
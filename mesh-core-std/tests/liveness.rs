@@ -0,0 +1,181 @@
+use mesh_core::{
+    link::{link_trait::Link, multilink::MultiLinkManager},
+    mesh::routing_frame::Frame,
+    types::{
+        peer::{LinkType, LivenessConfig, PeerEvent, PeerID, PeerInfo, PeerState, PeerStore},
+        routing::{Outgoing, RoutingLayer, RoutingStrategy},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+fn peer(id: &PeerID, addr: SocketAddr) -> PeerInfo {
+    PeerInfo::new(id.clone()).with_endpoint(LinkType::WIFI, addr.to_string())
+}
+
+fn fast_store() -> Arc<Mutex<PeerStore>> {
+    let liveness = LivenessConfig::new(Duration::from_millis(200), Duration::from_millis(400));
+    Arc::new(Mutex::new(PeerStore::default().with_liveness(liveness)))
+}
+
+#[test]
+fn silent_peers_turn_suspect_then_dead() {
+    let mut store = PeerStore::default();
+    let mut events = store.subscribe();
+    let id = PeerID("a".to_string());
+    let seen = Instant::now();
+    let mut info = peer(&id, "127.0.0.1:1".parse().unwrap());
+    info.last_seen = seen;
+    store.update_store(info);
+    assert!(matches!(events.try_recv(), Ok(PeerEvent::Joined(p)) if p.id == id));
+
+    assert!(store.reap(seen + Duration::from_secs(1)).is_empty());
+    assert_eq!(store.state(&id), PeerState::Alive);
+
+    let changed = store.reap(seen + store.liveness.suspect_after);
+    assert_eq!(changed, vec![(id.clone(), PeerState::Suspect)]);
+    assert_eq!(store.state(&id), PeerState::Suspect);
+    assert!(events.try_recv().is_err());
+
+    let changed = store.reap(seen + store.liveness.dead_after);
+    assert_eq!(changed, vec![(id.clone(), PeerState::Dead)]);
+    assert!(store.get_peer(id.clone()).is_none());
+    assert!(matches!(events.try_recv(), Ok(PeerEvent::Left(p)) if p.id == id));
+}
+
+#[test]
+fn sightings_revive_suspect_peers_without_rejoining() {
+    let mut store = PeerStore::default();
+    let mut events = store.subscribe();
+    let id = PeerID("a".to_string());
+    store.update_store(peer(&id, "127.0.0.1:1".parse().unwrap()));
+    assert!(matches!(events.try_recv(), Ok(PeerEvent::Joined(_))));
+
+    store.reap(Instant::now() + store.liveness.suspect_after);
+    assert_eq!(store.state(&id), PeerState::Suspect);
+
+    store.touch(&id);
+    assert_eq!(store.state(&id), PeerState::Alive);
+    store.update_store(peer(&id, "127.0.0.1:1".parse().unwrap()));
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn reaper_reports_peers_that_went_silent() {
+    let store = fast_store();
+    let mut events = store.lock().unwrap().subscribe();
+    let id = PeerID("a".to_string());
+    store
+        .lock()
+        .unwrap()
+        .update_store(peer(&id, "127.0.0.1:1".parse().unwrap()));
+    let reaper = PeerStore::spawn_reaper(&store);
+
+    assert!(matches!(events.recv().await, Ok(PeerEvent::Joined(_))));
+    let left = tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap();
+    assert!(matches!(left, Ok(PeerEvent::Left(p)) if p.id == id));
+    assert!(store.lock().unwrap().peers.is_empty());
+
+    drop(store);
+    tokio::time::timeout(Duration::from_secs(1), reaper)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn manager_drops_connections_to_dead_peers() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap();
+    tokio::spawn(async move { while b.accept().await.is_ok() {} });
+
+    let pool = a.pool.clone();
+    a.dial(&b_addr.to_string()).await.unwrap();
+    assert_eq!(pool.len(), 1);
+
+    let store = fast_store();
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(a));
+    let node_b = PeerID("b".to_string());
+    let manager = Arc::new(
+        MultiLinkManager::new(links, vec![(node_b.clone(), b_addr)], vec![LinkType::WIFI])
+            .with_peer_store(store.clone()),
+    );
+    manager.watch_peers();
+    manager.bootstrap_peers().await;
+    PeerStore::spawn_reaper(&store);
+
+    let started = Instant::now();
+    while !pool.is_empty() && started.elapsed() < Duration::from_secs(2) {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(pool.is_empty());
+    assert!(store.lock().unwrap().get_peer(node_b).is_none());
+}
+
+// remembers which neighbours were reported broken
+struct Recorder(Arc<Mutex<Vec<PeerID>>>);
+
+impl RoutingStrategy for Recorder {
+    fn next_hop(&mut self, _destination: &PeerID) -> Option<PeerID> {
+        None
+    }
+
+    fn on_control(&mut self, _from: &PeerID, _frame: Frame) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_route_missing(&mut self, _destination: &PeerID) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_link_broken(&mut self, neighbour: &PeerID) -> Vec<Outgoing> {
+        self.0.lock().unwrap().push(neighbour.clone());
+        vec![]
+    }
+
+    fn on_tick(&mut self, _interval: Duration) -> Vec<Outgoing> {
+        vec![]
+    }
+}
+
+#[tokio::test]
+async fn routing_treats_dead_neighbours_as_broken_links() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let link = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+
+    let store = fast_store();
+    let broken = Arc::new(Mutex::new(Vec::new()));
+    let routing = RoutingLayer::with_strategy(
+        PeerID("a".to_string()),
+        link,
+        store.clone(),
+        Box::new(Recorder(broken.clone())),
+    );
+    let _inbox = routing.start(Duration::from_secs(60));
+
+    let node_b = PeerID("b".to_string());
+    store
+        .lock()
+        .unwrap()
+        .update_store(peer(&node_b, "127.0.0.1:1".parse().unwrap()));
+    PeerStore::spawn_reaper(&store);
+
+    let started = Instant::now();
+    while broken.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(2) {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(*broken.lock().unwrap(), vec![node_b]);
+}
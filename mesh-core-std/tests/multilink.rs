@@ -0,0 +1,38 @@
+use mesh_core::{
+    link::{link_trait::Link, multilink::MultiLinkManager},
+    mesh::MeshMessage,
+    types::peer::{LinkType, PeerID},
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::{WifiQuicLink, QUIC_MIN_DATAGRAM_SIZE},
+};
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn send_records_the_connection_mtu() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        connection.receive().await.unwrap()
+    });
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(a));
+    let node_b = PeerID("b".to_string());
+    let manager =
+        MultiLinkManager::new(links, vec![(node_b.clone(), b_addr)], vec![LinkType::WIFI]);
+    manager.bootstrap_peers().await;
+
+    let message = MeshMessage::data(&PeerID("a".to_string()), &node_b, b"hi".to_vec());
+    manager.send(&node_b, &message).await.unwrap();
+    server.await.unwrap();
+
+    let store = manager.peer_store.lock().unwrap();
+    let mtu = store.get_peer(node_b).unwrap().mtu.unwrap();
+    assert!(mtu >= QUIC_MIN_DATAGRAM_SIZE);
+    assert!(mtu < 1500);
+}
@@ -0,0 +1,307 @@
+use mesh_core::{
+    link::{
+        link_trait::{Link, LinkConnection},
+        multilink::MultiLinkManager,
+    },
+    mesh::MeshMessage,
+    types::{
+        multipath::{
+            split_stripes, MultipathReceiver, SendMode, MAX_PENDING_MESSAGES, STRIPE_EXTENSION,
+        },
+        peer::{LinkType, PeerID, PeerInfo},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+    MeshError,
+};
+use proptest::prelude::*;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Mutex};
+
+// stands in for ble: what one side sends comes out of the other side's accepted connection
+#[derive(Clone)]
+struct ChannelLink {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    rx: Arc<Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>>,
+    // every frame that went through, for the test to look at
+    carried: Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+    broken: Arc<AtomicBool>,
+}
+
+fn channel_link() -> ChannelLink {
+    let (tx, rx) = mpsc::unbounded_channel();
+    ChannelLink {
+        tx,
+        rx: Arc::new(Mutex::new(Some(rx))),
+        carried: Default::default(),
+        broken: Default::default(),
+    }
+}
+
+struct ChannelConnection {
+    link: ChannelLink,
+    rx: Option<Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>,
+}
+
+#[async_trait::async_trait]
+impl Link for ChannelLink {
+    async fn dial(
+        &self,
+        _address: &str,
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        Ok(Box::new(ChannelConnection {
+            link: self.clone(),
+            rx: None,
+        }))
+    }
+
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        match self.rx.lock().await.take() {
+            Some(rx) => Ok(Box::new(ChannelConnection {
+                link: self.clone(),
+                rx: Some(Mutex::new(rx)),
+            })),
+            None => std::future::pending().await,
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        20
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_millis(50)
+    }
+}
+
+#[async_trait::async_trait]
+impl LinkConnection for ChannelConnection {
+    async fn send(&self, data: &[u8]) -> Result<(), MeshError> {
+        if self.link.broken.load(Ordering::Relaxed) {
+            return Err(MeshError::closed("link broken"));
+        }
+        self.link.carried.lock().unwrap().push(data.to_vec());
+        self.link
+            .tx
+            .send(data.to_vec())
+            .map_err(|_| MeshError::closed("receiver gone"))?;
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        let rx = self
+            .rx
+            .as_ref()
+            .ok_or_else(|| MeshError::other("dialed side doesn't receive"))?;
+        rx.lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| MeshError::closed("closed"))
+    }
+}
+
+fn message(payload: Vec<u8>) -> MeshMessage {
+    MeshMessage::data(&PeerID("a".to_string()), &PeerID("b".to_string()), payload)
+}
+
+// a sends to b over quic and a channel link, b receives through its own manager
+async fn pair() -> (
+    Arc<MultiLinkManager>,
+    ChannelLink,
+    mpsc::Receiver<MeshMessage>,
+) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let wifi_a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let wifi_b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = wifi_b.endpoint.local_addr().unwrap();
+    let ble = channel_link();
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(wifi_b));
+    links.insert(LinkType::BLE, Box::new(ble.clone()));
+    let b = Arc::new(MultiLinkManager::new(links, vec![], vec![]));
+    let inbox = b.start_receiving();
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(wifi_a));
+    links.insert(LinkType::BLE, Box::new(ble.clone()));
+    let a = Arc::new(MultiLinkManager::new(
+        links,
+        vec![],
+        vec![LinkType::WIFI, LinkType::BLE],
+    ));
+    a.peer_store.lock().unwrap().update_store(
+        PeerInfo::new(PeerID("b".to_string()))
+            .with_endpoint(LinkType::WIFI, b_addr.to_string())
+            .with_endpoint(LinkType::BLE, "AA:BB:CC:DD:EE:FF"),
+    );
+    (a, ble, inbox)
+}
+
+async fn next(inbox: &mut mpsc::Receiver<MeshMessage>) -> MeshMessage {
+    tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+async fn nothing_more(inbox: &mut mpsc::Receiver<MeshMessage>) {
+    let extra = tokio::time::timeout(Duration::from_millis(300), inbox.recv()).await;
+    assert!(extra.is_err(), "unexpected message {:?}", extra);
+}
+
+#[test]
+fn stripes_follow_the_weights() {
+    let original = message((0..100).collect());
+    let stripes = split_stripes(&original, &[3.0, 1.0]).unwrap();
+    assert_eq!(stripes.len(), 2);
+    assert_eq!(stripes[0].payload.len(), 75);
+    assert_eq!(stripes[1].payload.len(), 25);
+    assert!(stripes.iter().all(|s| s.id == original.id));
+    assert_eq!(stripes[1].extensions[STRIPE_EXTENSION], vec![1, 0, 2, 0]);
+
+    // a link with no measured capacity still gets a byte
+    let stripes = split_stripes(&original, &[1.0, 0.0]).unwrap();
+    assert_eq!(stripes[1].payload.len(), 1);
+
+    // never more stripes than bytes
+    assert_eq!(
+        split_stripes(&message(vec![1]), &[1.0, 1.0]).unwrap().len(),
+        1
+    );
+    assert!(split_stripes(&message(vec![]), &[1.0]).is_err());
+}
+
+#[test]
+fn receiver_drops_copies_and_forgets_them_after_the_window() {
+    let mut receiver = MultipathReceiver::new(Duration::from_secs(10));
+    let now = Instant::now();
+    let original = message(b"alert".to_vec());
+
+    assert!(receiver.push_at(original.clone(), now).unwrap().is_some());
+    assert!(receiver.push_at(original.clone(), now).unwrap().is_none());
+    let later = now + Duration::from_secs(11);
+    assert!(receiver.push_at(original, later).unwrap().is_some());
+}
+
+#[test]
+fn receiver_rejects_bad_stripes_and_gives_up_on_incomplete_ones() {
+    let mut receiver = MultipathReceiver::new(Duration::from_secs(10));
+    let now = Instant::now();
+
+    let bad = message(vec![1]).with_extension(STRIPE_EXTENSION, vec![2, 0, 2, 0]);
+    assert!(receiver.push_at(bad, now).is_err());
+
+    let stripes = split_stripes(&message(vec![1, 2, 3]), &[1.0, 1.0, 1.0]).unwrap();
+    assert!(receiver.push_at(stripes[0].clone(), now).unwrap().is_none());
+    assert_eq!(receiver.pending(), 1);
+    receiver.expire(now + Duration::from_secs(10));
+    assert_eq!(receiver.pending(), 0);
+}
+
+#[test]
+fn receiver_drops_the_stalest_message_when_too_many_are_incomplete() {
+    let mut receiver = MultipathReceiver::new(Duration::from_secs(10));
+    let now = Instant::now();
+
+    let messages: Vec<Vec<MeshMessage>> = (0..=MAX_PENDING_MESSAGES)
+        .map(|_| split_stripes(&message(vec![1, 2]), &[1.0, 1.0]).unwrap())
+        .collect();
+    for (i, stripes) in messages.iter().enumerate() {
+        let at = now + Duration::from_millis(i as u64);
+        assert!(receiver.push_at(stripes[0].clone(), at).unwrap().is_none());
+    }
+    assert_eq!(receiver.pending(), MAX_PENDING_MESSAGES);
+
+    // the first one was dropped to make room, the next one still completes
+    let later = now + Duration::from_secs(1);
+    assert!(receiver
+        .push_at(messages[1][1].clone(), later)
+        .unwrap()
+        .is_some());
+    assert!(receiver
+        .push_at(messages[0][1].clone(), later)
+        .unwrap()
+        .is_none());
+}
+
+proptest! {
+    #[test]
+    fn stripes_reassemble_in_any_order(
+        payload in proptest::collection::vec(any::<u8>(), 1..2048),
+        weights in proptest::collection::vec(0.0f64..10.0, 1..6),
+        order in any::<u64>(),
+    ) {
+        let original = message(payload);
+        let mut stripes = split_stripes(&original, &weights).unwrap();
+        let len = stripes.len();
+        stripes.rotate_left(order as usize % len);
+
+        let mut receiver = MultipathReceiver::default();
+        let mut whole = None;
+        for stripe in stripes.clone() {
+            prop_assert!(whole.is_none());
+            whole = receiver.push(stripe).unwrap();
+        }
+        prop_assert_eq!(whole, Some(original));
+        // late copies of a stripe don't deliver it again
+        prop_assert!(receiver.push(stripes[0].clone()).unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn redundant_messages_go_over_every_link_and_arrive_once() {
+    let (a, ble, mut inbox) = pair().await;
+    let b = PeerID("b".to_string());
+    let alert = message(b"fire".to_vec());
+
+    a.send_with_mode(&b, &alert, SendMode::Redundant)
+        .await
+        .unwrap();
+    assert_eq!(next(&mut inbox).await, alert);
+    assert_eq!(ble.carried.lock().unwrap().len(), 1);
+    nothing_more(&mut inbox).await;
+
+    // one link down is what redundancy is for
+    ble.broken.store(true, Ordering::Relaxed);
+    let alert = message(b"flood".to_vec());
+    a.send_with_mode(&b, &alert, SendMode::Redundant)
+        .await
+        .unwrap();
+    assert_eq!(next(&mut inbox).await, alert);
+}
+
+#[tokio::test]
+async fn striped_messages_are_split_by_capacity_and_put_back_together() {
+    let (a, ble, mut inbox) = pair().await;
+    let b = PeerID("b".to_string());
+    let bulk = message((0..20_000u32).map(|i| i as u8).collect());
+
+    a.send_with_mode(&b, &bulk, SendMode::Striped)
+        .await
+        .unwrap();
+    assert_eq!(next(&mut inbox).await, bulk);
+    nothing_more(&mut inbox).await;
+
+    // the slow narrow link got the smaller share
+    let carried = ble.carried.lock().unwrap().clone();
+    assert_eq!(carried.len(), 1);
+    assert!(carried[0].len() < bulk.payload.len() / 2);
+
+    // a stripe whose link fails goes over the one that worked
+    ble.broken.store(true, Ordering::Relaxed);
+    let bulk = message(vec![7; 5000]);
+    a.send_with_mode(&b, &bulk, SendMode::Striped)
+        .await
+        .unwrap();
+    assert_eq!(next(&mut inbox).await, bulk);
+}
@@ -0,0 +1,251 @@
+use mesh_core::{
+    node::MeshNode,
+    tcp::tcp_impl::TcpTlsLink,
+    types::{
+        discovery::DiscoveryConfig,
+        distance_vector::RoutingTable,
+        identity::NodeIdentity,
+        on_demand::OnDemandRouting,
+        peer::{LinkType, PeerID, PeerInfo},
+    },
+    utils::generate_certificate_authority,
+    MeshError,
+};
+use quinn::rustls::pki_types::CertificateDer;
+use rcgen::{Issuer, KeyPair};
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+struct Mesh {
+    ca: CertificateDer<'static>,
+    issuer: Issuer<'static, KeyPair>,
+}
+
+impl Mesh {
+    fn new() -> Self {
+        let (ca_cert, issuer) = generate_certificate_authority();
+        Self {
+            ca: ca_cert.der().clone().into_owned(),
+            issuer,
+        }
+    }
+
+    fn link(&self, name: &str) -> TcpTlsLink {
+        TcpTlsLink::new(
+            "127.0.0.1:0",
+            std::slice::from_ref(&self.ca),
+            name,
+            &self.issuer,
+        )
+        .unwrap()
+    }
+
+    fn node(&self, name: &str, link: TcpTlsLink) -> mesh_core::node::MeshNodeBuilder {
+        let address = link.local_addr.to_string();
+        MeshNode::builder(
+            Arc::new(NodeIdentity::new(&self.issuer, name)),
+            self.ca.clone(),
+        )
+        .with_link(LinkType::TCP, Box::new(link))
+        .with_address(LinkType::TCP, address)
+    }
+}
+
+fn peer(name: &str, link: &TcpTlsLink) -> PeerInfo {
+    PeerInfo::new(id(name)).with_endpoint(LinkType::TCP, link.local_addr.to_string())
+}
+
+fn id(name: &str) -> PeerID {
+    PeerID(name.to_string())
+}
+
+#[tokio::test]
+async fn neighbours_exchange_messages() {
+    let mesh = Mesh::new();
+    let (link_a, link_b) = (mesh.link("a"), mesh.link("b"));
+    let b_peer = peer("b", &link_b);
+    let b = mesh.node("b", link_b).start().await.unwrap();
+    let a = mesh
+        .node("a", link_a)
+        .with_peer(b_peer)
+        .start()
+        .await
+        .unwrap();
+    let mut inbox = b.subscribe();
+
+    a.send(&id("b"), b"hello").await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.payload, b"hello");
+    assert_eq!(message.source_id(), id("a"));
+    assert_eq!(a.peers().len(), 1);
+
+    assert!(matches!(
+        a.send(&id("nobody"), b"hello").await,
+        Err(MeshError::UnknownPeer(_))
+    ));
+
+    a.shutdown().await;
+    b.shutdown().await;
+}
+
+#[tokio::test]
+async fn messages_are_relayed_to_peers_out_of_reach() {
+    let mesh = Mesh::new();
+    let (link_a, link_b, link_c) = (mesh.link("a"), mesh.link("b"), mesh.link("c"));
+    let (a_peer, b_peer, c_peer) = (peer("a", &link_a), peer("b", &link_b), peer("c", &link_c));
+    let interval = Duration::from_millis(50);
+
+    // a - b - c, a and c don't know each other
+    let a = mesh
+        .node("a", link_a)
+        .with_peer(b_peer.clone())
+        .with_routing(Box::new(RoutingTable::new(id("a"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let b = mesh
+        .node("b", link_b)
+        .with_peer(a_peer)
+        .with_peer(c_peer)
+        .with_routing(Box::new(RoutingTable::new(id("b"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let c = mesh
+        .node("c", link_c)
+        .with_peer(b_peer)
+        .with_routing(Box::new(RoutingTable::new(id("c"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let mut inbox = c.subscribe();
+
+    // until the advertisements got around
+    let sent = tokio::time::timeout(Duration::from_secs(5), async {
+        while a.send(&id("c"), b"over b").await.is_err() {
+            tokio::time::sleep(interval).await;
+        }
+    })
+    .await;
+    assert!(sent.is_ok());
+
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.payload, b"over b");
+    assert_eq!(message.source_id(), id("a"));
+    assert!(!a.peers().iter().any(|peer| peer.id == id("c")));
+
+    for node in [a, b, c] {
+        node.shutdown().await;
+    }
+}
+
+#[tokio::test]
+async fn on_demand_routes_are_found_over_any_link() {
+    let mesh = Mesh::new();
+    let (link_a, link_b, link_c) = (mesh.link("a"), mesh.link("b"), mesh.link("c"));
+    let (a_peer, b_peer, c_peer) = (peer("a", &link_a), peer("b", &link_b), peer("c", &link_c));
+    // no ticks get in the way, the send alone has to find the route
+    let interval = Duration::from_secs(60);
+
+    let a = mesh
+        .node("a", link_a)
+        .with_peer(b_peer.clone())
+        .with_routing(Box::new(OnDemandRouting::new(id("a"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let b = mesh
+        .node("b", link_b)
+        .with_peer(a_peer)
+        .with_peer(c_peer)
+        .with_routing(Box::new(OnDemandRouting::new(id("b"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let c = mesh
+        .node("c", link_c)
+        .with_peer(b_peer)
+        .with_routing(Box::new(OnDemandRouting::new(id("c"))), interval)
+        .start()
+        .await
+        .unwrap();
+    let mut inbox = c.subscribe();
+
+    a.send(&id("c"), b"found").await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.payload, b"found");
+
+    for node in [a, b, c] {
+        node.shutdown().await;
+    }
+}
+
+#[tokio::test]
+async fn nodes_find_each_other_through_discovery() {
+    let mesh = Mesh::new();
+    let discovery = DiscoveryConfig::default()
+        .with_group(Ipv4Addr::new(239, 255, 42, 3), 47_123)
+        .with_interface(Ipv4Addr::LOCALHOST)
+        .with_interval(Duration::from_millis(50), Duration::from_millis(20));
+    let a = mesh
+        .node("a", mesh.link("a"))
+        .with_discovery(discovery.clone())
+        .start()
+        .await
+        .unwrap();
+    let b = mesh
+        .node("b", mesh.link("b"))
+        .with_discovery(discovery)
+        .start()
+        .await
+        .unwrap();
+    let mut inbox = b.subscribe();
+
+    let found = tokio::time::timeout(Duration::from_secs(5), async {
+        while !a.peers().iter().any(|peer| peer.id == id("b")) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+    assert!(found.is_ok());
+    // its own beacons come back over loopback but aren't a peer
+    assert!(!a.peers().iter().any(|peer| peer.id == id("a")));
+
+    a.send(&id("b"), b"found you").await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.payload, b"found you");
+
+    a.shutdown().await;
+    b.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_releases_the_links() {
+    let mesh = Mesh::new();
+    let link = mesh.link("a");
+    let address = link.local_addr;
+    let a = mesh.node("a", link).start().await.unwrap();
+    let weak = Arc::downgrade(&a.manager);
+
+    a.shutdown().await;
+    // the accept loops let go of the manager, and with it the listener
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while weak.strong_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(std::net::TcpListener::bind(address).is_ok());
+}
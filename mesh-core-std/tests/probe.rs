@@ -0,0 +1,240 @@
+use mesh_core::{
+    link::{
+        link_trait::{Link, LinkConnection},
+        multilink::MultiLinkManager,
+        registry::{Transport, TransportCapabilities},
+    },
+    types::{
+        peer::{LinkType, PeerID, PeerInfo, PeerStore},
+        probe::{LinkMetrics, ProbeConfig},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+    MeshError,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+fn peer(id: &PeerID, addr: SocketAddr) -> PeerInfo {
+    PeerInfo::new(id.clone()).with_endpoint(LinkType::WIFI, addr.to_string())
+}
+
+#[test]
+fn metrics_smooth_rtt_jitter_and_loss() {
+    let config = ProbeConfig::default();
+    let mut metrics = LinkMetrics::default();
+
+    metrics.record_rtt(Duration::from_millis(100), &config);
+    assert_eq!(metrics.srtt, Some(Duration::from_millis(100)));
+    assert_eq!(metrics.jitter, Duration::from_millis(50));
+
+    // a single outlier only moves the average by alpha
+    metrics.record_rtt(Duration::from_millis(180), &config);
+    assert_eq!(metrics.srtt, Some(Duration::from_millis(110)));
+    assert_eq!(
+        metrics.jitter,
+        Duration::from_millis(57) + Duration::from_micros(500)
+    );
+    assert_eq!(metrics.loss, 0.0);
+
+    metrics.record_loss(&config);
+    assert!((metrics.loss - 0.1).abs() < 1e-9);
+    for _ in 0..100 {
+        metrics.record_loss(&config);
+    }
+    assert!(metrics.loss_percent() > 99.0);
+    assert_eq!(metrics.probes_sent, 103);
+    assert_eq!(metrics.probes_lost, 101);
+    assert_eq!(metrics.srtt, Some(Duration::from_millis(110)));
+}
+
+#[test]
+fn store_summarises_the_best_link_and_keeps_it_across_sightings() {
+    let config = ProbeConfig::default();
+    let mut store = PeerStore::default();
+    let id = PeerID("a".to_string());
+    store.update_store(
+        peer(&id, "127.0.0.1:1".parse().unwrap()).with_endpoint(LinkType::BLE, "AA:BB:CC:DD:EE:FF"),
+    );
+
+    let mut wifi = LinkMetrics::default();
+    wifi.record_rtt(Duration::from_millis(8), &config);
+    let mut ble = LinkMetrics::default();
+    ble.record_rtt(Duration::from_millis(60), &config);
+    ble.record_loss(&config);
+    store.update_metrics(&id, &LinkType::BLE, "AA:BB:CC:DD:EE:FF", ble);
+    assert_eq!(store.get_peer(id.clone()).unwrap().rtt_ms, Some(60));
+    store.update_metrics(&id, &LinkType::WIFI, "127.0.0.1:1", wifi);
+
+    // a fresh discovery beacon doesn't wipe what was measured
+    store.update_store(peer(&id, "127.0.0.1:1".parse().unwrap()));
+    let info = store.get_peer(id).unwrap();
+    assert_eq!(info.rtt_ms, Some(8));
+    assert_eq!(info.loss_percent, Some(0.0));
+    assert_eq!(info.endpoints.len(), 2);
+}
+
+#[tokio::test]
+async fn probing_measures_reachable_peers_and_counts_lost_ones() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok(connection) = b.accept().await {
+            tokio::spawn(async move { while connection.receive().await.is_ok() {} });
+        }
+    });
+    // bound but never answering
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(a));
+    let (node_b, node_c) = (PeerID("b".to_string()), PeerID("c".to_string()));
+    let manager = Arc::new(MultiLinkManager::new(
+        links,
+        vec![
+            (node_b.clone(), b_addr),
+            (node_c.clone(), silent.local_addr().unwrap()),
+        ],
+        vec![LinkType::WIFI],
+    ));
+    manager.bootstrap_peers().await;
+
+    let config = ProbeConfig::default()
+        .with_interval(Duration::from_secs(1), Duration::from_millis(300))
+        .with_dial_timeout(Duration::from_millis(300));
+    let local = PeerID("a".to_string());
+    for _ in 0..3 {
+        manager.probe_peers(&local, &config).await;
+    }
+
+    let store = manager.peer_store.lock().unwrap();
+    let reachable = store.get_peer(node_b).unwrap();
+    let metrics = &reachable
+        .endpoints_on(&LinkType::WIFI)
+        .next()
+        .unwrap()
+        .metrics;
+    assert!(reachable.rtt_ms.is_some());
+    assert_eq!(reachable.loss_percent, Some(0.0));
+    assert_eq!(metrics.probes_sent, 3);
+    assert!(metrics.srtt.unwrap() < config.timeout);
+
+    let lost = store.get_peer(node_c).unwrap();
+    assert_eq!(lost.rtt_ms, None);
+    assert!(lost.loss_percent.unwrap() > 20.0);
+    assert_eq!(
+        lost.endpoints_on(&LinkType::WIFI)
+            .next()
+            .unwrap()
+            .metrics
+            .probes_lost,
+        3
+    );
+}
+
+// takes a while to connect, like ble scanning for the peer, sends are acknowledged at once
+struct SlowDialLink(Duration);
+
+struct Acknowledged;
+
+#[async_trait::async_trait]
+impl Link for SlowDialLink {
+    async fn dial(
+        &self,
+        _address: &str,
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        tokio::time::sleep(self.0).await;
+        Ok(Box::new(Acknowledged))
+    }
+
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        std::future::pending().await
+    }
+
+    fn mtu(&self) -> usize {
+        20
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_millis(50)
+    }
+}
+
+#[async_trait::async_trait]
+impl LinkConnection for Acknowledged {
+    async fn send(&self, _data: &[u8]) -> Result<(), MeshError> {
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn slow_dials_are_not_counted_as_lost_probes() {
+    let slow = LinkType::new("slow");
+    let transport = Transport::new(slow.clone()).with_capabilities(TransportCapabilities {
+        reliable: true,
+        ..Default::default()
+    });
+    let manager = Arc::new(
+        MultiLinkManager::new(HashMap::new(), vec![], vec![]).with_transport(
+            transport,
+            Box::new(SlowDialLink(Duration::from_millis(300))),
+        ),
+    );
+    let id = PeerID("b".to_string());
+    manager
+        .peer_store
+        .lock()
+        .unwrap()
+        .update_store(PeerInfo::new(id.clone()).with_endpoint(slow.clone(), "b"));
+
+    // the dial alone takes longer than a probe may
+    let config = ProbeConfig::default()
+        .with_interval(Duration::from_secs(1), Duration::from_millis(100))
+        .with_dial_timeout(Duration::from_secs(1));
+    manager.probe_peers(&PeerID("a".to_string()), &config).await;
+
+    let store = manager.peer_store.lock().unwrap();
+    let metrics = &store.get_peer(id).unwrap().endpoints[0].metrics;
+    assert_eq!(metrics.probes_sent, 1);
+    assert_eq!(metrics.probes_lost, 0);
+    assert!(metrics.srtt.unwrap() < config.timeout);
+}
+
+#[test]
+fn endpoints_on_one_link_keep_their_own_metrics_and_expire_alone() {
+    let config = ProbeConfig::default();
+    let mut store = PeerStore::default();
+    let id = PeerID("a".to_string());
+    let seen = Instant::now();
+    let mut info = peer(&id, "127.0.0.1:1".parse().unwrap());
+    info.endpoints[0].last_seen = seen;
+    store.update_store(info);
+    store.update_store(peer(&id, "[::1]:1".parse().unwrap()));
+
+    let mut v6 = LinkMetrics::default();
+    v6.record_rtt(Duration::from_millis(5), &config);
+    store.update_metrics(&id, &LinkType::WIFI, "[::1]:1", v6);
+
+    let info = store.get_peer(id.clone()).unwrap();
+    assert_eq!(info.endpoints_on(&LinkType::WIFI).count(), 2);
+    assert_eq!(info.rtt_ms, Some(5));
+    let v4 = info.endpoint(&LinkType::WIFI, "127.0.0.1:1").unwrap();
+    assert_eq!(v4.metrics.srtt, None);
+
+    // the v4 address went quiet, the peer stays reachable over v6
+    store.reap(seen + store.liveness.dead_after);
+    let info = store.get_peer(id).unwrap();
+    assert!(info.endpoint(&LinkType::WIFI, "127.0.0.1:1").is_none());
+    assert!(info.endpoint(&LinkType::WIFI, "[::1]:1").is_some());
+}
@@ -0,0 +1,286 @@
+use clap::Parser;
+use mesh_core::{
+    node::{MeshNode, MeshNodeBuilder},
+    reload::ConfigReloader,
+    tcp::tcp_impl::TcpTlsLink,
+    types::{
+        args::Args,
+        config::Config,
+        identity::NodeIdentity,
+        peer::{LinkType, PeerID, PeerInfo},
+    },
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+    MeshError,
+};
+use quinn::rustls::pki_types::CertificateDer;
+use rcgen::{Certificate, Issuer, KeyPair};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+const CONFIG: &str = r#"
+[node]
+name = "a"
+
+[links]
+priority = ["wifi", "tcp"]
+
+[links.wifi]
+listen = "127.0.0.1:0"
+
+[links.tcp]
+enabled = true
+listen = "127.0.0.1:0"
+
+[discovery]
+enabled = false
+"#;
+
+struct Mesh {
+    cert: Certificate,
+    ca: CertificateDer<'static>,
+    issuer: Issuer<'static, KeyPair>,
+}
+
+impl Mesh {
+    fn new() -> Self {
+        let (cert, issuer) = generate_certificate_authority();
+        Self {
+            ca: cert.der().clone().into_owned(),
+            cert,
+            issuer,
+        }
+    }
+
+    fn tcp(&self, name: &str) -> TcpTlsLink {
+        TcpTlsLink::new(
+            "127.0.0.1:0",
+            std::slice::from_ref(&self.ca),
+            name,
+            &self.issuer,
+        )
+        .unwrap()
+    }
+
+    fn node(&self, name: &str) -> MeshNodeBuilder {
+        MeshNode::builder(
+            Arc::new(NodeIdentity::new(&self.issuer, name)),
+            self.ca.clone(),
+        )
+    }
+}
+
+// a directory of its own per test, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("mesh-reload-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn args(config: &str) -> Args {
+    Args::parse_from(["mesh-core", "--config", config])
+}
+
+// node a as CONFIG describes it
+async fn node_a(mesh: &Mesh) -> MeshNode {
+    let wifi = WifiQuicLink::new(
+        "127.0.0.1:0",
+        std::slice::from_ref(&mesh.ca),
+        "a",
+        &mesh.issuer,
+    )
+    .unwrap();
+    mesh.node("a")
+        .with_link(LinkType::WIFI, Box::new(wifi))
+        .with_link(LinkType::TCP, Box::new(mesh.tcp("a")))
+        .start()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn safe_changes_apply_to_the_running_node() {
+    let mesh = Mesh::new();
+    let node = node_a(&mesh).await;
+    let config = Config::parse(CONFIG).unwrap();
+    let mut reloader = ConfigReloader::new(args("unused.toml"), config.clone()).unwrap();
+
+    let mut changed = config.clone();
+    changed.links.priority = vec!["tcp".into(), "wifi".into()];
+    changed.discovery.interval_ms = 500;
+    changed.liveness.suspect_after_ms = 1000;
+    changed.liveness.dead_after_ms = 2000;
+    changed.peers =
+        Config::parse("[[peers]]\nname = \"b\"\nlink = \"tcp\"\naddress = \"127.0.0.1:9\"")
+            .unwrap()
+            .peers;
+
+    let report = reloader.apply(&node, changed.clone()).unwrap();
+    assert_eq!(
+        report.applied,
+        [
+            "peers",
+            "links.priority",
+            "discovery.interval_ms, discovery.jitter_ms",
+            "liveness"
+        ]
+    );
+    assert!(report.needs_restart.is_empty());
+    assert_eq!(reloader.running(), &changed);
+
+    assert_eq!(node.manager.priority(), [LinkType::TCP, LinkType::WIFI]);
+    assert!(node
+        .peers()
+        .iter()
+        .any(|peer| peer.id == PeerID("b".into())));
+    let liveness = node.peer_store.lock().unwrap().liveness;
+    assert_eq!(liveness.suspect_after, Duration::from_secs(1));
+    assert_eq!(liveness.dead_after, Duration::from_secs(2));
+
+    // the same file again is nothing new
+    assert!(reloader.apply(&node, changed).unwrap().is_empty());
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn restart_only_changes_are_reported_and_kept_out() {
+    let mesh = Mesh::new();
+    let node = node_a(&mesh).await;
+    let config = Config::parse(CONFIG).unwrap();
+    let mut reloader = ConfigReloader::new(args("unused.toml"), config.clone()).unwrap();
+
+    let mut changed = config.clone();
+    changed.node.name = "z".into();
+    changed.links.tcp.listen = "127.0.0.1:7000".into();
+    changed.discovery.port = 4100;
+    changed.links.priority = vec!["tcp".into()];
+
+    let report = reloader.apply(&node, changed.clone()).unwrap();
+    assert_eq!(report.applied, ["links.priority"]);
+    assert_eq!(report.needs_restart, ["node", "links.tcp", "discovery"]);
+    assert_eq!(reloader.running().node.name, "a");
+    assert_eq!(reloader.running().discovery.port, config.discovery.port);
+    // wifi was left out and keeps its place behind tcp
+    assert_eq!(node.manager.priority(), [LinkType::TCP, LinkType::WIFI]);
+
+    // still not running with them, so they are reported again
+    let report = reloader.apply(&node, changed).unwrap();
+    assert!(report.applied.is_empty());
+    assert_eq!(report.needs_restart.len(), 3);
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_invalid_config_changes_nothing() {
+    let mesh = Mesh::new();
+    let node = node_a(&mesh).await;
+    let config = Config::parse(CONFIG).unwrap();
+    let mut reloader = ConfigReloader::new(args("unused.toml"), config.clone()).unwrap();
+
+    let mut changed = config.clone();
+    changed.links.priority = vec!["tcp".into()];
+    changed.liveness.dead_after_ms = 0;
+    assert!(matches!(
+        reloader.apply(&node, changed),
+        Err(MeshError::Config(_))
+    ));
+
+    let mut missing = config.clone();
+    missing.links.priority = vec!["tcp".into()];
+    missing.certificates.trusted = vec!["/nonexistent/ca.pem".into()];
+    assert!(reloader.apply(&node, missing).is_err());
+
+    assert_eq!(reloader.running(), &config);
+    assert_eq!(node.manager.priority(), [LinkType::WIFI, LinkType::TCP]);
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn edits_to_the_file_are_picked_up() {
+    let mesh = Mesh::new();
+    let node = node_a(&mesh).await;
+    let dir = TempDir::new("file");
+    let path = dir.write("config.toml", CONFIG);
+    let args = args(path.to_str().unwrap());
+    let mut reloader =
+        ConfigReloader::new(args.clone(), Config::from_args(&args).unwrap()).unwrap();
+    assert!(!reloader.changed());
+
+    // coarse file systems only notice a write some time later
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    dir.write(
+        "config.toml",
+        &CONFIG.replace(r#"["wifi", "tcp"]"#, r#"["tcp", "wifi"]"#),
+    );
+    assert!(reloader.changed());
+    assert!(!reloader.changed());
+
+    let report = reloader.reload(&node).unwrap();
+    assert_eq!(report.applied, ["links.priority"]);
+    assert_eq!(node.manager.priority(), [LinkType::TCP, LinkType::WIFI]);
+
+    // a file that no longer parses is rejected as a whole
+    dir.write("config.toml", "[links]\npriority = \"tcp\"");
+    assert!(matches!(reloader.reload(&node), Err(MeshError::Config(_))));
+    assert_eq!(node.manager.priority(), [LinkType::TCP, LinkType::WIFI]);
+    node.shutdown().await;
+}
+
+#[tokio::test]
+async fn trusting_another_ca_lets_its_nodes_in() {
+    let (ours, theirs) = (Mesh::new(), Mesh::new());
+    let link_b = theirs.tcp("b");
+    let b_peer = PeerInfo::new(PeerID("b".into()))
+        .with_endpoint(LinkType::TCP, link_b.local_addr.to_string());
+    let b = theirs
+        .node("b")
+        .with_link(LinkType::TCP, Box::new(link_b))
+        .start()
+        .await
+        .unwrap();
+    let a = ours
+        .node("a")
+        .with_link(LinkType::TCP, Box::new(ours.tcp("a")))
+        .with_peer(b_peer)
+        .start()
+        .await
+        .unwrap();
+    let mut inbox = b.subscribe();
+    let b_id = PeerID("b".into());
+
+    assert!(a.send(&b_id, b"who are you").await.is_err());
+
+    let dir = TempDir::new("trust");
+    let ca_path = dir.write("theirs.pem", &theirs.cert.pem());
+    let config = Config::parse("[links.tcp]\nenabled = true").unwrap();
+    let mut reloader = ConfigReloader::new(args("unused.toml"), config.clone()).unwrap();
+    let mut changed = config;
+    changed.certificates.trusted = vec![ca_path];
+    let report = reloader.apply(&a, changed).unwrap();
+    assert_eq!(report.applied, ["certificates.trusted"]);
+
+    a.send(&b_id, b"hello").await.unwrap();
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.payload, b"hello");
+
+    a.shutdown().await;
+    b.shutdown().await;
+}
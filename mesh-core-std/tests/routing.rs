@@ -1,81 +1,373 @@
 use mesh_core::{
-    discovery::{broadcast, listener},
+    envelope::DEFAULT_TTL,
+    mesh::{routing_frame::Frame, MeshMessage},
     types::{
-        peer::{PeerID, PeerStore},
-        routing::RoutingLayer,
+        distance_vector::{RoutingTable, INFINITY_METRIC},
+        on_demand::OnDemandRouting,
+        peer::{LinkType, PeerID, PeerInfo, PeerStore},
+        routing::{Outgoing, RoutingLayer, RoutingStrategy, SingleLink},
     },
-    utils::{generate_certificate_authority, generate_node_certs},
-    wifi::WifiQuicLink,
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
 };
 use std::{
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
     time::Duration,
 };
+use tokio::sync::mpsc::Receiver;
 
-#[tokio::test]
-async fn routing() {
-    let _ = env_logger::builder().is_test(true).try_init();
+fn neighbour(id: &PeerID, addr: SocketAddr) -> PeerInfo {
+    PeerInfo::new(id.clone()).with_endpoint(LinkType::WIFI, addr.to_string())
+}
 
-    let mut nodes = vec![];
-    let (ca_cert, ca_issuer) = generate_certificate_authority();
-    let (_node1_cert, _node1_key) = generate_node_certs(&ca_issuer, "Node 1");
-    let (_node2_cert, _node2_key) = generate_node_certs(&ca_issuer, "Node 2");
+#[test]
+fn distance_vector_converges() {
+    let (a, b, c) = (
+        PeerID("a".to_string()),
+        PeerID("b".to_string()),
+        PeerID("c".to_string()),
+    );
+    let mut table_a = RoutingTable::new(a.clone());
+    let mut table_b = RoutingTable::new(b.clone());
+    let mut table_c = RoutingTable::new(c.clone());
+
+    // a - b - c, a and c can't hear each other
+    assert!(table_b.apply_advertisement(&table_a.advertisement()));
+    assert!(table_b.apply_advertisement(&table_c.advertisement()));
+    let from_b = table_b.advertisement();
+    assert!(table_a.apply_advertisement(&from_b));
+    assert!(table_c.apply_advertisement(&from_b));
+
+    assert_eq!(table_a.next_hop(&c), Some(b.clone()));
+    assert_eq!(table_a.get_route(&c).unwrap().metric, 2);
+    assert_eq!(table_c.next_hop(&a), Some(b.clone()));
 
-    for (i, _port) in [5000, 5001, 5002].iter().enumerate() {
-        let id = PeerID(format!("node{}", i + 1));
-        let addr = format!("127.0.0.1:4000");
-        let address: SocketAddr = addr.clone().parse().unwrap();
-        let peer_store = Arc::new(Mutex::new(PeerStore::default()));
+    // the same vector again changes nothing
+    assert!(!table_a.apply_advertisement(&from_b));
 
-        tokio::spawn(listener(peer_store.clone(), "0.0.0.0:4000".to_string()));
+    // b loses c, a learns about it through b's next vector
+    assert!(table_b.invalidate_next_hop(&c));
+    assert!(table_a.apply_advertisement(&table_b.advertisement()));
+    assert_eq!(table_a.next_hop(&c), None);
+    assert_eq!(table_a.get_route(&c).unwrap().metric, INFINITY_METRIC);
 
-        tokio::spawn(broadcast(id.clone(), address));
+    // c comes back with a fresher sequence number and the route heals
+    assert!(table_b.apply_advertisement(&table_c.advertisement()));
+    assert!(table_a.apply_advertisement(&table_b.advertisement()));
+    assert_eq!(table_a.next_hop(&c), Some(b));
+}
+
+#[test]
+fn on_demand_discovers_reverse_and_forward_paths() {
+    let (a, b, c) = (
+        PeerID("a".to_string()),
+        PeerID("b".to_string()),
+        PeerID("c".to_string()),
+    );
+    let mut node_a = OnDemandRouting::new(a.clone());
+    let mut node_b = OnDemandRouting::new(b.clone());
+    let mut node_c = OnDemandRouting::new(c.clone());
 
-        let link = match WifiQuicLink::new(
-            &addr,
+    fn frame(mut outgoing: Vec<Outgoing>) -> (Option<PeerID>, Frame) {
+        assert_eq!(outgoing.len(), 1);
+        match outgoing.remove(0) {
+            Outgoing::Broadcast(frame) => (None, frame),
+            Outgoing::Unicast(to, frame) => (Some(to), frame),
+        }
+    }
+
+    // a floods a request, b relays it, c answers
+    let (to, request) = frame(node_a.on_route_missing(&c));
+    assert_eq!(to, None);
+    let (to, relayed) = frame(node_b.on_control(&a, request.clone()));
+    assert_eq!(to, None);
+    assert!(
+        node_b.on_control(&a, request).is_empty(),
+        "requests are relayed once"
+    );
+    assert!(node_a.on_control(&b, relayed.clone()).is_empty());
+    let (to, reply) = frame(node_c.on_control(&b, relayed));
+    assert_eq!(to, Some(b.clone()));
+
+    // the reply travels back along the reverse path
+    let (to, reply) = frame(node_b.on_control(&c, reply));
+    assert_eq!(to, Some(a.clone()));
+    assert!(node_a.on_control(&b, reply).is_empty());
+
+    assert_eq!(node_a.next_hop(&c), Some(b.clone()));
+    assert_eq!(node_a.get_route(&c).unwrap().hop_count, 2);
+    assert_eq!(node_c.next_hop(&a), Some(b.clone()));
+
+    // b loses c and tells its neighbours, a drops the route
+    let (to, error) = frame(node_b.on_link_broken(&c));
+    assert_eq!(to, None);
+    let (to, _) = frame(node_a.on_control(&b, error));
+    assert_eq!(to, None);
+    assert_eq!(node_a.next_hop(&c), None);
+}
+
+#[test]
+fn broken_routes_keep_their_sequence_until_deleted() {
+    let (a, b, c) = (
+        PeerID("a".to_string()),
+        PeerID("b".to_string()),
+        PeerID("c".to_string()),
+    );
+    let lifetime = Duration::from_millis(50);
+    let mut node_a = OnDemandRouting::with_lifetime(a.clone(), lifetime);
+    let mut node_c = OnDemandRouting::with_lifetime(c.clone(), lifetime);
+
+    // a's request reaches c through b, the reply comes back the same way
+    let request = match node_a.on_route_missing(&c).remove(0) {
+        Outgoing::Broadcast(frame) => frame,
+        Outgoing::Unicast(_, frame) => frame,
+    };
+    let reply = match node_c.on_control(&b, request).remove(0) {
+        Outgoing::Broadcast(frame) => frame,
+        Outgoing::Unicast(_, frame) => frame,
+    };
+    assert!(node_a.on_control(&b, reply).is_empty());
+    let sequence = node_a.get_route(&c).unwrap().sequence;
+
+    // the broken route stays around so the next request asks for something fresher
+    node_a.on_link_broken(&b);
+    node_a.on_tick(lifetime);
+    let broken = node_a.get_route(&c).unwrap();
+    assert!(!broken.valid);
+    assert_eq!(broken.sequence, sequence + 1);
+    match node_a.on_route_missing(&c).remove(0) {
+        Outgoing::Broadcast(Frame::Request(request)) => {
+            assert_eq!(request.destination_sequence, sequence + 1)
+        }
+        _ => panic!("expected a route request"),
+    }
+
+    std::thread::sleep(lifetime * 2);
+    node_a.on_tick(lifetime);
+    assert!(node_a.get_route(&c).is_none());
+}
+
+// starts one routing layer per node on a line topology, every node only knows its neighbours
+async fn line_topology(
+    length: usize,
+    strategy: impl Fn(PeerID) -> Box<dyn RoutingStrategy>,
+) -> (
+    Vec<PeerID>,
+    Vec<(
+        RoutingLayer<SingleLink<WifiQuicLink>>,
+        Receiver<MeshMessage>,
+    )>,
+) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let ids: Vec<PeerID> = (1..=length).map(|i| PeerID(format!("node{}", i))).collect();
+
+    let mut links = vec![];
+    for id in &ids {
+        let link = WifiQuicLink::new(
+            "127.0.0.1:0",
             &[ca_cert.der().clone().into_owned()],
             &id.0,
             &ca_issuer,
-        ) {
-            Ok(link) => {
-                log::info!("WifiQuicLink formed for {}", addr);
-                link
-            }
-            Err(e) => {
-                log::error!("Error occurred while forming WifiQuicLink: {e}");
-                return;
+        )
+        .unwrap();
+        let addr = link.endpoint.local_addr().unwrap();
+        links.push((link, addr));
+    }
+
+    let mut nodes = vec![];
+    for (i, id) in ids.iter().enumerate() {
+        let mut store = PeerStore::default();
+        for j in [i.wrapping_sub(1), i + 1] {
+            if let Some((_, addr)) = links.get(j) {
+                store.update_store(neighbour(&ids[j], *addr));
             }
-        };
+        }
+        let routing = RoutingLayer::with_strategy(
+            id.clone(),
+            links[i].0.clone(),
+            Arc::new(Mutex::new(store)),
+            strategy(id.clone()),
+        );
+        let inbox = routing.start(Duration::from_millis(200));
+        nodes.push((routing, inbox));
+    }
 
-        let routing = RoutingLayer::new(link.clone(), peer_store.clone());
+    (ids, nodes)
+}
+
+#[tokio::test]
+async fn routing() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let (ids, mut nodes) = line_topology(3, |id| Box::new(RoutingTable::new(id))).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
 
-        nodes.push((id, peer_store.clone(), routing));
+    let (node1, _) = &nodes[0];
+    node1
+        .send(ids[2].clone(), b"Hello from node1")
+        .await
+        .unwrap();
+
+    let (_, inbox) = &mut nodes[2];
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.source_id(), ids[0]);
+    assert_eq!(message.payload, b"Hello from node1");
+    // one hop used up on the way through node2
+    assert_eq!(message.ttl, DEFAULT_TTL - 1);
+
+    assert!(nodes[0]
+        .0
+        .send(PeerID("unknown".to_string()), b"nowhere")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn on_demand_routing() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let (ids, mut nodes) = line_topology(3, |id| Box::new(OnDemandRouting::new(id))).await;
+
+    // no warm up, the first send triggers route discovery
+    let (node3, _) = &nodes[2];
+    node3
+        .send(ids[0].clone(), b"Hello from node3")
+        .await
+        .unwrap();
+
+    let (_, inbox) = &mut nodes[0];
+    let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.source_id(), ids[2]);
+    assert_eq!(message.payload, b"Hello from node3");
+}
+
+// sends everything through one neighbour and never looks for routes itself
+struct Via(PeerID);
+
+impl RoutingStrategy for Via {
+    fn next_hop(&mut self, _destination: &PeerID) -> Option<PeerID> {
+        Some(self.0.clone())
     }
 
-    let _ = tokio::time::sleep(Duration::from_secs(20)).await;
-
-    for (id, _, routing) in &nodes {
-        for (target, _, _) in &nodes {
-            if id != target {
-                let result = routing
-                    .send(
-                        target.clone(),
-                        &format!("Hello from {} to {:?}", id.0, target.0).as_bytes(),
-                    )
-                    .await;
-                assert!(
-                    result.is_ok(),
-                    "Sending packet from {} to {} failed",
-                    id.0,
-                    target.0
-                );
-            }
-        }
+    fn on_control(&mut self, _from: &PeerID, _frame: Frame) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_route_missing(&mut self, _destination: &PeerID) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_link_broken(&mut self, _neighbour: &PeerID) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_tick(&mut self, _interval: Duration) -> Vec<Outgoing> {
+        vec![]
+    }
+}
+
+#[tokio::test]
+async fn relays_discover_routes_while_receiving() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // node2 gets the message without a route to node4, the reply that ends its discovery
+    // comes in on the same link as the message it is relaying
+    let (ids, mut nodes) = line_topology(4, |id| match id.0.as_str() {
+        "node1" => Box::new(Via(PeerID("node2".to_string()))),
+        _ => Box::new(OnDemandRouting::new(id)),
+    })
+    .await;
+
+    let (node1, _) = &nodes[0];
+    node1
+        .send(ids[3].clone(), b"Hello from node1")
+        .await
+        .unwrap();
+
+    let (_, inbox) = &mut nodes[3];
+    let message = tokio::time::timeout(Duration::from_secs(2), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.source_id(), ids[0]);
+    assert_eq!(message.payload, b"Hello from node1");
+}
+
+// counts the ticks it gets, knows no routes
+struct Ticks(Arc<AtomicUsize>);
+
+impl RoutingStrategy for Ticks {
+    fn next_hop(&mut self, _destination: &PeerID) -> Option<PeerID> {
+        None
+    }
+
+    fn on_control(&mut self, _from: &PeerID, _frame: Frame) -> Vec<Outgoing> {
+        vec![]
     }
 
-    for (id, peer_store, _) in &nodes {
-        let s = peer_store.lock().unwrap();
-        println!("Node {} knows {} peers", id.0, s.get_all_peers().len());
+    fn on_route_missing(&mut self, _destination: &PeerID) -> Vec<Outgoing> {
+        vec![]
     }
+
+    fn on_link_broken(&mut self, _neighbour: &PeerID) -> Vec<Outgoing> {
+        vec![]
+    }
+
+    fn on_tick(&mut self, _interval: Duration) -> Vec<Outgoing> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        vec![]
+    }
+}
+
+#[tokio::test]
+async fn dropping_the_inbox_stops_the_layer() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let link = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let routing = RoutingLayer::with_strategy(
+        PeerID("a".to_string()),
+        link,
+        Arc::new(Mutex::new(PeerStore::default())),
+        Box::new(Ticks(ticks.clone())),
+    );
+
+    let inbox = routing.start(Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(ticks.load(Ordering::SeqCst) > 0);
+
+    drop(inbox);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let stopped_at = ticks.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ticks.load(Ordering::SeqCst), stopped_at);
+}
+
+#[tokio::test]
+async fn dropping_the_inbox_frees_the_peer_store() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let link = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &ca_issuer).unwrap();
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    let freed: Weak<Mutex<PeerStore>> = Arc::downgrade(&store);
+    let routing = RoutingLayer::new(PeerID("a".to_string()), link, store);
+
+    let inbox = routing.start(Duration::from_millis(10));
+    drop(routing);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(freed.upgrade().is_some(), "running layers keep the store");
+
+    drop(inbox);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(freed.upgrade().is_none());
 }
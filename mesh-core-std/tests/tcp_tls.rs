@@ -0,0 +1,192 @@
+use mesh_core::{
+    link::{link_trait::Link, multilink::MultiLinkManager},
+    mesh::MeshMessage,
+    tcp::tcp_impl::TcpTlsLink,
+    types::peer::{LinkType, PeerID, PeerInfo},
+    utils::generate_certificate_authority,
+    wifi::wifi_impl::WifiQuicLink,
+};
+use quinn::rustls::pki_types::CertificateDer;
+use rcgen::{Issuer, KeyPair};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+fn mesh() -> (Vec<CertificateDer<'static>>, Issuer<'static, KeyPair>) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    (vec![ca_cert.der().clone().into_owned()], ca_issuer)
+}
+
+#[tokio::test]
+async fn frames_go_both_ways_and_sends_wait_for_the_ack() {
+    let (trusted, issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer).unwrap();
+    let b_addr = b.local_addr.to_string();
+
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        let first = connection.receive().await.unwrap();
+        let second = connection.receive().await.unwrap();
+        connection.send(b"pong").await.unwrap();
+        let third = connection.receive().await.unwrap();
+        (first, second, third)
+    });
+
+    let connection = a.dial(&b_addr).await.unwrap();
+    // bigger than a segment, framing keeps it whole
+    let bulk = vec![7u8; 100_000];
+    connection.send(b"ping").await.unwrap();
+    connection.send(&bulk).await.unwrap();
+    // the dialed connection also comes out of accept so replies get read
+    let dialed = a.accept().await.unwrap();
+    assert_eq!(dialed.receive().await.unwrap(), b"pong");
+
+    // a second dial reuses the connection
+    a.dial(&b_addr).await.unwrap().send(b"again").await.unwrap();
+
+    let (first, second, third) = server.await.unwrap();
+    assert_eq!(first, b"ping");
+    assert_eq!(second, bulk);
+    assert_eq!(third, b"again");
+}
+
+#[tokio::test]
+async fn peers_outside_the_mesh_are_refused() {
+    let (trusted, issuer) = mesh();
+    let (_, other_issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let stranger = TcpTlsLink::new("127.0.0.1:0", &trusted, "x", &other_issuer).unwrap();
+    let stranger_addr = stranger.local_addr.to_string();
+    tokio::spawn(async move { while stranger.accept().await.is_ok() {} });
+
+    assert!(a.dial(&stranger_addr).await.is_err());
+}
+
+#[tokio::test]
+async fn stalled_handshakes_dont_hold_up_other_peers() {
+    let (trusted, issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer).unwrap();
+    let b_addr = b.local_addr.to_string();
+    let server = tokio::spawn(async move { b.accept().await.map(|_| ()) });
+
+    // connects and never says hello, the handshake only gives up after the keepalive
+    let _stalled = tokio::net::TcpStream::connect(&b_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let dialed = tokio::time::timeout(Duration::from_secs(2), a.dial(&b_addr)).await;
+    assert!(dialed.unwrap().is_ok());
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn silent_peers_are_dropped_after_missed_keepalives() {
+    let (trusted, issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer)
+        .unwrap()
+        .with_keepalive(Duration::from_millis(100));
+    // never pings within the test
+    let b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer)
+        .unwrap()
+        .with_keepalive(Duration::from_secs(3600));
+    let b_addr = b.local_addr.to_string();
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        connection.receive().await
+    });
+
+    let connection = a.dial(&b_addr).await.unwrap();
+    let dialed = a.accept().await.unwrap();
+    let ended = tokio::time::timeout(Duration::from_secs(2), dialed.receive()).await;
+    assert!(matches!(ended, Ok(Err(_))));
+    assert!(connection.send(b"late").await.is_err());
+
+    // and the peer sees the connection end once we give up
+    let seen = tokio::time::timeout(Duration::from_secs(2), server).await;
+    assert!(matches!(seen, Ok(Ok(Err(_)))));
+}
+
+#[tokio::test]
+async fn forgetting_a_peer_closes_its_connection() {
+    let (trusted, issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer).unwrap();
+    let b_addr = b.local_addr.to_string();
+    let server = tokio::spawn(async move {
+        let connection = b.accept().await.unwrap();
+        connection.receive().await
+    });
+
+    let connection = a.dial(&b_addr).await.unwrap();
+    a.forget(&b_addr);
+    let seen = tokio::time::timeout(Duration::from_secs(2), server).await;
+    assert!(matches!(seen, Ok(Ok(Err(_)))));
+    assert!(connection.send(b"gone").await.is_err());
+}
+
+#[tokio::test]
+async fn sends_are_acknowledged_while_nobody_receives() {
+    let (trusted, issuer) = mesh();
+    let a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer).unwrap();
+    let b_addr = b.local_addr.to_string();
+
+    let (to_b, to_a) = tokio::join!(a.dial(&b_addr), b.accept());
+    let (to_b, to_a) = (to_b.unwrap(), to_a.unwrap());
+    // b never receives, a keeps sending until b's queue is full and a's send blocks
+    tokio::spawn(async move {
+        for i in 0..200u32 {
+            if to_b.send(&i.to_be_bytes()).await.is_err() {
+                return;
+            }
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // a's ack for this one arrives behind frames b has no room for yet
+    tokio::time::timeout(Duration::from_secs(2), to_a.send(b"still here"))
+        .await
+        .expect("send waited for receive to be drained")
+        .unwrap();
+    let dialed = a.accept().await.unwrap();
+    assert_eq!(dialed.receive().await.unwrap(), b"still here");
+
+    // a's sends pick up again once b drains its queue, in order
+    for i in 0..200u32 {
+        assert_eq!(to_a.receive().await.unwrap(), i.to_be_bytes());
+    }
+}
+
+#[tokio::test]
+async fn manager_falls_back_to_tcp_when_quic_fails() {
+    let (trusted, issuer) = mesh();
+    let wifi_a = WifiQuicLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let tcp_a = TcpTlsLink::new("127.0.0.1:0", &trusted, "a", &issuer).unwrap();
+    let tcp_b = TcpTlsLink::new("127.0.0.1:0", &trusted, "b", &issuer).unwrap();
+    let b_addr = tcp_b.local_addr;
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::TCP, Box::new(tcp_b));
+    let b = Arc::new(MultiLinkManager::new(links, vec![], vec![]));
+    let mut inbox = b.start_receiving();
+
+    let mut links: HashMap<LinkType, Box<dyn Link + Send + Sync>> = HashMap::new();
+    links.insert(LinkType::WIFI, Box::new(wifi_a));
+    links.insert(LinkType::TCP, Box::new(tcp_a));
+    let a = MultiLinkManager::new(links, vec![], vec![LinkType::WIFI, LinkType::TCP]);
+    let id = PeerID("b".to_string());
+    // an ipv4 quic endpoint fails to dial ipv6 right away, standing in for blocked udp
+    a.peer_store.lock().unwrap().update_store(
+        PeerInfo::new(id.clone())
+            .with_endpoint(LinkType::WIFI, "[::1]:9")
+            .with_endpoint(LinkType::TCP, b_addr.to_string()),
+    );
+    assert_eq!(a.pick_best_link(&id).await.unwrap().link, LinkType::WIFI);
+
+    let message = MeshMessage::data(&PeerID("a".to_string()), &id, b"over tcp".to_vec());
+    a.send(&id, &message).await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, message);
+}
@@ -0,0 +1,229 @@
+use mesh_core::{
+    link::{
+        discovery::{encode_announcement, handle_announcement, AnnouncementVerifier, LINK_WIFI},
+        link_trait::{Link, LinkConnection},
+        multilink::MultiLinkManager,
+        registry::{Transport, TransportCapabilities, TransportRegistry},
+    },
+    mesh::{LinkAddress, MeshMessage},
+    types::{
+        identity::NodeIdentity,
+        peer::{LinkType, PeerID, PeerInfo, PeerStore},
+        probe::ProbeConfig,
+    },
+    utils::generate_certificate_authority,
+    MeshError,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+fn lora() -> LinkType {
+    LinkType::new("lora")
+}
+
+// lora radios are addressed by a hex node number
+fn lora_transport(reliable: bool) -> Transport {
+    Transport::new(lora())
+        .with_capabilities(TransportCapabilities {
+            reliable,
+            ..Default::default()
+        })
+        .with_parser(|address, _| {
+            let number = u32::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map_err(|e| MeshError::InvalidAddress(e.to_string()))?;
+            Ok(format!("0x{:08x}", number))
+        })
+}
+
+// remembers where it was asked to send, nothing arrives anywhere
+#[derive(Clone, Default)]
+struct RecordingLink {
+    sent: Arc<Mutex<Vec<String>>>,
+}
+
+struct RecordingConnection {
+    address: String,
+    sent: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait::async_trait]
+impl Link for RecordingLink {
+    async fn dial(
+        &self,
+        address: &str,
+    ) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        Ok(Box::new(RecordingConnection {
+            address: address.to_string(),
+            sent: self.sent.clone(),
+        }))
+    }
+
+    async fn accept(&self) -> Result<Box<dyn LinkConnection + Send + Sync>, MeshError> {
+        std::future::pending().await
+    }
+
+    fn mtu(&self) -> usize {
+        200
+    }
+
+    fn latency(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+}
+
+#[async_trait::async_trait]
+impl LinkConnection for RecordingConnection {
+    async fn send(&self, _data: &[u8]) -> Result<(), MeshError> {
+        self.sent.lock().unwrap().push(self.address.clone());
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Vec<u8>, MeshError> {
+        std::future::pending().await
+    }
+
+    fn mtu(&self) -> usize {
+        200
+    }
+}
+
+#[test]
+fn registry_parses_addresses_per_transport() {
+    let registry = TransportRegistry::default().with_transport(lora_transport(true));
+    assert_eq!(
+        registry
+            .parse_address(
+                &LinkType::WIFI,
+                "0.0.0.0:4433",
+                "10.0.0.7:4000".parse().ok()
+            )
+            .unwrap(),
+        "10.0.0.7:4433"
+    );
+    assert!(registry
+        .parse_address(&LinkType::WIFI, "not an address", None)
+        .is_err());
+    assert_eq!(
+        registry.parse_address(&lora(), "0x1F", None).unwrap(),
+        "0x0000001f"
+    );
+    assert!(registry.parse_address(&lora(), "zz", None).is_err());
+
+    let unknown = LinkType::new("carrier-pigeon");
+    assert!(registry.parse_address(&unknown, "coop 3", None).is_err());
+    assert!(TransportRegistry::empty()
+        .parse_address(&LinkType::BLE, "AA:BB:CC:DD:EE:FF", None)
+        .is_err());
+    assert_eq!(
+        registry.capabilities(&LinkType::WIFI).map(|c| c.ip),
+        Some(true)
+    );
+}
+
+#[test]
+fn store_keeps_only_registered_endpoints_in_canonical_form() {
+    let mut store = PeerStore::default()
+        .with_registry(TransportRegistry::default().with_transport(lora_transport(true)));
+    let id = PeerID("a".to_string());
+    store.update_store(
+        PeerInfo::new(id.clone())
+            .with_endpoint(LinkType::WIFI, "127.0.0.1:1")
+            .with_endpoint(lora(), "1f")
+            .with_endpoint(lora(), "not hex")
+            .with_endpoint(LinkType::new("carrier-pigeon"), "coop 3"),
+    );
+
+    let peer = store.get_peer(id).unwrap();
+    assert_eq!(peer.endpoints.len(), 2);
+    assert!(peer.endpoint(&lora(), "0x0000001f").is_some());
+}
+
+#[test]
+fn discovery_records_addresses_on_registered_transports() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let ca = ca_cert.der().clone().into_owned();
+    let identity = NodeIdentity::new(&ca_issuer, "node1");
+    let addresses = vec![
+        LinkAddress::new(LINK_WIFI, "0.0.0.0:12345"),
+        LinkAddress::new("lora", "0xbeef"),
+    ];
+    let src = "10.0.0.7:4000".parse().unwrap();
+
+    // a node that doesn't know lora only sees the wi-fi address
+    let store = Mutex::new(PeerStore::default());
+    let beacon = encode_announcement(&identity, &addresses, &[], 1).unwrap();
+    let mut verifier = AnnouncementVerifier::new(ca.clone());
+    handle_announcement(&store, &mut verifier, &beacon, src).unwrap();
+    let peer = store
+        .lock()
+        .unwrap()
+        .get_peer(identity.id.clone())
+        .cloned()
+        .unwrap();
+    assert_eq!(peer.endpoints.len(), 1);
+    assert!(peer.endpoint(&LinkType::WIFI, "10.0.0.7:12345").is_some());
+
+    let store = Mutex::new(
+        PeerStore::default()
+            .with_registry(TransportRegistry::default().with_transport(lora_transport(true))),
+    );
+    let mut verifier = AnnouncementVerifier::new(ca);
+    handle_announcement(&store, &mut verifier, &beacon, src).unwrap();
+    let peer = store
+        .lock()
+        .unwrap()
+        .get_peer(identity.id.clone())
+        .cloned()
+        .unwrap();
+    assert!(peer.endpoint(&lora(), "0x0000beef").is_some());
+}
+
+#[tokio::test]
+async fn manager_sends_over_added_transports() {
+    let link = RecordingLink::default();
+    let store = Arc::new(Mutex::new(PeerStore::default()));
+    // the store comes second, the transport has to carry over into it
+    let manager = Arc::new(
+        MultiLinkManager::new(HashMap::new(), vec![], vec![])
+            .with_transport(lora_transport(true), Box::new(link.clone()))
+            .with_peer_store(store.clone()),
+    );
+    assert!(store.lock().unwrap().registry.contains(&lora()));
+    assert_eq!(manager.priority(), vec![lora()]);
+
+    let id = PeerID("b".to_string());
+    store
+        .lock()
+        .unwrap()
+        .update_store(PeerInfo::new(id.clone()).with_endpoint(lora(), "2a"));
+    let message = MeshMessage::data(&PeerID("a".to_string()), &id, b"hi".to_vec());
+    manager.send(&id, &message).await.unwrap();
+    assert_eq!(*link.sent.lock().unwrap(), vec!["0x0000002a".to_string()]);
+    assert_eq!(manager.pick_best_link(&id).await.unwrap().link, lora());
+}
+
+#[tokio::test]
+async fn only_reliable_transports_are_probed() {
+    let link = RecordingLink::default();
+    let manager = Arc::new(
+        MultiLinkManager::new(HashMap::new(), vec![], vec![])
+            .with_transport(lora_transport(false), Box::new(link.clone())),
+    );
+    let id = PeerID("b".to_string());
+    manager
+        .peer_store
+        .lock()
+        .unwrap()
+        .update_store(PeerInfo::new(id.clone()).with_endpoint(lora(), "2a"));
+
+    manager
+        .probe_peers(&PeerID("a".to_string()), &ProbeConfig::default())
+        .await;
+    assert!(link.sent.lock().unwrap().is_empty());
+    let store = manager.peer_store.lock().unwrap();
+    let peer = store.get_peer(id).unwrap();
+    assert_eq!(peer.endpoints[0].metrics.probes_sent, 0);
+}
@@ -0,0 +1,207 @@
+use mesh_core::{
+    link::link_trait::Link, utils::generate_certificate_authority, wifi::wifi_impl::WifiQuicLink,
+};
+use std::{net::SocketAddr, time::Duration};
+
+fn links(idle_timeout: Duration) -> (WifiQuicLink, WifiQuicLink) {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::with_idle_timeout("127.0.0.1:0", &trusted, "a", &ca_issuer, idle_timeout)
+        .unwrap();
+    let b = WifiQuicLink::with_idle_timeout("127.0.0.1:0", &trusted, "b", &ca_issuer, idle_timeout)
+        .unwrap();
+    (a, b)
+}
+
+// reads every connection b gets and forwards the payloads
+fn serve(link: WifiQuicLink) -> tokio::sync::mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(async move {
+        while let Ok(connection) = link.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Ok(data) = connection.receive().await {
+                    let _ = tx.send(data).await;
+                }
+            });
+        }
+    });
+    rx
+}
+
+#[tokio::test]
+async fn dials_reuse_pooled_connection() {
+    let (a, b) = links(Duration::from_secs(60));
+    let b_addr = b.endpoint.local_addr().unwrap();
+    let mut inbox = serve(b.clone());
+
+    let mut dials = vec![];
+    for i in 0..5u8 {
+        let a = a.clone();
+        dials.push(tokio::spawn(async move {
+            let connection = a.dial(&b_addr.to_string()).await.unwrap();
+            connection.send(&[i]).await.unwrap();
+        }));
+    }
+    for dial in dials {
+        dial.await.unwrap();
+    }
+
+    // a single handshake served every dial
+    assert_eq!(a.pool.len(), 1);
+    assert_eq!(b.pool.len(), 1);
+
+    let mut received = vec![];
+    for _ in 0..5 {
+        let data = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.push(data[0]);
+    }
+    received.sort();
+    assert_eq!(received, vec![0, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn idle_connections_are_evicted_and_redialed() {
+    let (a, b) = links(Duration::from_millis(200));
+    let b_addr = b.endpoint.local_addr().unwrap();
+    let _inbox = serve(b);
+
+    a.dial(&b_addr.to_string()).await.unwrap();
+    // held on to, stable ids of connections already freed get reused
+    let first = a.pool.get(&b_addr).unwrap();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(a.pool.is_empty());
+    assert!(first.is_closed());
+
+    a.dial(&b_addr.to_string()).await.unwrap();
+    let second = a.pool.get(&b_addr).unwrap().connection.stable_id();
+    assert_ne!(first.connection.stable_id(), second);
+}
+
+#[tokio::test]
+async fn closed_connections_are_redialed() {
+    let (a, b) = links(Duration::from_secs(60));
+    let b_addr = b.endpoint.local_addr().unwrap();
+    let _inbox = serve(b.clone());
+
+    a.dial(&b_addr.to_string()).await.unwrap();
+    let first = a.pool.get(&b_addr).unwrap();
+
+    // the peer drops the connection, the next dial must not hand out the dead one
+    let a_addr = a.endpoint.local_addr().unwrap();
+    let inbound = loop {
+        match b.pool.get(&a_addr) {
+            Some(connection) => break connection,
+            None => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    inbound.close("restart");
+    first.connection.closed().await;
+
+    let connection = a.dial(&b_addr.to_string()).await.unwrap();
+    connection.send(b"again").await.unwrap();
+    assert_ne!(
+        a.pool.get(&b_addr).unwrap().connection.stable_id(),
+        first.connection.stable_id()
+    );
+}
+
+#[tokio::test]
+async fn simultaneous_dials_converge() {
+    let (a, b) = links(Duration::from_secs(60));
+    let a_addr = a.endpoint.local_addr().unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap();
+    let mut a_inbox = serve(a.clone());
+    let mut b_inbox = serve(b.clone());
+
+    let (a_addr, b_addr) = (a_addr.to_string(), b_addr.to_string());
+    let (to_b, to_a) = tokio::join!(a.dial(&b_addr), b.dial(&a_addr));
+    let (to_b, to_a) = (to_b.unwrap(), to_a.unwrap());
+    to_b.send(b"to b").await.unwrap();
+    to_a.send(b"to a").await.unwrap();
+
+    assert_eq!(a.pool.len(), 1);
+    assert_eq!(b.pool.len(), 1);
+
+    let timeout = Duration::from_secs(5);
+    let data = tokio::time::timeout(timeout, b_inbox.recv()).await.unwrap();
+    assert_eq!(data, Some(b"to b".to_vec()));
+    let data = tokio::time::timeout(timeout, a_inbox.recv()).await.unwrap();
+    assert_eq!(data, Some(b"to a".to_vec()));
+}
+
+#[tokio::test]
+async fn dual_stack_links_pool_ipv4_peers() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("[::]:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("127.0.0.1:0", &trusted, "b", &ca_issuer).unwrap();
+    let b_addr = b.endpoint.local_addr().unwrap().to_string();
+    let mut inbox = serve(b.clone());
+
+    // the peer shows up as ::ffff:127.0.0.1 on a's socket, still one pooled connection
+    for _ in 0..3 {
+        a.dial(&b_addr).await.unwrap().send(b"v4").await.unwrap();
+        assert_eq!(inbox.recv().await.unwrap(), b"v4");
+    }
+    assert_eq!(a.pool.len(), 1);
+
+    // and an ipv4 only link refuses ipv6 peers outright
+    let error = b.dial("[::1]:4433").await.err().unwrap();
+    assert!(error.to_string().contains("ipv4"));
+    assert!(a.dial("[fe80::1]:4433").await.is_err());
+}
+
+#[tokio::test]
+async fn simultaneous_dials_converge_on_wildcard_links() {
+    let (ca_cert, ca_issuer) = generate_certificate_authority();
+    let trusted = [ca_cert.der().clone().into_owned()];
+    let a = WifiQuicLink::new("0.0.0.0:0", &trusted, "a", &ca_issuer).unwrap();
+    let b = WifiQuicLink::new("0.0.0.0:0", &trusted, "b", &ca_issuer).unwrap();
+    // as the shipped config binds, the peer is dialed at an address of its host
+    let local =
+        |link: &WifiQuicLink| SocketAddr::from(([127, 0, 0, 1], link.pool.local_addr.port()));
+    let (a_addr, b_addr) = (local(&a), local(&b));
+    let mut a_inbox = serve(a.clone());
+    let mut b_inbox = serve(b.clone());
+
+    let (a_dest, b_dest) = (a_addr.to_string(), b_addr.to_string());
+    let (to_b, to_a) = tokio::join!(a.dial(&b_dest), b.dial(&a_dest));
+    to_b.unwrap().send(b"to b").await.unwrap();
+    to_a.unwrap().send(b"to a").await.unwrap();
+    let timeout = Duration::from_secs(5);
+    tokio::time::timeout(timeout, b_inbox.recv()).await.unwrap();
+    tokio::time::timeout(timeout, a_inbox.recv()).await.unwrap();
+
+    // the same connection on both ends, dialed by one and accepted by the other
+    let (to_b, to_a) = (a.pool.get(&b_addr).unwrap(), b.pool.get(&a_addr).unwrap());
+    assert_ne!(to_b.connection.side(), to_a.connection.side());
+}
+
+#[tokio::test]
+async fn dialed_connections_wait_in_the_pool_for_accept() {
+    let (a, b) = links(Duration::from_millis(200));
+    let b_addr = b.endpoint.local_addr().unwrap();
+    let _inbox = serve(b);
+
+    // handed out once, later dials reuse it without queueing it again
+    a.dial(&b_addr.to_string()).await.unwrap();
+    a.dial(&b_addr.to_string()).await.unwrap();
+    let accepted = tokio::time::timeout(Duration::from_secs(1), a.accept()).await;
+    assert!(accepted.is_ok());
+    let again = tokio::time::timeout(Duration::from_millis(100), a.accept()).await;
+    assert!(again.is_err());
+
+    // a link that only dials holds nothing past the pool
+    a.dial(&b_addr.to_string()).await.ok();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(a.pool.is_empty());
+    a.dial(&b_addr.to_string()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(a.pool.is_empty());
+    let evicted = tokio::time::timeout(Duration::from_millis(100), a.accept()).await;
+    assert!(evicted.is_err());
+}